@@ -0,0 +1,130 @@
+// Times KbCollisionManager queries as the number of shapes grows.  Run with cargo bench
+use std::time::Instant;
+
+use kb_engine3::{kb_collision::*, kb_utils::*};
+
+const SHAPE_COUNTS: [usize; 4] = [100, 1000, 5000, 20000];
+const NUM_QUERIES: usize = 10000;
+const BENCH_SEED: u64 = 0x6B62_636F_6C6C;
+
+// Monsters and pickups spread over a level, plus a few walls spanning it
+fn build_level(num_shapes: usize) -> KbCollisionManager {
+    let mut collision_manager = KbCollisionManager::new();
+    let level_size = (num_shapes as f32).sqrt() * 4.0;
+    for i in 0..num_shapes {
+        let position = kb_random_vec3(
+            CgVec3::new(-level_size, 0.0, -level_size),
+            CgVec3::new(level_size, 4.0, level_size),
+        );
+        let shape = if i % 2 == 0 {
+            KbCollisionShape::Sphere(KbCollisionSphere {
+                position,
+                radius: 1.0,
+                block: false,
+                ..Default::default()
+            })
+        } else {
+            KbCollisionShape::AABB(KbCollisionAABB {
+                position,
+                extents: CgVec3::new(1.0, 2.0, 1.0),
+                block: true,
+                ..Default::default()
+            })
+        };
+        collision_manager.add_collision(&shape);
+    }
+
+    for wall in 0..4 {
+        let side = if wall % 2 == 0 { -1.0 } else { 1.0 };
+        let (position, extents) = if wall < 2 {
+            (
+                CgVec3::new(side * level_size, 0.0, 0.0),
+                CgVec3::new(1.0, 10.0, level_size),
+            )
+        } else {
+            (
+                CgVec3::new(0.0, 0.0, side * level_size),
+                CgVec3::new(level_size, 10.0, 1.0),
+            )
+        };
+        collision_manager.add_collision(&KbCollisionShape::AABB(KbCollisionAABB {
+            position,
+            extents,
+            block: true,
+            ..Default::default()
+        }));
+    }
+    collision_manager
+}
+
+fn time_queries(name: &str, num_shapes: usize, mut query: impl FnMut()) {
+    let start = Instant::now();
+    for _ in 0..NUM_QUERIES {
+        query();
+    }
+    let micros = start.elapsed().as_secs_f64() * 1_000_000.0 / NUM_QUERIES as f64;
+    println!(
+        "{:<16} {:>6} shapes: {:>8.3} us/query",
+        name, num_shapes, micros
+    );
+}
+
+fn main() {
+    // The same levels and queries every run so timings can be compared
+    kb_seed_random(BENCH_SEED);
+
+    for num_shapes in SHAPE_COUNTS {
+        let mut collision_manager = build_level(num_shapes);
+        let filter = KbCollisionFilter::default();
+        let level_size = (num_shapes as f32).sqrt() * 4.0;
+        let random_point = || {
+            kb_random_vec3(
+                CgVec3::new(-level_size, 0.0, -level_size),
+                CgVec3::new(level_size, 4.0, level_size),
+            )
+        };
+
+        // Short rays like the monsters' move traces
+        time_queries("cast_ray short", num_shapes, || {
+            let dir = kb_random_vec3(CgVec3::new(-1.0, 0.0, -1.0), CgVec3::new(1.0, 0.0, 1.0));
+            let _ = collision_manager.cast_ray(&random_point(), &dir, 1.0, &filter);
+        });
+
+        // Long rays like the player's shots
+        time_queries("cast_ray long", num_shapes, || {
+            let dir = kb_random_vec3(CgVec3::new(-1.0, 0.0, -1.0), CgVec3::new(1.0, 0.0, 1.0));
+            let _ = collision_manager.cast_ray(&random_point(), &dir, level_size, &filter);
+        });
+
+        time_queries("cast_ray_all long", num_shapes, || {
+            let dir = kb_random_vec3(CgVec3::new(-1.0, 0.0, -1.0), CgVec3::new(1.0, 0.0, 1.0));
+            let _ = collision_manager.cast_ray_all(&random_point(), &dir, level_size, &filter);
+        });
+
+        time_queries("overlap_shape", num_shapes, || {
+            let sphere = KbCollisionShape::Sphere(KbCollisionSphere {
+                position: random_point(),
+                radius: 2.0,
+                block: false,
+                ..Default::default()
+            });
+            let _ = collision_manager.overlap_shape(&sphere, &filter);
+        });
+
+        time_queries("overlap_point", num_shapes, || {
+            let _ = collision_manager.overlap_point(&random_point(), &filter);
+        });
+
+        let moving_shape =
+            collision_manager.add_collision(&KbCollisionShape::Sphere(KbCollisionSphere {
+                position: CG_VEC3_ZERO,
+                radius: 1.0,
+                block: false,
+                ..Default::default()
+            }));
+        time_queries("update_position", num_shapes, || {
+            collision_manager.update_collision_position(&moving_shape, &random_point());
+        });
+        println!();
+    }
+}
@@ -1,9 +1,9 @@
 use cgmath::{InnerSpace, Vector3};
-use instant::Instant;
 
+use kb_engine3::kb_clock::{kb_time_now, kb_time_since};
 use kb_engine3::kb_utils::*;
 use kb_engine3::{
-    kb_config::KbConfig,
+    kb_config::{KbConfig, KbConfigDiff, KbConfigSection, KbConfigValue, KbJsonValue},
     kb_engine::KbGameEngine,
     kb_game_object::{GameObject, GameObjectState, GameObjectType},
     kb_input::KbInputManager,
@@ -18,10 +18,35 @@ const HILL_Z: f32 = 30.0;
 const BUILDING_Z: f32 = 50.0;
 const CHARACTER_Z: f32 = 100.0;
 
+// Read from the "game" section of game_config.txt
+pub struct Example2DConfig {
+    enemy_spawn_delay: f32,
+    enemy_move_speed: f32,
+}
+
+impl Default for Example2DConfig {
+    fn default() -> Self {
+        Self {
+            enemy_spawn_delay: 1.0,
+            enemy_move_speed: 0.01,
+        }
+    }
+}
+
+impl KbConfigSection for Example2DConfig {
+    fn apply_value(&mut self, key: &str, value: &KbJsonValue) -> Result<bool, &'static str> {
+        match key {
+            "enemy_spawn_delay" => self.enemy_spawn_delay = f32::from_config_value(value)?,
+            "enemy_move_speed" => self.enemy_move_speed = f32::from_config_value(value)?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
 pub struct Example2DGame {
     pub game_objects: Vec<GameObject>,
-    game_start_time: Instant,
-    current_frame_time: Instant,
+    game_start_time: f64,
     next_enemy_spawn_time: f32,
     num_enemies: u32,
 
@@ -37,7 +62,7 @@ impl Example2DGame {
             return;
         }
 
-        let game_time = self.game_start_time.elapsed().as_secs_f32();
+        let game_time = kb_time_since(self.game_start_time);
         if game_time > self.next_enemy_spawn_time {
             self.next_enemy_spawn_time = game_time + self.enemy_spawn_delay;
             self.num_enemies += 1;
@@ -64,8 +89,8 @@ impl Example2DGame {
                 texture_index: 0,
                 sprite_index: 8,
                 anim_frame: 0,
-                life_start_time: Instant::now(),
-                state_start_time: Instant::now(),
+                life_start_time: kb_time_now(),
+                state_start_time: kb_time_now(),
                 gravity_scale: 0.0,
                 random_val: kb_random_f32(0.0, 1000.0),
                 is_enemy: true,
@@ -121,21 +146,29 @@ impl Example2DGame {
 }
 
 impl KbGameEngine for Example2DGame {
+    fn initialize_input(&mut self, input_manager: &mut KbInputManager) {
+        input_manager
+            .load_bindings(include_str!("input_bindings.txt"))
+            .unwrap_or_else(|error| panic!("Failed to load input_bindings.txt - {}", error));
+    }
+
     fn new(game_config: &KbConfig) -> Self {
         log!("GameEngine::new() caled...");
 
-        let cur_time = Instant::now();
+        let cur_time = kb_time_now();
+        let example_config = game_config
+            .get_section::<Example2DConfig>("game")
+            .unwrap_or_else(|error| panic!("Bad \"game\" section in game_config.txt - {}", error));
 
         Self {
             game_objects: Vec::<GameObject>::new(),
             game_start_time: cur_time,
-            current_frame_time: cur_time,
-            next_enemy_spawn_time: cur_time.elapsed().as_secs_f32() + game_config.enemy_spawn_delay,
+            next_enemy_spawn_time: kb_time_since(cur_time) + example_config.enemy_spawn_delay,
             num_enemies: 0,
 
             max_game_objects: game_config.max_render_instances as usize,
-            enemy_spawn_delay: game_config.enemy_spawn_delay,
-            enemy_speed: game_config.enemy_move_speed,
+            enemy_spawn_delay: example_config.enemy_spawn_delay,
+            enemy_speed: example_config.enemy_move_speed,
         }
     }
 
@@ -158,8 +191,8 @@ impl KbGameEngine for Example2DGame {
             texture_index: 0,
             sprite_index: 0,
             anim_frame: 0,
-            life_start_time: Instant::now(),
-            state_start_time: Instant::now(),
+            life_start_time: kb_time_now(),
+            state_start_time: kb_time_now(),
             gravity_scale: 3.1,
             uv_tiles: (1.0, 1.0),
             random_val: kb_random_f32(0.0, 1000.0),
@@ -178,8 +211,8 @@ impl KbGameEngine for Example2DGame {
             texture_index: 0,
             sprite_index: 25,
             anim_frame: 0,
-            life_start_time: Instant::now(),
-            state_start_time: Instant::now(),
+            life_start_time: kb_time_now(),
+            state_start_time: kb_time_now(),
             gravity_scale: 0.0,
             random_val: kb_random_f32(0.0, 1000.0),
             uv_tiles: (1.0, 1.0),
@@ -198,8 +231,8 @@ impl KbGameEngine for Example2DGame {
             texture_index: 0,
             sprite_index: 27,
             anim_frame: 0,
-            life_start_time: Instant::now(),
-            state_start_time: Instant::now(),
+            life_start_time: kb_time_now(),
+            state_start_time: kb_time_now(),
             gravity_scale: 0.0,
             random_val: kb_random_f32(0.0, 1000.0),
             uv_tiles: (1.0, 1.0),
@@ -228,8 +261,8 @@ impl KbGameEngine for Example2DGame {
                 sprite_index: 18 + kb_random_u32(0, 1) as i32,
                 uv_tiles: (1.0, 1.0),
                 anim_frame: 0,
-                life_start_time: Instant::now(),
-                state_start_time: Instant::now(),
+                life_start_time: kb_time_now(),
+                state_start_time: kb_time_now(),
                 gravity_scale: 0.0,
                 random_val: kb_random_f32(0.0, 1000.0),
                 is_enemy: false,
@@ -253,8 +286,8 @@ impl KbGameEngine for Example2DGame {
             texture_index: 0,
             sprite_index: 21,
             anim_frame: 0,
-            life_start_time: Instant::now(),
-            state_start_time: Instant::now(),
+            life_start_time: kb_time_now(),
+            state_start_time: kb_time_now(),
             gravity_scale: 0.0,
             random_val: kb_random_f32(0.0, 1000.0),
             uv_tiles: (1.0, 1.0),
@@ -281,8 +314,8 @@ impl KbGameEngine for Example2DGame {
                 texture_index: 0,
                 sprite_index: kb_random_u32(32, 39) as i32,
                 anim_frame: 0,
-                life_start_time: Instant::now(),
-                state_start_time: Instant::now(),
+                life_start_time: kb_time_now(),
+                state_start_time: kb_time_now(),
                 gravity_scale: 0.0,
                 random_val: kb_random_f32(0.0, 1000.0),
                 uv_tiles: (1.0, 1.0),
@@ -307,8 +340,8 @@ impl KbGameEngine for Example2DGame {
                 texture_index: 0,
                 sprite_index: kb_random_u32(23, 24) as i32,
                 anim_frame: 0,
-                life_start_time: Instant::now(),
-                state_start_time: Instant::now(),
+                life_start_time: kb_time_now(),
+                state_start_time: kb_time_now(),
                 gravity_scale: 0.0,
                 random_val: kb_random_f32(0.0, 1000.0),
                 uv_tiles: (1.0, 1.0),
@@ -328,8 +361,8 @@ impl KbGameEngine for Example2DGame {
             texture_index: 0,
             sprite_index: 22,
             anim_frame: 0,
-            life_start_time: Instant::now(),
-            state_start_time: Instant::now(),
+            life_start_time: kb_time_now(),
+            state_start_time: kb_time_now(),
             gravity_scale: 0.0,
             random_val: kb_random_f32(0.0, 1000.0),
             uv_tiles: (1.0, 1.0),
@@ -346,8 +379,8 @@ impl KbGameEngine for Example2DGame {
             texture_index: 0,
             sprite_index: 22,
             anim_frame: 0,
-            life_start_time: Instant::now(),
-            state_start_time: Instant::now(),
+            life_start_time: kb_time_now(),
+            state_start_time: kb_time_now(),
             gravity_scale: 0.0,
             random_val: kb_random_f32(0.0, 1000.0),
             uv_tiles: (1.0, 1.0),
@@ -355,6 +388,24 @@ impl KbGameEngine for Example2DGame {
         });
     }
 
+    fn config_changed(&mut self, config_diff: &KbConfigDiff, game_config: &KbConfig) {
+        if !config_diff.contains("game.enemy_spawn_delay")
+            && !config_diff.contains("game.enemy_move_speed")
+        {
+            return;
+        }
+
+        match game_config.get_section::<Example2DConfig>("game") {
+            Ok(example_config) => {
+                self.enemy_spawn_delay = example_config.enemy_spawn_delay;
+                self.enemy_speed = example_config.enemy_move_speed;
+            }
+            Err(error) => {
+                log!("Ignoring \"game\" section changes - {}", error);
+            }
+        }
+    }
+
     fn get_game_objects(&self) -> &Vec<GameObject> {
         &self.game_objects
     }
@@ -362,12 +413,9 @@ impl KbGameEngine for Example2DGame {
     fn tick_frame_internal(
         &mut self,
         renderer: &mut KbRenderer,
-        input_manager: &KbInputManager,
-        _game_config: &KbConfig,
+        input_manager: &mut KbInputManager,
+        game_config: &KbConfig,
     ) {
-        let _delta_time_secs = self.current_frame_time.elapsed().as_secs_f32();
-        self.current_frame_time = Instant::now();
-
         // Player Movement
         let mut move_vec: cgmath::Vector3<f32> = (0.0, 0.0, 0.0).into();
 
@@ -396,17 +444,17 @@ impl KbGameEngine for Example2DGame {
             }
         }
 
-        if input_manager.get_key_state("a").is_down() || swipe_left {
+        if input_manager.action_down("move_left") || swipe_left {
             move_vec = Vector3::new(-1.0, 0.0, 0.0);
             self.game_objects[0].direction.x = -1.0;
         }
 
-        if input_manager.get_key_state("d").is_down() || swipe_right {
+        if input_manager.action_down("move_right") || swipe_right {
             move_vec = Vector3::new(1.0, 0.0, 0.0);
             self.game_objects[0].direction.x = 1.0;
         }
 
-        if input_manager.get_key_state("w").is_down() || jump {
+        if input_manager.action_down("jump") || jump {
             move_vec.y = 1.0;
         }
 
@@ -416,9 +464,7 @@ impl KbGameEngine for Example2DGame {
         self.update_projectiles();
 
         // Player Action
-        if (shoot || input_manager.get_key_state("space").is_down())
-            && self.game_objects[0].start_attack()
-        {
+        if (shoot || input_manager.action_down("fire")) && self.game_objects[0].start_attack() {
             let direction = self.game_objects[0].direction;
             let velocity = if direction.x > 0.0 {
                 (5.0, 0.0, 0.0).into()
@@ -436,8 +482,8 @@ impl KbGameEngine for Example2DGame {
                 texture_index: 0,
                 sprite_index: 5,
                 anim_frame: 0,
-                life_start_time: Instant::now(),
-                state_start_time: Instant::now(),
+                life_start_time: kb_time_now(),
+                state_start_time: kb_time_now(),
                 gravity_scale: 0.0,
                 random_val: kb_random_f32(0.0, 1000.0),
                 uv_tiles: (1.0, 1.0),
@@ -450,7 +496,7 @@ impl KbGameEngine for Example2DGame {
         // Update game objects
         let game_object_iter = self.game_objects.iter_mut();
         for game_object in game_object_iter {
-            game_object.update(_delta_time_secs);
+            game_object.update(game_config.delta_time);
         }
 
         let debug_msg = "Move: [W][A][S][D]    Shoot: [Space]\nToggle VSync: [V]".to_string();
@@ -6,7 +6,13 @@ use example_game::Example2DGame;
 
 fn main() {
     let config_file_text = include_str!("game_config.txt");
-    let game_config = KbConfig::new(config_file_text);
+    let mut game_config = KbConfig::load_layered(config_file_text)
+        .unwrap_or_else(|error| panic!("Failed to load game_config.txt - {}", error));
+    let config_file_path = concat!(env!("CARGO_MANIFEST_DIR"), "/src/game_config.txt");
+    game_config.watch_file(config_file_path);
+    if let Err(error) = game_config.load_user_settings(config_file_path) {
+        kb_engine3::log!("Ignoring user settings - {}", error);
+    }
 
     let run_game = kb_engine3::run_game::<Example2DGame>(game_config);
 
@@ -1,15 +1,18 @@
 use cgmath::InnerSpace;
-use instant::Instant;
 
 use kb_engine3::{
-    kb_assets::*, kb_collision::*, kb_config::*, kb_engine::*, kb_game_object::*, kb_input::*,
-    kb_renderer::*, kb_resource::*, kb_utils::*, log,
+    kb_assets::*, kb_clock::*, kb_collision::*, kb_config::*, kb_engine::*, kb_game_object::*,
+    kb_input::*, kb_renderer::*, kb_resource::*, kb_utils::*, log,
 };
 
 use crate::{game_actors::*, game_vfx::*};
 
 pub const CAMERA_MOVE_RATE: f32 = 10.0;
 pub const CAMERA_ROTATION_RATE: f32 = 150.0;
+pub const MOUSE_LOOK_RATE: f32 = 0.15;
+pub const MIN_ZOOM_FOV: f32 = 30.0;
+pub const PLAYER_RADIUS: f32 = 1.0;
+pub const PAUSE_MENU_CONTEXT: &str = "pause_menu";
 pub const CROSSHAIR_ERROR_RATE: f32 = 10.0;
 
 pub struct Example3DGame {
@@ -20,6 +23,8 @@ pub struct Example3DGame {
 
     game_objects: Vec<GameObject>,
     game_camera: KbCamera,
+    player_controller: KbCharacterController,
+    player_collision_handle: KbCollisionHandle,
 
     collision_manager: KbCollisionManager,
     vfx_manager: GameVfxManager,
@@ -33,15 +38,20 @@ pub struct Example3DGame {
     outline_render_group: usize,
     decal_render_group: usize,
 
-    monster_spawn_timer: Instant,
-    barrel_spawn_timer: Instant,
-    shotgun_spawn_timer: Instant,
+    monster_spawn_timer: f64,
+    barrel_spawn_timer: f64,
+    shotgun_spawn_timer: f64,
 
     score: i32,
     high_score: i32,
+    player_name: String,
     next_harm_time: f32,
+    enemy_spawn_delay: f32,
 
     crosshair_error: f32,
+    move_stick: KbVirtualStick,
+    look_stick: KbVirtualStick,
+    zoom_fov: f32,
     invert_y: bool,
     debug_collision: bool,
     pause_monsters: bool,
@@ -178,12 +188,23 @@ impl Example3DGame {
 }
 
 impl KbGameEngine for Example3DGame {
-    fn new(_game_config: &KbConfig) -> Self {
+    fn initialize_input(&mut self, input_manager: &mut KbInputManager) {
+        input_manager
+            .load_bindings(include_str!("input_bindings.txt"))
+            .unwrap_or_else(|error| panic!("Failed to load input_bindings.txt - {}", error));
+    }
+
+    fn new(game_config: &KbConfig) -> Self {
         log!("GameEngine::new() caled...");
+        let enemy_spawn_delay = game_config
+            .get_value_or("game", "enemy_spawn_delay", 1.0)
+            .unwrap_or_else(|error| panic!("Bad \"game\" section in game_config.txt - {}", error));
         let game_objects = Vec::<GameObject>::new();
 
         let mut game_camera = KbCamera::new();
         game_camera.set_position(&CgVec3::new(0.0, 3.5, -5.0));
+        let player_controller =
+            KbCharacterController::new(&game_camera.get_position(), PLAYER_RADIUS, 0.0);
 
         Self {
             world_actors: Vec::<KbActor>::new(),
@@ -191,26 +212,33 @@ impl KbGameEngine for Example3DGame {
             props: Vec::<GameProp>::new(),
             game_objects,
             game_camera,
+            player_controller,
+            player_collision_handle: KbCollisionHandle::make_invalid(),
             vfx_manager: GameVfxManager::new(),
             sign_prop: None,
             barrel_model: KbModelHandle::make_invalid(),
             shotgun_model: KbModelHandle::make_invalid(),
             monster_model: KbModelHandle::make_invalid(),
             monster_render_group: usize::MAX,
-            monster_spawn_timer: Instant::now(),
-            shotgun_spawn_timer: Instant::now(),
-            barrel_spawn_timer: Instant::now(),
+            monster_spawn_timer: kb_time_now(),
+            shotgun_spawn_timer: kb_time_now(),
+            barrel_spawn_timer: kb_time_now(),
             outline_render_group: usize::MAX,
             decal_render_group: usize::MAX,
             player: None,
             crosshair_error: 0.0,
+            move_stick: KbVirtualStick::new((0.0, 0.0), (0.37, 1.0), (0.09, 0.86), 0.12),
+            look_stick: KbVirtualStick::new((0.37, 0.74), (1.0, 1.0), (0.9, 0.86), 0.12),
+            zoom_fov: game_config.fov,
             collision_manager: KbCollisionManager::new(),
             debug_collision: false,
             invert_y: false,
             pause_monsters: false,
             score: 0,
             high_score: 0,
+            player_name: "Player".to_string(),
             next_harm_time: -1.0,
+            enemy_spawn_delay,
             post_process_override: KbPostProcessMode::Passthrough,
         }
     }
@@ -221,8 +249,6 @@ impl KbGameEngine for Example3DGame {
         game_config: &mut KbConfig,
     ) {
         log!("GameEngine::initialize_world()...");
-        game_config.clear_color = CgVec4::new(0.87, 0.58, 0.24, 1.0);
-        game_config.sun_color = CgVec4::new(0.8 * 0.8, 0.58 * 0.58, 0.24 * 0.24, 0.0);
 
         // self.game_objects order is hard-coded.  Indices 0-3 contain the cross hair sprites
         for _ in 0..4 {
@@ -237,8 +263,8 @@ impl KbGameEngine for Example3DGame {
                 texture_index: 1,
                 sprite_index: 40,
                 anim_frame: 0,
-                life_start_time: Instant::now(),
-                state_start_time: Instant::now(),
+                life_start_time: kb_time_now(),
+                state_start_time: kb_time_now(),
                 gravity_scale: 0.0,
                 random_val: kb_random_f32(0.0, 1000.0),
                 is_enemy: false,
@@ -246,83 +272,6 @@ impl KbGameEngine for Example3DGame {
             });
         }
 
-        // Index 4 and 5 are the virtual thumb sticks
-        self.game_objects.push(GameObject {
-            position: CgVec3::new(1.45, -0.7, 0.0),
-            scale: CgVec3::new(0.2, 0.2, 1.0),
-            direction: (1.0, 0.0, 0.0).into(),
-            velocity: (0.0, 0.0, 0.0).into(),
-            object_type: GameObjectType::Background,
-            object_state: GameObjectState::Idle,
-            next_attack_time: 0.0,
-            texture_index: 0,
-            sprite_index: 43,
-            anim_frame: 0,
-            life_start_time: Instant::now(),
-            state_start_time: Instant::now(),
-            gravity_scale: 0.0,
-            random_val: kb_random_f32(0.0, 1000.0),
-            is_enemy: false,
-            uv_tiles: (2.0, 2.0),
-        });
-
-        self.game_objects.push(GameObject {
-            position: CgVec3::new(-1.45, -0.7, 0.0),
-            scale: CgVec3::new(0.2, 0.2, 1.0),
-            direction: (1.0, 0.0, 0.0).into(),
-            velocity: (0.0, 0.0, 0.0).into(),
-            object_type: GameObjectType::Background,
-            object_state: GameObjectState::Idle,
-            next_attack_time: 0.0,
-            texture_index: 0,
-            sprite_index: 45,
-            anim_frame: 0,
-            life_start_time: Instant::now(),
-            state_start_time: Instant::now(),
-            gravity_scale: 0.0,
-            random_val: kb_random_f32(0.0, 1000.0),
-            is_enemy: false,
-            uv_tiles: (2.0, 2.0),
-        });
-
-        self.game_objects.push(GameObject {
-            position: CgVec3::new(-1.45, -0.7, 0.0),
-            scale: CgVec3::new(0.1, 0.1, 1.0),
-            direction: (1.0, 0.0, 0.0).into(),
-            velocity: (0.0, 0.0, 0.0).into(),
-            object_type: GameObjectType::Background,
-            object_state: GameObjectState::Idle,
-            next_attack_time: 0.0,
-            texture_index: 0,
-            sprite_index: 58,
-            anim_frame: 0,
-            life_start_time: Instant::now(),
-            state_start_time: Instant::now(),
-            gravity_scale: 0.0,
-            random_val: kb_random_f32(0.0, 1000.0),
-            is_enemy: false,
-            uv_tiles: (1.0, 1.0),
-        });
-
-        self.game_objects.push(GameObject {
-            position: CgVec3::new(1.45, -0.7, 0.0),
-            scale: CgVec3::new(0.1, 0.1, 1.0),
-            direction: (1.0, 0.0, 0.0).into(),
-            velocity: (0.0, 0.0, 0.0).into(),
-            object_type: GameObjectType::Background,
-            object_state: GameObjectState::Idle,
-            next_attack_time: 0.0,
-            texture_index: 0,
-            sprite_index: 59,
-            anim_frame: 0,
-            life_start_time: Instant::now(),
-            state_start_time: Instant::now(),
-            gravity_scale: 0.0,
-            random_val: kb_random_f32(0.0, 1000.0),
-            is_enemy: false,
-            uv_tiles: (1.0, 1.0),
-        });
-
         self.shotgun_model = renderer
             .load_model("game_assets/models/shotgun.glb", false)
             .await;
@@ -495,13 +444,15 @@ impl KbGameEngine for Example3DGame {
             position: CgVec3::new(0.0, 2.4, 20.0),
             extents: CgVec3::new(20.0, 10.0, 2.0),
             block: true,
+            ..Default::default()
         });
         let _ = self.collision_manager.add_collision(&collision_box);
 
         let collision_box = KbCollisionShape::AABB(KbCollisionAABB {
             position: CgVec3::new(0.0, 2.4, -20.0),
-            extents: CgVec3::new(-20.0, 10.0, 2.0),
+            extents: CgVec3::new(20.0, 10.0, 2.0),
             block: true,
+            ..Default::default()
         });
         let _ = self.collision_manager.add_collision(&collision_box);
 
@@ -509,6 +460,7 @@ impl KbGameEngine for Example3DGame {
             position: CgVec3::new(20.0, 2.4, 0.0),
             extents: CgVec3::new(2.0, 10.0, 20.0),
             block: true,
+            ..Default::default()
         });
         let _ = self.collision_manager.add_collision(&collision_box);
 
@@ -516,6 +468,7 @@ impl KbGameEngine for Example3DGame {
             position: CgVec3::new(-20.0, 2.4, 0.0),
             extents: CgVec3::new(2.0, 10.0, 20.0),
             block: true,
+            ..Default::default()
         });
         let _ = self.collision_manager.add_collision(&collision_box);
 
@@ -523,9 +476,21 @@ impl KbGameEngine for Example3DGame {
             position: CgVec3::new(0.0, -0.5, 0.0),
             extents: CgVec3::new(20.0, 0.0, 20.0),
             block: true,
+            ..Default::default()
         });
         let _ = self.collision_manager.add_collision(&collision_box);
 
+        // The player's body from the floor to above the camera, so shots never start outside it
+        let player_pos = self.player_controller.position;
+        let player_box = KbCollisionShape::AABB(KbCollisionAABB {
+            position: player_pos,
+            extents: CgVec3::new(PLAYER_RADIUS, player_pos.y, PLAYER_RADIUS),
+            block: false,
+            layer: PLAYER_COLLISION_LAYER,
+            ..Default::default()
+        });
+        self.player_collision_handle = self.collision_manager.add_collision(&player_box);
+
         // Trans Flag
         let sun_color = game_config.sun_color;
         let trans_colors = [
@@ -591,6 +556,17 @@ impl KbGameEngine for Example3DGame {
         self.spawn_barrel(renderer);
     }
 
+    fn config_changed(&mut self, config_diff: &KbConfigDiff, game_config: &KbConfig) {
+        if config_diff.contains("game.enemy_spawn_delay") {
+            match game_config.get_value_or("game", "enemy_spawn_delay", 1.0) {
+                Ok(enemy_spawn_delay) => self.enemy_spawn_delay = enemy_spawn_delay,
+                Err(error) => {
+                    log!("Ignoring \"game\" section changes - {}", error);
+                }
+            }
+        }
+    }
+
     fn get_game_objects(&self) -> &Vec<GameObject> {
         &self.game_objects
     }
@@ -598,7 +574,7 @@ impl KbGameEngine for Example3DGame {
     fn tick_frame_internal(
         &mut self,
         renderer: &mut KbRenderer,
-        input_manager: &KbInputManager,
+        input_manager: &mut KbInputManager,
         game_config: &KbConfig,
     ) {
         for game_object in &mut self.game_objects {
@@ -610,103 +586,51 @@ impl KbGameEngine for Example3DGame {
         let camera_pos = self.game_camera.get_position();
         let mut camera_rot = self.game_camera.get_rotation();
 
-        let mut move_vec = CG_VEC3_ZERO;
-        let touch_map_iter = input_manager.get_touch_map().iter();
-        let mut local_move_vec_move = (0.0, 0.0);
-        let mut local_move_vec_look = (0.0, 0.0);
-        for touch_pair in touch_map_iter {
-            let touch = &touch_pair.1;
-
-            // Left thumb
-            if touch.touch_state.is_down() && touch.start_pos.0 < 500.0 {
-                local_move_vec_move.0 = (touch.current_pos.0 as f32 - 118.0).clamp(-90.0, 90.0);
-                if local_move_vec_move.0 < 0.0 {
-                    local_move_vec_move.0 = (local_move_vec_move.0 + 20.0).clamp(-90.0, 0.0);
-                } else {
-                    local_move_vec_move.0 = (local_move_vec_move.0 - 20.0).clamp(0.0, 90.0);
+        // Touch thumbsticks
+        self.move_stick.update(input_manager);
+        self.look_stick.update(input_manager);
+        let stick_color = CgVec4::new(1.0, 1.0, 1.0, 0.35);
+        renderer.draw_virtual_stick(&self.move_stick, &stick_color);
+        renderer.draw_virtual_stick(&self.look_stick, &stick_color);
+
+        // Pinch to zoom.  Ignored while a thumb is on a stick so moving both sticks isn't a pinch
+        if !self.move_stick.is_active() && !self.look_stick.is_active() {
+            for gesture in input_manager.get_gestures() {
+                if let KbGesture::Pinch { scale, .. } = gesture {
+                    self.zoom_fov =
+                        (self.zoom_fov / *scale as f32).clamp(MIN_ZOOM_FOV, game_config.fov);
+                    renderer.set_fov(self.zoom_fov);
                 }
-                local_move_vec_move.1 = (touch.current_pos.1 as f32 - 660.0).clamp(-90.0, 90.0);
-                move_vec += right_dir * local_move_vec_move.0;
-                move_vec -= forward_dir * local_move_vec_move.1;
-            }
-
-            // Right Thumb
-            if touch.start_pos.1 > 570.0 && touch.touch_state.is_down() && touch.start_pos.0 > 500.0
-            {
-                local_move_vec_look.0 = (touch.current_pos.0 as f32 - 1233.0).clamp(-90.0, 90.0);
-                local_move_vec_look.1 = (touch.current_pos.1 as f32 - 660.0).clamp(-90.0, 90.0);
-                if local_move_vec_look.0 < 0.0 {
-                    local_move_vec_look.0 = (local_move_vec_look.0 + 20.0).clamp(-90.0, 0.0);
-                } else {
-                    local_move_vec_look.0 = (local_move_vec_look.0 - 20.0).clamp(0.0, 90.0);
-                }
-
-                camera_rot.x -= 2.0 * delta_time * local_move_vec_look.0;
-                camera_rot.y += 1.0 * delta_time * local_move_vec_look.1;
             }
-
-            // Help
-            if touch.start_pos.0 < 300.0
-                && touch.start_pos.1 < 300.0
-                && touch.touch_state.just_pressed()
-            {
-                renderer.enable_help_text();
-            }
-
-            // Help
-            if touch.start_pos.0 < 300.0
-                && touch.start_pos.1 < 300.0
-                && touch.touch_state.just_pressed()
-            {
-                renderer.enable_help_text();
-            }
-        }
-
-        if input_manager.get_key_state("w").is_down() {
-            move_vec += forward_dir
         }
 
-        if input_manager.get_key_state("s").is_down() {
-            move_vec += -forward_dir;
-        }
+        let mut move_vec = CG_VEC3_ZERO;
+        let move_axis = self.move_stick.get_axis();
+        move_vec += right_dir * move_axis.0;
+        move_vec -= forward_dir * move_axis.1;
 
-        if input_manager.get_key_state("d").is_down() {
-            move_vec += right_dir;
+        if input_manager.action_just_pressed("toggle_help") {
+            renderer.enable_help_text();
         }
 
-        if input_manager.get_key_state("a").is_down() {
-            move_vec += -right_dir;
-        }
+        move_vec += forward_dir * input_manager.action_axis("move_back", "move_forward");
+        move_vec += right_dir * input_manager.action_axis("move_left", "move_right");
 
         move_vec = move_vec.normalize();
-        if input_manager.get_key_state("left_shift").is_down() {
+        if input_manager.action_down("walk") {
             move_vec *= 0.45;
         }
         move_vec *= delta_time * CAMERA_MOVE_RATE;
 
         if move_vec.magnitude2() > 0.001 {
-            let trace_start = CgVec3::new(camera_pos.x, 0.25, camera_pos.z);
-            let (t, handle, _, _) = self.collision_manager.cast_ray(&trace_start, &move_vec);
-            if (0.0..1.0).contains(&t) {
-                self.props.retain_mut(|prop| {
-                    if prop.get_prop_type() == GamePropType::Shotgun
-                        && prop.get_collision_handle() == *handle.as_ref().unwrap()
-                    {
-                        prop.take_damage(&mut self.collision_manager, renderer);
-                        self.player
-                            .as_mut()
-                            .unwrap()
-                            .give_shotgun(&self.shotgun_model);
-                        return false;
-                    }
-                    true
-                });
-            }
-            let mut final_pos = camera_pos + move_vec;
-            final_pos.x = final_pos.x.clamp(-17.0, 17.0);
-            final_pos.z = final_pos.z.clamp(-17.0, 17.0);
-
-            self.game_camera.set_position(&final_pos);
+            self.player_controller
+                .move_and_slide(&self.collision_manager, &move_vec);
+            self.game_camera
+                .set_position(&self.player_controller.position);
+            self.collision_manager.update_collision_position(
+                &self.player_collision_handle,
+                &self.player_controller.position,
+            );
 
             self.crosshair_error =
                 (self.crosshair_error + delta_time * CROSSHAIR_ERROR_RATE).clamp(0.0, 1.0);
@@ -715,6 +639,29 @@ impl KbGameEngine for Example3DGame {
                 (self.crosshair_error - delta_time * CROSSHAIR_ERROR_RATE).clamp(0.0, 1.0);
         }
 
+        // Pick up shotguns the player walks into
+        self.collision_manager.update_triggers();
+        for event in self.collision_manager.drain_trigger_events() {
+            if event.event_type != KbTriggerEventType::Enter
+                || event.other != self.player_collision_handle
+            {
+                continue;
+            }
+            self.props.retain_mut(|prop| {
+                if prop.get_prop_type() == GamePropType::Shotgun
+                    && prop.get_collision_handle() == event.trigger
+                {
+                    prop.take_damage(&mut self.collision_manager, renderer);
+                    self.player
+                        .as_mut()
+                        .unwrap()
+                        .give_shotgun(&self.shotgun_model);
+                    return false;
+                }
+                true
+            });
+        }
+
         let x_radians = delta_time * CAMERA_ROTATION_RATE;
         let y_radians = if self.invert_y {
             -delta_time * CAMERA_ROTATION_RATE
@@ -722,17 +669,23 @@ impl KbGameEngine for Example3DGame {
             delta_time * CAMERA_ROTATION_RATE
         };
 
-        if input_manager.get_key_state("left_arrow").is_down() {
-            camera_rot.x += x_radians;
-        }
-        if input_manager.get_key_state("right_arrow").is_down() {
-            camera_rot.x -= x_radians;
-        }
-        if input_manager.get_key_state("up_arrow").is_down() {
-            camera_rot.y -= y_radians;
-        }
-        if input_manager.get_key_state("down_arrow").is_down() {
-            camera_rot.y += y_radians
+        camera_rot.x -= x_radians * input_manager.action_axis("look_left", "look_right");
+        camera_rot.y += y_radians * input_manager.action_axis("look_up", "look_down");
+
+        let look_axis = self.look_stick.get_axis();
+        camera_rot.x -= x_radians * look_axis.0;
+        camera_rot.y += 0.5 * x_radians * look_axis.1;
+
+        if renderer.is_cursor_locked() {
+            if input_manager.action_just_pressed("unlock_cursor") {
+                renderer.set_cursor_locked(false);
+            }
+            let (mouse_x, mouse_y) = input_manager.get_mouse_look(game_config);
+            let mouse_y = if self.invert_y { -mouse_y } else { mouse_y };
+            camera_rot.x -= mouse_x * MOUSE_LOOK_RATE;
+            camera_rot.y += mouse_y * MOUSE_LOOK_RATE;
+        } else if input_manager.action_just_pressed("lock_cursor") {
+            renderer.set_cursor_locked(true);
         }
         camera_rot.y = camera_rot.y.clamp(-60.0, 75.0);
 
@@ -793,10 +746,14 @@ impl KbGameEngine for Example3DGame {
                     }
                 };
 
-                let (hit_t, handle, hit_loc, _) = self
-                    .collision_manager
-                    .cast_ray(&trace_start_pos, &trace_end_pos);
-                let found_hit = (0.0..1.0).contains(&hit_t);
+                let trace_dir = trace_end_pos - trace_start_pos;
+                let ray_hit = self.collision_manager.cast_ray(
+                    &trace_start_pos,
+                    &trace_dir,
+                    trace_dir.magnitude(),
+                    &KbCollisionFilter::default(),
+                );
+                let found_hit = ray_hit.is_some();
                 let mut mob_killed = false;
 
                 let color = if found_hit {
@@ -805,21 +762,19 @@ impl KbGameEngine for Example3DGame {
                     CgVec4::new(0.0, 0.0, 1.0, 1.0)
                 };
 
-                if found_hit && self.sign_prop.is_some() {
-                    let sign_prop = self.sign_prop.as_mut().unwrap();
-                    if sign_prop.collision_handle == handle.unwrap() {
-                        let trace_dir = (trace_end_pos - trace_start_pos).normalize();
+                if let (Some(hit), Some(sign_prop)) = (&ray_hit, self.sign_prop.as_mut()) {
+                    if sign_prop.collision_handle == hit.handle {
                         renderer.add_bullet_hole(
                             &sign_prop.get_actors()[0],
-                            &hit_loc.unwrap(),
-                            &trace_dir,
+                            &hit.point,
+                            &trace_dir.normalize(),
                         );
                     }
                 }
-                if found_hit {
-                    let hit_loc = hit_loc.unwrap();
+                if let Some(hit) = ray_hit {
+                    let hit_loc = hit.point;
                     self.mobs.retain_mut(|mob| {
-                        if mob.get_collision_handle() == *handle.as_ref().unwrap() {
+                        if mob.get_collision_handle() == hit.handle {
                             mob_killed = mob.take_damage(&mut self.collision_manager, renderer);
                             self.score += 1;
 
@@ -843,7 +798,7 @@ impl KbGameEngine for Example3DGame {
                     if !mob_killed {
                         self.props.retain_mut(|prop| {
                             if prop.get_prop_type() == GamePropType::Barrel
-                                && prop.get_collision_handle() == *handle.as_ref().unwrap()
+                                && prop.get_collision_handle() == hit.handle
                             {
                                 explode_pos = prop.get_actors()[0].get_position();
 
@@ -921,13 +876,13 @@ impl KbGameEngine for Example3DGame {
         let spawn_timer = {
             let t = 1.0 - (self.score as f32 / 20.0).clamp(0.0, 1.0);
             if self.score == 0 {
-                t + game_config.enemy_spawn_delay + 5.0
+                t + self.enemy_spawn_delay + 5.0
             } else {
-                t + game_config.enemy_spawn_delay + 1.0
+                t + self.enemy_spawn_delay + 1.0
             }
         };
-        if self.monster_spawn_timer.elapsed().as_secs_f32() > spawn_timer {
-            self.monster_spawn_timer = Instant::now();
+        if kb_time_since(self.monster_spawn_timer) > spawn_timer {
+            self.monster_spawn_timer = kb_time_now();
             self.spawn_monster(renderer);
         }
 
@@ -937,8 +892,8 @@ impl KbGameEngine for Example3DGame {
             .filter(|&p| p.get_prop_type() == GamePropType::Shotgun)
             .count();
         if shotgun_count > 0 {
-            self.shotgun_spawn_timer = Instant::now();
-        } else if self.shotgun_spawn_timer.elapsed().as_secs_f32() > 20.0 {
+            self.shotgun_spawn_timer = kb_time_now();
+        } else if kb_time_since(self.shotgun_spawn_timer) > 20.0 {
             self.spawn_shotgun(renderer);
         }
 
@@ -948,8 +903,8 @@ impl KbGameEngine for Example3DGame {
             .filter(|&p| p.get_prop_type() == GamePropType::Barrel)
             .count();
         if barrel_count > 0 {
-            self.barrel_spawn_timer = Instant::now();
-        } else if self.barrel_spawn_timer.elapsed().as_secs_f32() > 20.0 {
+            self.barrel_spawn_timer = kb_time_now();
+        } else if kb_time_since(self.barrel_spawn_timer) > 20.0 {
             self.spawn_barrel(renderer);
         }
 
@@ -961,7 +916,7 @@ impl KbGameEngine for Example3DGame {
         }
 
         if num_attacking > 0 {
-            let elapsed_time = game_config.start_time.elapsed().as_secs_f32();
+            let elapsed_time = kb_time_since(game_config.start_time);
             if self.next_harm_time < 0.0 {
                 self.next_harm_time = elapsed_time + 1.0;
             } else if elapsed_time > self.next_harm_time {
@@ -984,7 +939,49 @@ impl KbGameEngine for Example3DGame {
         // UI
         {
             self.high_score = self.high_score.max(self.score);
-            renderer.set_hud_msg("-/+ to change post-processes");
+
+            // The pause menu sits above gameplay and takes all of its input
+            if input_manager.is_top_input_context(PAUSE_MENU_CONTEXT) {
+                if input_manager
+                    .get_input_context(PAUSE_MENU_CONTEXT)
+                    .action_just_pressed("pause_menu")
+                {
+                    input_manager.remove_input_context(PAUSE_MENU_CONTEXT);
+                }
+            } else if input_manager.action_just_pressed("pause_menu") {
+                let pause_menu_context = KbInputContext::new_exclusive(PAUSE_MENU_CONTEXT);
+                input_manager.push_input_context(pause_menu_context);
+                renderer.set_cursor_locked(false);
+            }
+
+            if input_manager.is_text_input_active() {
+                if input_manager.text_input_submitted() {
+                    self.player_name = input_manager.stop_text_input();
+                } else if input_manager.get_key_state("escape").just_pressed() {
+                    input_manager.stop_text_input();
+                }
+            } else if input_manager.action_just_pressed("rename_player") {
+                input_manager.start_text_input(&self.player_name);
+            }
+
+            let hud_msg = if input_manager.has_input_context(PAUSE_MENU_CONTEXT) {
+                "Paused   [P] to resume".to_string()
+            } else if input_manager.is_text_input_active() {
+                let name = input_manager.get_text_input();
+                let cursor = input_manager.get_text_cursor();
+                format!(
+                    "Name: {}{}|{}   [Enter] to accept, [Esc] to cancel",
+                    &name[..cursor],
+                    input_manager.get_text_composition(),
+                    &name[cursor..]
+                )
+            } else {
+                format!(
+                    "-/+ to change post-processes   [N] to rename {}",
+                    self.player_name
+                )
+            };
+            renderer.set_hud_msg(&hud_msg);
             let player = self.player.as_ref().unwrap();
             let (positions, sprites, scale) = {
                 if !player.has_shotgun() {
@@ -1038,8 +1035,8 @@ impl KbGameEngine for Example3DGame {
                     texture_index: 0,
                     sprite_index,
                     anim_frame: 0,
-                    life_start_time: Instant::now(),
-                    state_start_time: Instant::now(),
+                    life_start_time: kb_time_now(),
+                    state_start_time: kb_time_now(),
                     gravity_scale: 0.0,
                     random_val: kb_random_f32(0.0, 1000.0),
                     is_enemy: false,
@@ -1049,29 +1046,20 @@ impl KbGameEngine for Example3DGame {
             }
         }
 
-        // Virtual sticks
-        self.game_objects[6].position.x = (-1.45 + local_move_vec_move.0 * 0.001).clamp(-1.6, -1.4);
-        self.game_objects[6].position.y =
-            (-0.7 - local_move_vec_move.1 * 0.001).clamp(-0.85, -0.65);
-
-        self.game_objects[7].position.x = (1.45 + local_move_vec_look.0 * 0.001).clamp(1.4, 1.6);
-        self.game_objects[7].position.y =
-            (-0.7 - local_move_vec_look.1 * 0.001).clamp(-0.85, -0.65);
-
         // Debug
-        if input_manager.get_key_state("i").just_pressed() {
+        if input_manager.action_just_pressed("toggle_collision") {
             self.debug_collision = !self.debug_collision;
         }
 
-        if input_manager.get_key_state("y").just_pressed() {
+        if input_manager.action_just_pressed("toggle_invert_y") {
             self.invert_y = !self.invert_y;
         }
 
-        if input_manager.get_key_state("m").just_pressed() {
+        if input_manager.action_just_pressed("toggle_pause_monsters") {
             self.pause_monsters = !self.pause_monsters;
         }
 
-        if input_manager.get_key_state("+").just_pressed() {
+        if input_manager.action_just_pressed("cycle_postprocess") {
             self.post_process_override = match self.post_process_override {
                 KbPostProcessMode::Passthrough => KbPostProcessMode::Desaturation,
                 KbPostProcessMode::Desaturation => KbPostProcessMode::ScanLines,
@@ -1088,7 +1076,7 @@ impl KbGameEngine for Example3DGame {
         let num_active_particles = renderer.num_active_particles();
         let num_active_decals = self.vfx_manager.num_active_decals();
 
-        let debug_msg = format!("Move: [W][A][S][D] or L Thumb   Look: [Arrow Keys] or R Thumb    Shoot: [Space] or tap bullets\nToggle VSync: [V]   Invert Y: [Y]   Toggle collision: [i]   Pause monsters: [M]\n\
+        let debug_msg = format!("Move: [W][A][S][D] or L Thumb   Look: [Arrow Keys], Mouse (click to lock, [Esc] to unlock) or R Thumb    Shoot: [Space] or tap bullets   Reload: [R]\nToggle VSync: [V]   Invert Y: [Y]   Toggle collision: [i]   Pause monsters: [M]\n\
             # collision objs = {}, # active particles {}, # active decals {}", num_collision_obj, num_active_particles, num_active_decals);
         renderer.set_debug_game_msg(&debug_msg);
         renderer.set_debug_font_color(&CgVec4::new(1.0, 0.0, 0.0, 1.0));
@@ -1,9 +1,8 @@
 use cgmath::{InnerSpace, Rotation, SquareMatrix};
-use instant::Instant;
 
 use kb_engine3::{
-    kb_assets::*, kb_collision::*, kb_config::*, kb_game_object::*, kb_input::*, kb_renderer::*,
-    kb_resource::*, kb_utils::*,
+    kb_assets::*, kb_clock::*, kb_collision::*, kb_config::*, kb_game_object::*, kb_input::*,
+    kb_renderer::*, kb_resource::*, kb_utils::*,
 };
 
 #[allow(dead_code)]
@@ -18,7 +17,7 @@ pub enum GamePlayerState {
 
 pub struct GamePlayer {
     current_state: GamePlayerState,
-    current_state_time: Instant,
+    current_state_time: f64,
 
     hands_model: KbModelHandle,
     hands_actor: KbActor,
@@ -37,6 +36,9 @@ const PISTOL_AMMO_MAX: u32 = 8;
 const SHOTGUN_AMMO_MAX: u32 = 4;
 pub const GLOBAL_SCALE: CgVec3 = CgVec3::new(0.3, 0.3, 0.3);
 
+// The player's collision is on its own layer so only pickups' triggers look for it
+pub const PLAYER_COLLISION_LAYER: u32 = 2;
+
 impl GamePlayer {
     pub async fn new(hands_model: &KbModelHandle) -> Self {
         let mut hands_actor = KbActor::new();
@@ -66,7 +68,7 @@ impl GamePlayer {
 
         GamePlayer {
             current_state: GamePlayerState::Idle,
-            current_state_time: Instant::now(),
+            current_state_time: kb_time_now(),
             hands_actor,
             outline_actors,
             has_shotgun: false,
@@ -85,7 +87,7 @@ impl GamePlayer {
 
     pub fn set_state(&mut self, new_state: GamePlayerState) {
         self.current_state = new_state;
-        self.current_state_time = Instant::now();
+        self.current_state_time = kb_time_now();
     }
 
     pub fn give_shotgun(&mut self, model_handle: &KbModelHandle) {
@@ -168,22 +170,20 @@ impl GamePlayer {
     }
 
     fn tick_idle(&mut self, input_manager: &KbInputManager) -> GamePlayerState {
-        let touch_map_iter = input_manager.get_touch_map().iter();
-        let mut fire = false;
-        for touch_pair in touch_map_iter {
-            let touch = &touch_pair.1;
-            if touch.start_pos.1 < 570.0 && touch.start_pos.0 > 500.0 {
-                fire = true;
-                break;
-            }
-        }
-        if self.current_state_time.elapsed().as_secs_f32() > 0.1
-            && (input_manager.get_key_state("space").is_down() || fire)
-        {
+        if kb_time_since(self.current_state_time) > 0.1 && input_manager.action_down("fire") {
             self.set_state(GamePlayerState::Shooting);
             self.ammo_count -= 1;
             return GamePlayerState::Shooting;
         }
+
+        // The shotgun is dropped once it runs out, so only the pistol can be reloaded early
+        if !self.has_shotgun
+            && self.ammo_count < PISTOL_AMMO_MAX
+            && input_manager.action_just_pressed("reload")
+        {
+            self.set_state(GamePlayerState::StartReloading);
+            return GamePlayerState::StartReloading;
+        }
         GamePlayerState::Idle
     }
 
@@ -191,7 +191,7 @@ impl GamePlayer {
         let shoot_state_length = 0.3;
         let recoil_time = 0.001;
 
-        let elasped_state_time = self.current_state_time.elapsed().as_secs_f32();
+        let elasped_state_time = kb_time_since(self.current_state_time);
         let t = if elasped_state_time <= recoil_time {
             elasped_state_time / recoil_time
         } else {
@@ -208,7 +208,7 @@ impl GamePlayer {
         self.recoil_radians = cgmath::Rad::from(cgmath::Deg(max_angle * t));
         self.recoil_offset = t * max_offset;
 
-        if self.current_state_time.elapsed().as_secs_f32() > 0.3 {
+        if kb_time_since(self.current_state_time) > 0.3 {
             if self.ammo_count == 0 {
                 self.set_state(GamePlayerState::StartReloading);
                 return GamePlayerState::StartReloading;
@@ -226,7 +226,7 @@ impl GamePlayer {
         let half_duration = reload_duration * 0.5;
         let hand_lower_distance = -3.0;
 
-        let cur_state_time = self.current_state_time.elapsed().as_secs_f32();
+        let cur_state_time = kb_time_since(self.current_state_time);
         if cur_state_time < half_duration {
             self.hand_bone_offset.y = (hand_lower_distance * cur_state_time * one_over_duration)
                 .clamp(hand_lower_distance, 0.0);
@@ -269,7 +269,7 @@ impl GamePlayer {
         let half_duration = reload_duration * 0.5;
         let hand_lower_distance = -3.0;
 
-        let cur_state_time = self.current_state_time.elapsed().as_secs_f32();
+        let cur_state_time = kb_time_since(self.current_state_time);
         if cur_state_time < half_duration {
             self.hand_bone_offset.y =
                 (hand_lower_distance * (half_duration - cur_state_time) * one_over_duration)
@@ -294,9 +294,10 @@ pub enum GameMobState {
 pub struct GameMob {
     monster_actors: Vec<KbActor>,
     collision_handle: KbCollisionHandle,
+    controller: KbCharacterController,
 
     current_state: GameMobState,
-    _current_state_time: Instant,
+    _current_state_time: f64,
 }
 
 impl GameMob {
@@ -317,9 +318,14 @@ impl GameMob {
             position: monster_actor.get_position(),
             extents: CgVec3::new(2.0, 2.0, 2.0),
             block: true,
+            ..Default::default()
         });
         let collision_handle = collision_manager.add_collision(&collision_box);
 
+        // Mobs don't trip over their own collision box
+        let mut controller = KbCharacterController::new(&monster_actor.get_position(), 2.0, 0.0);
+        controller.filter.ignore.push(collision_handle);
+
         let mut monster_actors = Vec::<KbActor>::new();
         monster_actors.push(monster_actor);
 
@@ -337,8 +343,9 @@ impl GameMob {
         GameMob {
             monster_actors,
             collision_handle,
+            controller,
             current_state: GameMobState::Idle,
-            _current_state_time: Instant::now(),
+            _current_state_time: kb_time_now(),
         }
     }
 
@@ -379,21 +386,9 @@ impl GameMob {
         {
             let monster_actor = &mut self.monster_actors[0];
             if dist_to_player > 5.0 {
-                collision_manager.remove_collision(&self.collision_handle); // hack remove self collision temporarily so ray cast doesn't iot.
                 let move_vec = vec_to_player * game_config.delta_time * speed_multiplier;
-                let (t, _, _, blocks) =
-                    collision_manager.cast_ray(&monster_actor.get_position(), &move_vec);
-
-                let block = blocks.unwrap_or(true);
-                if !(0.0..1.0).contains(&t) || !block {
-                    monster_actor.set_position(&(monster_actor.get_position() + move_vec));
-                }
-                let collision_box = KbCollisionShape::AABB(KbCollisionAABB {
-                    position: monster_actor.get_position(),
-                    extents: CgVec3::new(2.0, 2.0, 2.0),
-                    block: true,
-                });
-                self.collision_handle = collision_manager.add_collision(&collision_box);
+                self.controller.move_and_slide(collision_manager, &move_vec);
+                monster_actor.set_position(&self.controller.position);
                 self.current_state = GameMobState::Chasing;
             } else {
                 self.current_state = GameMobState::Attacking;
@@ -427,7 +422,7 @@ pub struct GameProp {
     pub collision_handle: KbCollisionHandle,
     prop_type: GamePropType,
     particle_handles: [KbParticleHandle; 2],
-    _start_time: Instant,
+    _start_time: f64,
 }
 
 impl GameProp {
@@ -439,25 +434,33 @@ impl GameProp {
         collision_manager: &mut KbCollisionManager,
         particle_handles: [KbParticleHandle; 2],
     ) -> Self {
-        let mut coll_pos = *position;
-
-        let extents = {
+        let collision_shape = {
             match prop_type {
-                GamePropType::Shotgun => CgVec3::new(1.5, 1.5, 1.5),
-                GamePropType::Barrel => CgVec3::new(1.1, 4.0, 1.1),
-                GamePropType::Sign => {
-                    coll_pos = CgVec3::new(0.0, 0.0, 0.0);
-                    CgVec3::new(9.5, 10.0, 0.3)
-                }
+                GamePropType::Shotgun => KbCollisionShape::Sphere(KbCollisionSphere {
+                    position: *position,
+                    radius: 1.5,
+                    block: false,
+                    mask: PLAYER_COLLISION_LAYER,
+                    ..Default::default()
+                }),
+                GamePropType::Barrel => KbCollisionShape::AABB(KbCollisionAABB {
+                    position: *position,
+                    extents: CgVec3::new(1.1, 4.0, 1.1),
+                    block: false,
+                    ..Default::default()
+                }),
+                GamePropType::Sign => KbCollisionShape::AABB(KbCollisionAABB {
+                    position: CgVec3::new(0.0, 0.0, 0.0),
+                    extents: CgVec3::new(9.5, 10.0, 0.3),
+                    block: false,
+                    ..Default::default()
+                }),
             }
         };
-
-        let collision_box = KbCollisionShape::AABB(KbCollisionAABB {
-            position: coll_pos,
-            extents,
-            block: false,
-        });
-        let collision_handle = collision_manager.add_collision(&collision_box);
+        let collision_handle = match prop_type {
+            GamePropType::Shotgun => collision_manager.add_trigger(&collision_shape),
+            _ => collision_manager.add_collision(&collision_shape),
+        };
 
         let mut actors = Vec::<KbActor>::new();
         let mut actor = KbActor::new();
@@ -490,7 +493,7 @@ impl GameProp {
             collision_handle,
             prop_type: *prop_type,
             particle_handles,
-            _start_time: Instant::now(),
+            _start_time: kb_time_now(),
         }
     }
 
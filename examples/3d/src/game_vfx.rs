@@ -1,6 +1,7 @@
+use cgmath::InnerSpace;
 use kb_engine3::{
-    kb_assets::*, kb_collision::*, kb_config::*, kb_game_object::*, kb_renderer::*, kb_resource::*,
-    kb_utils::*,
+    kb_assets::*, kb_clock::*, kb_collision::*, kb_config::*, kb_game_object::*, kb_renderer::*,
+    kb_resource::*, kb_utils::*,
 };
 
 use crate::game_actors::*;
@@ -143,7 +144,7 @@ impl GameVfxManager {
 
         // Decals
         self.num_active_decals = 0;
-        let elapsed_time = game_config.start_time.elapsed().as_secs_f32();
+        let elapsed_time = kb_time_since(game_config.start_time);
         self.decals.retain_mut(|d| {
             if elapsed_time > d.start_time + 2.0 {
                 renderer.remove_actor(&d.actor);
@@ -197,8 +198,7 @@ impl GameVfxManager {
             renderer.add_or_update_actor(&decal_actor);
             let decal = GameDecal {
                 actor: decal_actor,
-                start_time: game_config.start_time.elapsed().as_secs_f32()
-                    + kb_random_f32(-0.25, 0.25),
+                start_time: kb_time_since(game_config.start_time) + kb_random_f32(-0.25, 0.25),
             };
             self.decals.push(decal);
         }
@@ -217,17 +217,21 @@ impl GameVfxManager {
             );
             let mut splat_dir = rotation * view_dir * 15.0;
 
-            let (t, _, decal_hit_loc, _) = collision_manager.cast_ray(mob_pos, &splat_dir);
-            if (0.0..1.0).contains(&t) {
+            let ray_hit = collision_manager.cast_ray(
+                mob_pos,
+                &splat_dir,
+                splat_dir.magnitude(),
+                &KbCollisionFilter::default(),
+            );
+            if let Some(hit) = ray_hit {
                 splat_dir.y += kb_random_f32(-decal_position_range.x, decal_position_range.x);
 
                 // The world is a cube that extends from approximately (-20.0, -20.0, -20.0) to (20.0, 20.0, 20.0)
                 let (pos, rotation) = {
-                    let decal_hit_loc = decal_hit_loc.unwrap();
-                    if decal_hit_loc.x.abs() > decal_hit_loc.z.abs() {
+                    if hit.normal.x.abs() > hit.normal.z.abs() {
                         splat_dir.z +=
                             kb_random_f32(-decal_position_range.z, decal_position_range.z);
-                        if decal_hit_loc.x < 0.0 {
+                        if hit.normal.x > 0.0 {
                             (CgVec3::new(-18.0, splat_dir.y, splat_dir.z), 90.0)
                         } else {
                             (CgVec3::new(18.0, splat_dir.y, splat_dir.z), 90.0)
@@ -235,7 +239,7 @@ impl GameVfxManager {
                     } else {
                         splat_dir.x +=
                             kb_random_f32(-decal_position_range.x, decal_position_range.x);
-                        if decal_hit_loc.z < 0.0 {
+                        if hit.normal.z > 0.0 {
                             (CgVec3::new(splat_dir.x, splat_dir.y, -18.0), 180.0)
                         } else {
                             (CgVec3::new(splat_dir.x, splat_dir.y, 18.0), 180.0)
@@ -268,8 +272,7 @@ impl GameVfxManager {
 
                 let decal = GameDecal {
                     actor: decal_actor,
-                    start_time: game_config.start_time.elapsed().as_secs_f32()
-                        + kb_random_f32(-0.25, 0.25),
+                    start_time: kb_time_since(game_config.start_time) + kb_random_f32(-0.25, 0.25),
                 };
                 self.decals.push(decal);
             }
@@ -0,0 +1,102 @@
+use std::{cell::Cell, cell::RefCell, rc::Rc};
+
+// Source of time for the engine.  now() is in seconds and must never go backwards.  Absolute times
+// are f64 so they keep sub-millisecond precision after long sessions.  Deltas fit in an f32
+pub trait KbClock {
+    fn now(&self) -> f64;
+}
+
+// Wall-clock time since the clock was created
+#[derive(Debug)]
+pub struct KbSystemClock {
+    start_time: instant::Instant,
+    time_offset: f64,
+}
+
+impl KbSystemClock {
+    pub fn new() -> Self {
+        Self::starting_at(0.0)
+    }
+
+    // Continues from time instead of 0, for replacing another clock without going backwards
+    pub fn starting_at(time: f64) -> Self {
+        KbSystemClock {
+            start_time: instant::Instant::now(),
+            time_offset: time,
+        }
+    }
+}
+
+impl Default for KbSystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KbClock for KbSystemClock {
+    fn now(&self) -> f64 {
+        self.time_offset + self.start_time.elapsed().as_secs_f64()
+    }
+}
+
+// Only moves when told to.  Lets tests and replays step the engine by exact amounts
+#[derive(Debug)]
+pub struct KbManualClock {
+    time: Cell<f64>,
+}
+
+impl KbManualClock {
+    pub fn new() -> Self {
+        KbManualClock {
+            time: Cell::new(0.0),
+        }
+    }
+
+    pub fn advance(&self, seconds: f64) {
+        assert!(
+            seconds >= 0.0,
+            "KbManualClock::advance() - Time can't go backwards"
+        );
+        self.time.set(self.time.get() + seconds);
+    }
+
+    pub fn set_time(&self, time: f64) {
+        assert!(
+            time >= self.time.get(),
+            "KbManualClock::set_time() - Time can't go backwards"
+        );
+        self.time.set(time);
+    }
+}
+
+impl Default for KbManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KbClock for KbManualClock {
+    fn now(&self) -> f64 {
+        self.time.get()
+    }
+}
+
+thread_local! {
+    static KB_CLOCK: RefCell<Rc<dyn KbClock>> = RefCell::new(Rc::new(KbSystemClock::new()));
+}
+
+// Replaces the clock every engine system reads from.  Call before creating the config and game so
+// their start times come from the new clock
+pub fn kb_set_clock(clock: Rc<dyn KbClock>) {
+    KB_CLOCK.with(|kb_clock| *kb_clock.borrow_mut() = clock);
+}
+
+// Seconds on the engine clock
+pub fn kb_time_now() -> f64 {
+    KB_CLOCK.with(|kb_clock| kb_clock.borrow().now())
+}
+
+// Seconds on the engine clock since start_time, a value previously returned by kb_time_now()
+pub fn kb_time_since(start_time: f64) -> f32 {
+    (kb_time_now() - start_time) as f32
+}
@@ -1,13 +1,46 @@
-use std::collections::HashMap;
+use cgmath::{ElementWise, InnerSpace};
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
+    sync::Arc,
+};
 
-use crate::{kb_config::*, kb_renderer::*, kb_utils::*, log, make_kb_handle};
+use crate::{kb_config::*, kb_game_object::*, kb_renderer::*, kb_utils::*, log, make_kb_handle};
 
 make_kb_handle!(KbCollisionShape, KbCollisionHandle, KbCollisionMappings);
 
+const DEBUG_SPHERE_SEGMENTS: usize = 24;
+const MAX_TRIANGLES_PER_LEAF: usize = 4;
+const DEFAULT_GRID_CELL_SIZE: f32 = 4.0;
+
+// Shapes spanning more cells than this along any axis, like level walls, skip the grid and are
+// tested by every query
+const GRID_MAX_SHAPE_CELLS: i32 = 8;
+
+// Shapes are on one or more layers and only interact with the layers in their mask
+pub const KB_COLLISION_LAYER_DEFAULT: u32 = 1;
+pub const KB_COLLISION_LAYER_ALL: u32 = u32::MAX;
+
 #[derive(Clone, Copy)]
 pub struct KbCollisionSphere {
     pub position: CgVec3,
     pub radius: f32,
+    pub block: bool,
+    pub layer: u32,
+    pub mask: u32,
+    pub user_data: u64,
+}
+
+impl Default for KbCollisionSphere {
+    fn default() -> Self {
+        KbCollisionSphere {
+            position: CG_VEC3_ZERO,
+            radius: 1.0,
+            block: true,
+            layer: KB_COLLISION_LAYER_DEFAULT,
+            mask: KB_COLLISION_LAYER_ALL,
+            user_data: 0,
+        }
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -15,6 +48,22 @@ pub struct KbCollisionAABB {
     pub position: CgVec3,
     pub extents: CgVec3,
     pub block: bool,
+    pub layer: u32,
+    pub mask: u32,
+    pub user_data: u64,
+}
+
+impl Default for KbCollisionAABB {
+    fn default() -> Self {
+        KbCollisionAABB {
+            position: CG_VEC3_ZERO,
+            extents: CG_VEC3_ONE,
+            block: true,
+            layer: KB_COLLISION_LAYER_DEFAULT,
+            mask: KB_COLLISION_LAYER_ALL,
+            user_data: 0,
+        }
+    }
 }
 
 impl KbCollisionAABB {
@@ -27,184 +76,3103 @@ impl KbCollisionAABB {
     }
 }
 
-#[derive(Clone, Copy)]
+// Static triangle soup like level geometry.  Triangles are relative to position, which is the only
+// part that can change after creation
+#[derive(Clone)]
+pub struct KbCollisionMesh {
+    pub position: CgVec3,
+    pub block: bool,
+    pub layer: u32,
+    pub mask: u32,
+    pub user_data: u64,
+    tree: Arc<KbTriangleTree>,
+}
+
+impl KbCollisionMesh {
+    pub fn new(position: &CgVec3, triangles: Vec<[CgVec3; 3]>) -> Self {
+        KbCollisionMesh {
+            position: *position,
+            block: true,
+            layer: KB_COLLISION_LAYER_DEFAULT,
+            mask: KB_COLLISION_LAYER_ALL,
+            user_data: 0,
+            tree: Arc::new(KbTriangleTree::new(triangles)),
+        }
+    }
+
+    // Collision matching actor's model as it's drawn.  mesh_name picks one mesh in the model, like a
+    // simplified collision mesh authored alongside the visual one.  None if the model isn't loaded
+    // or has no mesh with that name
+    pub fn from_actor(
+        renderer: &mut KbRenderer,
+        actor: &KbActor,
+        mesh_name: Option<&str>,
+    ) -> Option<Self> {
+        let rotation = actor.get_rotation();
+        let scale = actor.get_scale();
+        let triangles = renderer
+            .get_model_triangles(&actor.get_model(), mesh_name)?
+            .iter()
+            .map(|triangle| triangle.map(|vertex| rotation * vertex.mul_element_wise(scale)))
+            .collect();
+        Some(Self::new(&actor.get_position(), triangles))
+    }
+
+    // Relative to position
+    pub fn get_triangles(&self) -> &[[CgVec3; 3]] {
+        &self.tree.triangles
+    }
+}
+
+#[derive(Clone)]
 pub enum KbCollisionShape {
     Sphere(KbCollisionSphere),
     AABB(KbCollisionAABB),
+    Mesh(KbCollisionMesh),
 }
 
-pub struct KbCollisionManager {
-    collision_objects: KbCollisionMappings,
+impl KbCollisionShape {
+    pub fn blocks(&self) -> bool {
+        match self {
+            KbCollisionShape::Sphere(sphere) => sphere.block,
+            KbCollisionShape::AABB(aabb) => aabb.block,
+            KbCollisionShape::Mesh(mesh) => mesh.block,
+        }
+    }
+
+    pub fn layer(&self) -> u32 {
+        match self {
+            KbCollisionShape::Sphere(sphere) => sphere.layer,
+            KbCollisionShape::AABB(aabb) => aabb.layer,
+            KbCollisionShape::Mesh(mesh) => mesh.layer,
+        }
+    }
+
+    pub fn mask(&self) -> u32 {
+        match self {
+            KbCollisionShape::Sphere(sphere) => sphere.mask,
+            KbCollisionShape::AABB(aabb) => aabb.mask,
+            KbCollisionShape::Mesh(mesh) => mesh.mask,
+        }
+    }
+
+    pub fn user_data(&self) -> u64 {
+        match self {
+            KbCollisionShape::Sphere(sphere) => sphere.user_data,
+            KbCollisionShape::AABB(aabb) => aabb.user_data,
+            KbCollisionShape::Mesh(mesh) => mesh.user_data,
+        }
+    }
 }
 
-impl Default for KbCollisionManager {
+// Which shapes a ray cast or overlap query can hit.  A shape is hit when its layer is in mask, its
+// own mask contains layer, it isn't in ignore, and it blocks if blocking_only is set
+#[derive(Clone, Debug)]
+pub struct KbCollisionFilter {
+    pub layer: u32,
+    pub mask: u32,
+    pub ignore: Vec<KbCollisionHandle>,
+    pub blocking_only: bool,
+}
+
+impl Default for KbCollisionFilter {
     fn default() -> Self {
-        Self::new()
+        KbCollisionFilter {
+            layer: KB_COLLISION_LAYER_ALL,
+            mask: KB_COLLISION_LAYER_ALL,
+            ignore: Vec::new(),
+            blocking_only: false,
+        }
     }
 }
 
-impl KbCollisionManager {
-    pub fn new() -> Self {
-        log!("Initializing KbCollisionManager...");
-        KbCollisionManager {
-            collision_objects: KbCollisionMappings::new(),
+impl KbCollisionFilter {
+    pub fn new(layer: u32, mask: u32) -> Self {
+        KbCollisionFilter {
+            layer,
+            mask,
+            ..Default::default()
         }
     }
 
-    pub fn add_collision(&mut self, collision: &KbCollisionShape) -> KbCollisionHandle {
-        let mappings = &mut self.collision_objects;
-        let new_handle = {
-            if !mappings.next_handle.is_valid() {
-                mappings.next_handle.index = 0;
+    // Hits everything except handles, e.g. the shape casting the ray
+    pub fn ignoring(handles: &[KbCollisionHandle]) -> Self {
+        KbCollisionFilter {
+            ignore: handles.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    pub fn accepts(&self, handle: &KbCollisionHandle, shape: &KbCollisionShape) -> bool {
+        shape.layer() & self.mask != 0
+            && shape.mask() & self.layer != 0
+            && (!self.blocking_only || shape.blocks())
+            && !self.ignore.contains(handle)
+    }
+}
+
+// Where two shapes overlap.  Moving the first shape by normal * depth separates them
+#[derive(Clone, Copy, Debug)]
+pub struct KbCollisionContact {
+    pub point: CgVec3,
+    pub normal: CgVec3,
+    pub depth: f32,
+}
+
+// Where a ray hits a shape.  distance is along the ray's direction in world units and normal points
+// out of the shape that was hit
+#[derive(Clone, Copy, Debug)]
+pub struct KbRayHit {
+    pub distance: f32,
+    pub point: CgVec3,
+    pub normal: CgVec3,
+    pub handle: KbCollisionHandle,
+    pub block: bool,
+    pub user_data: u64,
+}
+
+// Where a moving shape first touches another.  t is the fraction of the movement made before
+// touching and normal points out of the shape that was hit
+#[derive(Clone, Copy, Debug)]
+pub struct KbSweepHit {
+    pub t: f32,
+    pub point: CgVec3,
+    pub normal: CgVec3,
+    pub handle: KbCollisionHandle,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KbTriggerEventType {
+    Enter,
+    Stay,
+    Exit,
+}
+
+// A shape that started, kept or stopped overlapping a trigger during the last update_triggers().
+// Removing a trigger also counts as everything in it leaving
+#[derive(Clone, Copy, Debug)]
+pub struct KbTriggerEvent {
+    pub event_type: KbTriggerEventType,
+    pub trigger: KbCollisionHandle,
+    pub trigger_user_data: u64,
+    pub other: KbCollisionHandle,
+    pub other_user_data: u64,
+}
+
+// Touching shapes don't overlap, depth is always greater than 0.  Meshes don't overlap other meshes
+pub fn kb_shapes_overlap(
+    shape_a: &KbCollisionShape,
+    shape_b: &KbCollisionShape,
+) -> Option<KbCollisionContact> {
+    match (shape_a, shape_b) {
+        (KbCollisionShape::Sphere(a), KbCollisionShape::Sphere(b)) => sphere_vs_sphere(a, b),
+        (KbCollisionShape::Sphere(a), KbCollisionShape::AABB(b)) => sphere_vs_aabb(a, b),
+        (KbCollisionShape::AABB(a), KbCollisionShape::Sphere(b)) => {
+            let contact = sphere_vs_aabb(b, a)?;
+            Some(KbCollisionContact {
+                normal: -contact.normal,
+                ..contact
+            })
+        }
+        (KbCollisionShape::AABB(a), KbCollisionShape::AABB(b)) => aabb_vs_aabb(a, b),
+        (KbCollisionShape::Sphere(a), KbCollisionShape::Mesh(b)) => sphere_vs_mesh(a, b),
+        (KbCollisionShape::AABB(a), KbCollisionShape::Mesh(b)) => aabb_vs_mesh(a, b),
+        (KbCollisionShape::Mesh(_), KbCollisionShape::Mesh(_)) => None,
+        (KbCollisionShape::Mesh(_), _) => {
+            let contact = kb_shapes_overlap(shape_b, shape_a)?;
+            Some(KbCollisionContact {
+                normal: -contact.normal,
+                ..contact
+            })
+        }
+    }
+}
+
+// Meshes are surfaces without an inside, so points are never in them
+pub fn kb_point_in_shape(point: &CgVec3, shape: &KbCollisionShape) -> bool {
+    match shape {
+        KbCollisionShape::Sphere(sphere) => {
+            (point - sphere.position).magnitude2() <= sphere.radius * sphere.radius
+        }
+        KbCollisionShape::AABB(aabb) => {
+            let (min, max) = (aabb.min(), aabb.max());
+            point.x >= min.x
+                && point.x <= max.x
+                && point.y >= min.y
+                && point.y <= max.y
+                && point.z >= min.z
+                && point.z <= max.z
+        }
+        KbCollisionShape::Mesh(_) => false,
+    }
+}
+
+fn sphere_vs_sphere(a: &KbCollisionSphere, b: &KbCollisionSphere) -> Option<KbCollisionContact> {
+    let b_to_a = a.position - b.position;
+    let distance = b_to_a.magnitude();
+    let depth = a.radius + b.radius - distance;
+    if depth <= 0.0 {
+        return None;
+    }
+
+    // Concentric spheres get pushed up
+    let normal = if distance > f32::EPSILON {
+        b_to_a / distance
+    } else {
+        CG_VEC3_UP
+    };
+    Some(KbCollisionContact {
+        point: a.position - normal * (a.radius - depth * 0.5),
+        normal,
+        depth,
+    })
+}
+
+fn sphere_vs_aabb(
+    sphere: &KbCollisionSphere,
+    aabb: &KbCollisionAABB,
+) -> Option<KbCollisionContact> {
+    let (min, max) = (aabb.min(), aabb.max());
+    let center = sphere.position;
+    let closest_point = CgVec3::new(
+        center.x.clamp(min.x, max.x),
+        center.y.clamp(min.y, max.y),
+        center.z.clamp(min.z, max.z),
+    );
+
+    let box_to_center = center - closest_point;
+    let distance_sq = box_to_center.magnitude2();
+    if distance_sq > f32::EPSILON {
+        if distance_sq >= sphere.radius * sphere.radius {
+            return None;
+        }
+
+        let distance = distance_sq.sqrt();
+        return Some(KbCollisionContact {
+            point: closest_point,
+            normal: box_to_center / distance,
+            depth: sphere.radius - distance,
+        });
+    }
+
+    // The center is inside the box, so push out through the nearest face
+    let offset = center - aabb.position;
+    let face_distances = [
+        aabb.extents.x - offset.x.abs(),
+        aabb.extents.y - offset.y.abs(),
+        aabb.extents.z - offset.z.abs(),
+    ];
+    let axis = nearest_axis(&face_distances);
+    let mut normal = CG_VEC3_ZERO;
+    normal[axis] = if offset[axis] < 0.0 { -1.0 } else { 1.0 };
+
+    let mut point = center;
+    point[axis] = aabb.position[axis] + normal[axis] * aabb.extents[axis];
+    Some(KbCollisionContact {
+        point,
+        normal,
+        depth: sphere.radius + face_distances[axis],
+    })
+}
+
+fn aabb_vs_aabb(a: &KbCollisionAABB, b: &KbCollisionAABB) -> Option<KbCollisionContact> {
+    let offset = a.position - b.position;
+    let overlaps = [
+        a.extents.x + b.extents.x - offset.x.abs(),
+        a.extents.y + b.extents.y - offset.y.abs(),
+        a.extents.z + b.extents.z - offset.z.abs(),
+    ];
+    if overlaps.iter().any(|overlap| *overlap <= 0.0) {
+        return None;
+    }
+
+    // Separate along the axis that needs the smallest push
+    let axis = nearest_axis(&overlaps);
+    let mut normal = CG_VEC3_ZERO;
+    normal[axis] = if offset[axis] < 0.0 { -1.0 } else { 1.0 };
+
+    let min = CgVec3::new(
+        a.min().x.max(b.min().x),
+        a.min().y.max(b.min().y),
+        a.min().z.max(b.min().z),
+    );
+    let max = CgVec3::new(
+        a.max().x.min(b.max().x),
+        a.max().y.min(b.max().y),
+        a.max().z.min(b.max().z),
+    );
+    Some(KbCollisionContact {
+        point: (min + max) * 0.5,
+        normal,
+        depth: overlaps[axis],
+    })
+}
+
+fn nearest_axis(distances: &[f32; 3]) -> usize {
+    if distances[0] <= distances[1] && distances[0] <= distances[2] {
+        0
+    } else if distances[1] <= distances[2] {
+        1
+    } else {
+        2
+    }
+}
+
+// Distance along dir to where the ray enters shape, in multiples of dir, and the shape's normal
+// there.  Rays starting inside a shape don't hit it
+fn ray_vs_shape(start: &CgVec3, dir: &CgVec3, shape: &KbCollisionShape) -> Option<(f32, CgVec3)> {
+    let (t, normal) = match shape {
+        KbCollisionShape::Sphere(sphere) => {
+            let t = ray_enters_sphere(start, dir, &sphere.position, sphere.radius)?;
+            (t, (start + dir * t - sphere.position) / sphere.radius)
+        }
+        KbCollisionShape::AABB(aabb) => {
+            let (t, axis) = ray_enters_box(start, dir, &aabb.min(), &aabb.max())?;
+            let mut normal = CG_VEC3_ZERO;
+            normal[axis] = if dir[axis] > 0.0 { -1.0 } else { 1.0 };
+            (t, normal)
+        }
+        KbCollisionShape::Mesh(mesh) => {
+            let local_start = start - mesh.position;
+            let (t, triangle) = mesh.tree.cast_ray(&local_start, dir)?;
+            (t, triangle_normal_facing(&triangle, &local_start)?)
+        }
+    };
+    if t > 0.0 {
+        Some((t, normal))
+    } else {
+        None
+    }
+}
+
+// Where the line start + dir * t enters the sphere.  Negative when the line enters behind start
+fn ray_enters_sphere(start: &CgVec3, dir: &CgVec3, center: &CgVec3, radius: f32) -> Option<f32> {
+    // Solve |start + dir * t - center| = radius for the nearest t
+    let to_start = start - center;
+    let a = dir.dot(*dir);
+    let b = 2.0 * dir.dot(to_start);
+    let c = to_start.dot(to_start) - radius * radius;
+    let discriminant = b * b - 4.0 * a * c;
+    if a <= 0.0 || discriminant < 0.0 {
+        return None;
+    }
+    Some((-b - discriminant.sqrt()) / (2.0 * a))
+}
+
+// Where the line start + dir * t enters the box, and the axis of the face it enters through.
+// Negative when the line enters behind start
+fn ray_enters_box(
+    start: &CgVec3,
+    dir: &CgVec3,
+    min: &CgVec3,
+    max: &CgVec3,
+) -> Option<(f32, usize)> {
+    let mut t_enter = f32::MIN;
+    let mut t_exit = f32::MAX;
+    let mut enter_axis = 0;
+    for axis in 0..3 {
+        if dir[axis] == 0.0 {
+            if start[axis] < min[axis] || start[axis] > max[axis] {
+                return None;
             }
-            let new_handle = mappings.next_handle;
-            mappings.next_handle.index += 1;
-            new_handle
-        };
-        self.collision_objects
-            .handles_to_assets
-            .insert(new_handle, *collision);
-        new_handle
+            continue;
+        }
+
+        let t_0 = (min[axis] - start[axis]) / dir[axis];
+        let t_1 = (max[axis] - start[axis]) / dir[axis];
+        if t_0.min(t_1) > t_enter {
+            t_enter = t_0.min(t_1);
+            enter_axis = axis;
+        }
+        t_exit = t_exit.min(t_0.max(t_1));
     }
 
-    pub fn remove_collision(&mut self, handle: &KbCollisionHandle) {
-        self.collision_objects.handles_to_assets.remove(handle);
+    if t_enter <= t_exit && t_exit >= 0.0 {
+        Some((t_enter, enter_axis))
+    } else {
+        None
     }
+}
 
-    pub fn get_collision(&self, handle: &KbCollisionHandle) -> KbCollisionShape {
-        *self
-            .collision_objects
-            .handles_to_assets
-            .get(handle)
-            .unwrap()
+// Where the ray enters the capsule around the segment from a to b.  Rays starting inside don't hit
+fn ray_enters_capsule(
+    start: &CgVec3,
+    dir: &CgVec3,
+    a: &CgVec3,
+    b: &CgVec3,
+    radius: f32,
+) -> Option<f32> {
+    let axis = b - a;
+    let axis_length_sq = axis.magnitude2();
+    let a_to_start = start - a;
+
+    // Side of the cylinder, using only the parts of the ray perpendicular to the axis
+    let mut closest = None;
+    let dir_perp = dir - axis * (dir.dot(axis) / axis_length_sq);
+    let start_perp = a_to_start - axis * (a_to_start.dot(axis) / axis_length_sq);
+    let qa = dir_perp.magnitude2();
+    let qb = 2.0 * dir_perp.dot(start_perp);
+    let qc = start_perp.magnitude2() - radius * radius;
+    let discriminant = qb * qb - 4.0 * qa * qc;
+    if qa > f32::EPSILON && discriminant >= 0.0 {
+        let t = (-qb - discriminant.sqrt()) / (2.0 * qa);
+        let along_axis = (a_to_start + dir * t).dot(axis) / axis_length_sq;
+        if t >= 0.0 && (0.0..=1.0).contains(&along_axis) {
+            closest = Some(t);
+        }
     }
 
-    pub fn update_collision_position(&mut self, handle: &KbCollisionHandle, new_pos: &CgVec3) {
-        let collision = self
-            .collision_objects
-            .handles_to_assets
-            .get_mut(handle)
-            .expect("Bad collision handle");
+    // End caps
+    for center in [a, b] {
+        if let Some(t) = ray_enters_sphere(start, dir, center, radius) {
+            if t >= 0.0 && closest.is_none_or(|closest| t < closest) {
+                closest = Some(t);
+            }
+        }
+    }
+    closest
+}
 
-        let new_collision = match collision {
-            KbCollisionShape::Sphere(s) => KbCollisionShape::Sphere(KbCollisionSphere {
-                position: *new_pos,
-                radius: s.radius,
-            }),
-            KbCollisionShape::AABB(b) => KbCollisionShape::AABB(KbCollisionAABB {
-                position: *new_pos,
-                extents: b.extents,
-                block: b.block,
-            }),
-        };
+fn closest_point_in_box(point: &CgVec3, min: &CgVec3, max: &CgVec3) -> CgVec3 {
+    CgVec3::new(
+        point.x.clamp(min.x, max.x),
+        point.y.clamp(min.y, max.y),
+        point.z.clamp(min.z, max.z),
+    )
+}
 
-        self.collision_objects
-            .handles_to_assets
-            .insert(*handle, new_collision);
+// Fraction of movement before the sphere touches the box, the box's normal there and the point of
+// contact
+fn sweep_sphere_vs_aabb(
+    sphere: &KbCollisionSphere,
+    movement: &CgVec3,
+    aabb: &KbCollisionAABB,
+) -> Option<(f32, CgVec3, CgVec3)> {
+    // The sphere's center hits the box grown by the radius, rounded at the edges and corners.  Test
+    // the square box first, then the rounded parts if that hit an edge or corner
+    let (min, max) = (aabb.min(), aabb.max());
+    let radius = sphere.radius;
+    let grown = CgVec3::new(radius, radius, radius);
+    let (t, axis) = ray_enters_box(&sphere.position, movement, &(min - grown), &(max + grown))?;
+    if t > 1.0 {
+        return None;
     }
 
-    pub fn cast_ray(
-        &mut self,
-        start: &CgVec3,
-        dir: &CgVec3,
-    ) -> (f32, Option<KbCollisionHandle>, Option<CgVec3>, Option<bool>) {
-        let mut closest_hit = f32::MAX;
-        let mut closest_handle = KbCollisionHandle::make_invalid();
-        let mut blocks = None;
-
-        for (handle, value) in &mut self.collision_objects.handles_to_assets {
-            match value {
-                KbCollisionShape::Sphere(_s) => {}
-
-                KbCollisionShape::AABB(aabb) => {
-                    let mut t_min = aabb.min() - start;
-                    t_min.x /= dir.x;
-                    t_min.y /= dir.y;
-                    t_min.z /= dir.z;
-
-                    let mut t_max = aabb.max() - start;
-                    t_max.x /= dir.x;
-                    t_max.y /= dir.y;
-                    t_max.z /= dir.z;
-
-                    let mut actual_min = CG_VEC3_ZERO;
-                    let mut actual_max = CG_VEC3_ZERO;
-                    actual_min.x = t_min.x.min(t_max.x);
-                    actual_max.x = t_min.x.max(t_max.x);
-                    actual_min.y = t_min.y.min(t_max.y);
-                    actual_max.y = t_min.y.max(t_max.y);
-                    actual_min.z = t_min.z.min(t_max.z);
-                    actual_max.z = t_min.z.max(t_max.z);
-
-                    let smallest_max = actual_max.x.min(actual_max.y).min(actual_max.z);
-                    let largest_min = actual_min.x.max(actual_min.y).max(actual_min.z);
-
-                    if largest_min > 0.0 && smallest_max >= largest_min && largest_min < closest_hit
-                    {
-                        closest_hit = largest_min;
-                        closest_handle = *handle;
-                        blocks = Some(aabb.block);
-                    }
+    let t = t.max(0.0);
+    let center = sphere.position + movement * t;
+    let below = [center.x < min.x, center.y < min.y, center.z < min.z];
+    let above = [center.x > max.x, center.y > max.y, center.z > max.z];
+    let outside_axes: Vec<usize> = (0..3).filter(|axis| below[*axis] || above[*axis]).collect();
+
+    let t = if outside_axes.len() <= 1 {
+        t
+    } else {
+        let corner = CgVec3::new(
+            if below[0] { min.x } else { max.x },
+            if below[1] { min.y } else { max.y },
+            if below[2] { min.z } else { max.z },
+        );
+
+        // Edges leaving the nearest corner along axes the center is outside of
+        let mut closest = None;
+        for edge_axis in 0..3 {
+            if outside_axes.len() == 2 && outside_axes.contains(&edge_axis) {
+                continue;
+            }
+            let mut edge_start = corner;
+            let mut edge_end = corner;
+            edge_start[edge_axis] = min[edge_axis];
+            edge_end[edge_axis] = max[edge_axis];
+            if let Some(t) =
+                ray_enters_capsule(&sphere.position, movement, &edge_start, &edge_end, radius)
+            {
+                if closest.is_none_or(|closest| t < closest) {
+                    closest = Some(t);
                 }
             }
         }
+        match closest {
+            Some(t) if t <= 1.0 => t,
+            _ => return None,
+        }
+    };
 
-        let hit_loc = {
-            if closest_handle.is_valid() {
-                Some(start + dir * closest_hit)
-            } else {
-                None
+    let center = sphere.position + movement * t;
+    let point = closest_point_in_box(&center, &min, &max);
+    let normal = if (center - point).magnitude2() > f32::EPSILON {
+        (center - point).normalize()
+    } else {
+        let mut normal = CG_VEC3_ZERO;
+        normal[axis] = if movement[axis] > 0.0 { -1.0 } else { 1.0 };
+        normal
+    };
+    Some((t, normal, point))
+}
+
+// Fraction of movement before moving_shape touches other_shape, other_shape's normal there and the
+// point of contact.  Shapes that already overlap or touch only hit if movement pushes them together.
+// Meshes check this for each triangle instead
+fn sweep_vs_shape(
+    moving_shape: &KbCollisionShape,
+    movement: &CgVec3,
+    other_shape: &KbCollisionShape,
+) -> Option<(f32, CgVec3, CgVec3)> {
+    let overlap = match other_shape {
+        KbCollisionShape::Mesh(_) => None,
+        _ => kb_shapes_overlap(moving_shape, other_shape),
+    };
+    if let Some(contact) = overlap {
+        if movement.dot(contact.normal) < 0.0 {
+            return Some((0.0, contact.normal, contact.point));
+        }
+        return None;
+    }
+
+    let (t, normal, point) = sweep_vs_separate_shape(moving_shape, movement, other_shape)?;
+    if movement.dot(normal) < 0.0 {
+        Some((t, normal, point))
+    } else {
+        None
+    }
+}
+
+fn sweep_vs_separate_shape(
+    moving_shape: &KbCollisionShape,
+    movement: &CgVec3,
+    other_shape: &KbCollisionShape,
+) -> Option<(f32, CgVec3, CgVec3)> {
+    match (moving_shape, other_shape) {
+        (KbCollisionShape::Sphere(a), KbCollisionShape::Sphere(b)) => {
+            let t = ray_enters_sphere(&a.position, movement, &b.position, a.radius + b.radius)?;
+            if !(0.0..=1.0).contains(&t) {
+                return None;
+            }
+            let normal = (a.position + movement * t - b.position).normalize();
+            Some((t, normal, b.position + normal * b.radius))
+        }
+
+        (KbCollisionShape::Sphere(a), KbCollisionShape::AABB(b)) => {
+            sweep_sphere_vs_aabb(a, movement, b)
+        }
+
+        (KbCollisionShape::AABB(a), KbCollisionShape::Sphere(b)) => {
+            // Same as the sphere moving the other way into the box
+            let (t, normal, point) = sweep_sphere_vs_aabb(b, &-*movement, a)?;
+            Some((t, -normal, point + movement * t))
+        }
+
+        (KbCollisionShape::AABB(a), KbCollisionShape::AABB(b)) => {
+            let grown_min = b.min() - a.extents;
+            let grown_max = b.max() + a.extents;
+            let (t, axis) = ray_enters_box(&a.position, movement, &grown_min, &grown_max)?;
+            if !(0.0..=1.0).contains(&t) {
+                return None;
+            }
+
+            let mut normal = CG_VEC3_ZERO;
+            normal[axis] = if movement[axis] > 0.0 { -1.0 } else { 1.0 };
+
+            // Middle of the touching faces
+            let moved_position = a.position + movement * t;
+            let min = CgVec3::new(
+                (moved_position.x - a.extents.x).max(b.min().x),
+                (moved_position.y - a.extents.y).max(b.min().y),
+                (moved_position.z - a.extents.z).max(b.min().z),
+            );
+            let max = CgVec3::new(
+                (moved_position.x + a.extents.x).min(b.max().x),
+                (moved_position.y + a.extents.y).min(b.max().y),
+                (moved_position.z + a.extents.z).min(b.max().z),
+            );
+            Some((t, normal, (min + max) * 0.5))
+        }
+
+        (KbCollisionShape::Sphere(a), KbCollisionShape::Mesh(b)) => {
+            sweep_sphere_vs_mesh(a, movement, b)
+        }
+
+        (KbCollisionShape::AABB(a), KbCollisionShape::Mesh(b)) => {
+            sweep_aabb_vs_mesh(a, movement, b)
+        }
+
+        // Meshes are static
+        (KbCollisionShape::Mesh(_), _) => None,
+    }
+}
+
+struct KbTriangleNode {
+    min: CgVec3,
+    max: CgVec3,
+
+    // Leaves hold the count triangles starting at first.  Other nodes have a count of 0 and their
+    // children are at first and first + 1
+    first: usize,
+    count: usize,
+}
+
+// Bounding volume hierarchy over a mesh's triangles
+struct KbTriangleTree {
+    triangles: Vec<[CgVec3; 3]>,
+    nodes: Vec<KbTriangleNode>,
+}
+
+impl KbTriangleTree {
+    fn new(mut triangles: Vec<[CgVec3; 3]>) -> Self {
+        let mut nodes = Vec::new();
+        if !triangles.is_empty() {
+            let num_triangles = triangles.len();
+            nodes.push(KbTriangleNode {
+                min: CG_VEC3_ZERO,
+                max: CG_VEC3_ZERO,
+                first: 0,
+                count: 0,
+            });
+            Self::build_node(&mut triangles, &mut nodes, 0, 0, num_triangles);
+        }
+        KbTriangleTree { triangles, nodes }
+    }
+
+    // Splits the triangles at their median along the node's longest axis until the leaves are small
+    fn build_node(
+        triangles: &mut [[CgVec3; 3]],
+        nodes: &mut Vec<KbTriangleNode>,
+        node_index: usize,
+        first: usize,
+        count: usize,
+    ) {
+        let node_triangles = &mut triangles[first..first + count];
+        let mut min = CgVec3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = CgVec3::new(f32::MIN, f32::MIN, f32::MIN);
+        for vertex in node_triangles.iter().flatten() {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(vertex[axis]);
+                max[axis] = max[axis].max(vertex[axis]);
             }
+        }
+        nodes[node_index].min = min;
+        nodes[node_index].max = max;
+
+        if count <= MAX_TRIANGLES_PER_LEAF {
+            nodes[node_index].first = first;
+            nodes[node_index].count = count;
+            return;
+        }
+
+        let size = max - min;
+        let axis = if size.x >= size.y && size.x >= size.z {
+            0
+        } else if size.y >= size.z {
+            1
+        } else {
+            2
         };
-        (closest_hit, Some(closest_handle), hit_loc, blocks)
+        let half = count / 2;
+        node_triangles.select_nth_unstable_by(half, |a, b| {
+            let a_center = a[0][axis] + a[1][axis] + a[2][axis];
+            let b_center = b[0][axis] + b[1][axis] + b[2][axis];
+            a_center.total_cmp(&b_center)
+        });
+
+        let children = nodes.len();
+        for _ in 0..2 {
+            nodes.push(KbTriangleNode {
+                min: CG_VEC3_ZERO,
+                max: CG_VEC3_ZERO,
+                first: 0,
+                count: 0,
+            });
+        }
+        nodes[node_index].first = children;
+        Self::build_node(triangles, nodes, children, first, half);
+        Self::build_node(triangles, nodes, children + 1, first + half, count - half);
     }
 
-    pub fn num_collision_objects(&self) -> usize {
-        self.collision_objects.handles_to_assets.len()
+    // Calls visit with the triangles in every leaf touching the box from min to max
+    fn query_bounds(&self, min: &CgVec3, max: &CgVec3, mut visit: impl FnMut(&[CgVec3; 3])) {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let mut stack = vec![0];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if (0..3).any(|axis| node.min[axis] > max[axis] || node.max[axis] < min[axis]) {
+                continue;
+            }
+
+            if node.count > 0 {
+                for triangle in &self.triangles[node.first..node.first + node.count] {
+                    visit(triangle);
+                }
+            } else {
+                stack.push(node.first);
+                stack.push(node.first + 1);
+            }
+        }
     }
 
-    pub fn debug_draw(&mut self, renderer: &mut KbRenderer, config: &KbConfig) {
-        for value in &mut self.collision_objects.handles_to_assets.values_mut() {
-            match value {
-                KbCollisionShape::Sphere(_s) => {}
-
-                KbCollisionShape::AABB(aabb) => {
-                    let extent_0 = aabb.position
-                        + CgVec3::new(-aabb.extents.x, aabb.extents.y, aabb.extents.z);
-                    let extent_1 =
-                        aabb.position + CgVec3::new(aabb.extents.x, aabb.extents.y, aabb.extents.z);
-                    let extent_2 = aabb.position
-                        + CgVec3::new(aabb.extents.x, -aabb.extents.y, aabb.extents.z);
-                    let extent_3 = aabb.position
-                        + CgVec3::new(-aabb.extents.x, -aabb.extents.y, aabb.extents.z);
-
-                    let extent_4 = aabb.position
-                        + CgVec3::new(-aabb.extents.x, aabb.extents.y, -aabb.extents.z);
-                    let extent_5 = aabb.position
-                        + CgVec3::new(aabb.extents.x, aabb.extents.y, -aabb.extents.z);
-                    let extent_6 = aabb.position
-                        + CgVec3::new(aabb.extents.x, -aabb.extents.y, -aabb.extents.z);
-                    let extent_7 = aabb.position
-                        + CgVec3::new(-aabb.extents.x, -aabb.extents.y, -aabb.extents.z);
-
-                    let color = CgVec4::new(1.0, 1.0, 0.0, 1.0);
-
-                    renderer.add_line(&extent_0, &extent_1, &color, 0.05, 0.001, config);
-                    renderer.add_line(&extent_1, &extent_2, &color, 0.05, 0.001, config);
-                    renderer.add_line(&extent_2, &extent_3, &color, 0.05, 0.001, config);
-                    renderer.add_line(&extent_3, &extent_0, &color, 0.05, 0.001, config);
-
-                    renderer.add_line(&extent_4, &extent_5, &color, 0.05, 0.001, config);
-                    renderer.add_line(&extent_5, &extent_6, &color, 0.05, 0.001, config);
-                    renderer.add_line(&extent_6, &extent_7, &color, 0.05, 0.001, config);
-                    renderer.add_line(&extent_7, &extent_4, &color, 0.05, 0.001, config);
-
-                    renderer.add_line(&extent_0, &extent_4, &color, 0.05, 0.001, config);
-                    renderer.add_line(&extent_1, &extent_5, &color, 0.05, 0.001, config);
-                    renderer.add_line(&extent_2, &extent_6, &color, 0.05, 0.001, config);
-                    renderer.add_line(&extent_3, &extent_7, &color, 0.05, 0.001, config);
+    // Nearest triangle in front of start along dir, in multiples of dir
+    fn cast_ray(&self, start: &CgVec3, dir: &CgVec3) -> Option<(f32, [CgVec3; 3])> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let mut closest: Option<(f32, [CgVec3; 3])> = None;
+        let mut stack = vec![0];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            match ray_enters_box(start, dir, &node.min, &node.max) {
+                Some((t, _)) if closest.is_none_or(|(closest_t, _)| t < closest_t) => {}
+                _ => continue,
+            }
+
+            if node.count > 0 {
+                for triangle in &self.triangles[node.first..node.first + node.count] {
+                    if let Some(t) = ray_vs_triangle(start, dir, triangle) {
+                        if t > 0.0 && closest.is_none_or(|(closest_t, _)| t < closest_t) {
+                            closest = Some((t, *triangle));
+                        }
+                    }
                 }
+            } else {
+                stack.push(node.first);
+                stack.push(node.first + 1);
+            }
+        }
+        closest
+    }
+}
+
+// Where the line start + dir * t crosses the triangle, from either side
+fn ray_vs_triangle(start: &CgVec3, dir: &CgVec3, triangle: &[CgVec3; 3]) -> Option<f32> {
+    let edge_0 = triangle[1] - triangle[0];
+    let edge_1 = triangle[2] - triangle[0];
+    let p = dir.cross(edge_1);
+    let determinant = edge_0.dot(p);
+    if determinant.abs() <= f32::EPSILON {
+        return None;
+    }
+
+    let to_start = start - triangle[0];
+    let u = to_start.dot(p) / determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = to_start.cross(edge_0);
+    let v = dir.dot(q) / determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    Some(edge_1.dot(q) / determinant)
+}
+
+// Unit normal on the same side as point, or None for a degenerate triangle
+fn triangle_normal_facing(triangle: &[CgVec3; 3], point: &CgVec3) -> Option<CgVec3> {
+    let normal = (triangle[1] - triangle[0]).cross(triangle[2] - triangle[0]);
+    if normal.magnitude2() <= f32::EPSILON * f32::EPSILON {
+        return None;
+    }
+    let normal = normal.normalize();
+    if normal.dot(point - triangle[0]) < 0.0 {
+        Some(-normal)
+    } else {
+        Some(normal)
+    }
+}
+
+fn closest_point_on_triangle(point: &CgVec3, triangle: &[CgVec3; 3]) -> CgVec3 {
+    // Find which vertex, edge or the face is nearest from the barycentric coordinates
+    let [a, b, c] = *triangle;
+    let ab = b - a;
+    let ac = c - a;
+    let ap = point - a;
+    let d_1 = ab.dot(ap);
+    let d_2 = ac.dot(ap);
+    if d_1 <= 0.0 && d_2 <= 0.0 {
+        return a;
+    }
+
+    let bp = point - b;
+    let d_3 = ab.dot(bp);
+    let d_4 = ac.dot(bp);
+    if d_3 >= 0.0 && d_4 <= d_3 {
+        return b;
+    }
+
+    let vc = d_1 * d_4 - d_3 * d_2;
+    if vc <= 0.0 && d_1 >= 0.0 && d_3 <= 0.0 {
+        return a + ab * (d_1 / (d_1 - d_3));
+    }
+
+    let cp = point - c;
+    let d_5 = ab.dot(cp);
+    let d_6 = ac.dot(cp);
+    if d_6 >= 0.0 && d_5 <= d_6 {
+        return c;
+    }
+
+    let vb = d_5 * d_2 - d_1 * d_6;
+    if vb <= 0.0 && d_2 >= 0.0 && d_6 <= 0.0 {
+        return a + ac * (d_2 / (d_2 - d_6));
+    }
+
+    let va = d_3 * d_6 - d_5 * d_4;
+    if va <= 0.0 && (d_4 - d_3) >= 0.0 && (d_5 - d_6) >= 0.0 {
+        return b + (c - b) * ((d_4 - d_3) / ((d_4 - d_3) + (d_5 - d_6)));
+    }
+
+    let denominator = 1.0 / (va + vb + vc);
+    a + ab * (vb * denominator) + ac * (vc * denominator)
+}
+
+// Axes that can separate a box from a triangle: the box's axes, the triangle's normal and the cross
+// products of the box's axes with the triangle's edges.  Parallel edges give no axis
+fn box_triangle_axes(triangle: &[CgVec3; 3]) -> [Option<CgVec3>; 13] {
+    let box_axes = [
+        CgVec3::new(1.0, 0.0, 0.0),
+        CgVec3::new(0.0, 1.0, 0.0),
+        CgVec3::new(0.0, 0.0, 1.0),
+    ];
+    let edges = [
+        triangle[1] - triangle[0],
+        triangle[2] - triangle[1],
+        triangle[0] - triangle[2],
+    ];
+    let normalized =
+        |axis: CgVec3| (axis.magnitude2() > f32::EPSILON * f32::EPSILON).then(|| axis.normalize());
+
+    let mut axes = [None; 13];
+    axes[3] = normalized(edges[0].cross(edges[1]));
+    for (i, box_axis) in box_axes.iter().enumerate() {
+        axes[i] = Some(*box_axis);
+        for (j, edge) in edges.iter().enumerate() {
+            axes[4 + i * 3 + j] = normalized(box_axis.cross(*edge));
+        }
+    }
+    axes
+}
+
+fn project_box(center: &CgVec3, extents: &CgVec3, axis: &CgVec3) -> (f32, f32) {
+    let center = center.dot(*axis);
+    let radius = extents.x * axis.x.abs() + extents.y * axis.y.abs() + extents.z * axis.z.abs();
+    (center - radius, center + radius)
+}
+
+fn project_triangle(triangle: &[CgVec3; 3], axis: &CgVec3) -> (f32, f32) {
+    let d_0 = triangle[0].dot(*axis);
+    let d_1 = triangle[1].dot(*axis);
+    let d_2 = triangle[2].dot(*axis);
+    (d_0.min(d_1).min(d_2), d_0.max(d_1).max(d_2))
+}
+
+// Depth and direction of the smallest push that moves the box off the triangle
+fn aabb_vs_triangle(
+    center: &CgVec3,
+    extents: &CgVec3,
+    triangle: &[CgVec3; 3],
+) -> Option<(f32, CgVec3)> {
+    let mut closest: Option<(f32, CgVec3)> = None;
+    for axis in box_triangle_axes(triangle).into_iter().flatten() {
+        let (box_min, box_max) = project_box(center, extents, &axis);
+        let (triangle_min, triangle_max) = project_triangle(triangle, &axis);
+        let push_back = box_max - triangle_min;
+        let push_forward = triangle_max - box_min;
+        let depth = push_back.min(push_forward);
+        if depth <= 0.0 {
+            return None;
+        }
+        if closest.is_none_or(|(closest_depth, _)| depth < closest_depth) {
+            let normal = if push_back < push_forward {
+                -axis
+            } else {
+                axis
+            };
+            closest = Some((depth, normal));
+        }
+    }
+    closest
+}
+
+// Fraction of movement before the box touches the triangle and the normal pointing from the
+// triangle to the box there.  Boxes already touching it only hit if movement pushes them together
+fn sweep_aabb_vs_triangle(
+    center: &CgVec3,
+    extents: &CgVec3,
+    movement: &CgVec3,
+    triangle: &[CgVec3; 3],
+) -> Option<(f32, CgVec3)> {
+    if let Some((_, normal)) = aabb_vs_triangle(center, extents, triangle) {
+        return (movement.dot(normal) < 0.0).then_some((0.0, normal));
+    }
+
+    // The shapes touch once they overlap along every axis
+    let mut t_enter = f32::MIN;
+    let mut t_exit = f32::MAX;
+    let mut normal = CG_VEC3_ZERO;
+    for axis in box_triangle_axes(triangle).into_iter().flatten() {
+        let (box_min, box_max) = project_box(center, extents, &axis);
+        let (triangle_min, triangle_max) = project_triangle(triangle, &axis);
+        let speed = movement.dot(axis);
+        if speed.abs() <= f32::EPSILON {
+            if box_max <= triangle_min || box_min >= triangle_max {
+                return None;
             }
+            continue;
         }
+
+        let t_0 = (triangle_min - box_max) / speed;
+        let t_1 = (triangle_max - box_min) / speed;
+        if t_0.min(t_1) > t_enter {
+            t_enter = t_0.min(t_1);
+            normal = if speed > 0.0 { -axis } else { axis };
+        }
+        t_exit = t_exit.min(t_0.max(t_1));
+    }
+
+    if !(0.0..=1.0).contains(&t_enter) || t_enter > t_exit {
+        return None;
+    }
+    Some((t_enter, normal))
+}
+
+// Fraction of movement before the sphere touches the triangle, from either side, and the normal
+// pointing from the triangle to the sphere there.  Spheres already touching it only hit if movement
+// pushes them together
+fn sweep_sphere_vs_triangle(
+    sphere: &KbCollisionSphere,
+    movement: &CgVec3,
+    triangle: &[CgVec3; 3],
+) -> Option<(f32, CgVec3)> {
+    let center = sphere.position;
+    let radius = sphere.radius;
+    let face_normal = triangle_normal_facing(triangle, &center)?;
+
+    // Already touching, which only hits if movement pushes them together
+    let to_center = center - closest_point_on_triangle(&center, triangle);
+    if to_center.magnitude2() < radius * radius {
+        let normal = if to_center.magnitude2() > f32::EPSILON {
+            to_center.normalize()
+        } else {
+            face_normal
+        };
+        return (movement.dot(normal) < 0.0).then_some((0.0, normal));
+    }
+
+    // The face, if the sphere's nearest point lands inside the triangle
+    let distance = face_normal.dot(center - triangle[0]);
+    let speed = face_normal.dot(*movement);
+    if speed < 0.0 && distance >= radius {
+        let t = (distance - radius) / -speed;
+        if t <= 1.0 {
+            let touch_point = center + movement * t - face_normal * radius;
+            let on_face = closest_point_on_triangle(&touch_point, triangle);
+            if (on_face - touch_point).magnitude2() <= 1e-8 {
+                return Some((t, face_normal));
+            }
+        }
+    }
+
+    // Otherwise an edge or a corner, which are the capsules around each edge
+    let mut closest: Option<f32> = None;
+    for i in 0..3 {
+        let edge_start = triangle[i];
+        let edge_end = triangle[(i + 1) % 3];
+        if let Some(t) = ray_enters_capsule(&center, movement, &edge_start, &edge_end, radius) {
+            if closest.is_none_or(|closest| t < closest) {
+                closest = Some(t);
+            }
+        }
+    }
+    let t = closest.filter(|t| (0.0..=1.0).contains(t))?;
+    let moved_center = center + movement * t;
+    let to_center = moved_center - closest_point_on_triangle(&moved_center, triangle);
+    if to_center.magnitude2() <= f32::EPSILON {
+        return Some((t, face_normal));
+    }
+    Some((t, to_center.normalize()))
+}
+
+fn sphere_vs_mesh(
+    sphere: &KbCollisionSphere,
+    mesh: &KbCollisionMesh,
+) -> Option<KbCollisionContact> {
+    let center = sphere.position - mesh.position;
+    let grown = CgVec3::new(sphere.radius, sphere.radius, sphere.radius);
+    let mut deepest: Option<KbCollisionContact> = None;
+    mesh.tree
+        .query_bounds(&(center - grown), &(center + grown), |triangle| {
+            let closest_point = closest_point_on_triangle(&center, triangle);
+            let to_center = center - closest_point;
+            let distance_sq = to_center.magnitude2();
+            if distance_sq >= sphere.radius * sphere.radius {
+                return;
+            }
+
+            let distance = distance_sq.sqrt();
+            let normal = if distance > f32::EPSILON {
+                to_center / distance
+            } else {
+                match triangle_normal_facing(triangle, &center) {
+                    Some(normal) => normal,
+                    None => return,
+                }
+            };
+            let depth = sphere.radius - distance;
+            if deepest.is_none_or(|deepest| depth > deepest.depth) {
+                deepest = Some(KbCollisionContact {
+                    point: closest_point + mesh.position,
+                    normal,
+                    depth,
+                });
+            }
+        });
+    deepest
+}
+
+fn aabb_vs_mesh(aabb: &KbCollisionAABB, mesh: &KbCollisionMesh) -> Option<KbCollisionContact> {
+    let center = aabb.position - mesh.position;
+    let min = center - aabb.extents;
+    let max = center + aabb.extents;
+    let mut deepest: Option<KbCollisionContact> = None;
+    mesh.tree.query_bounds(&min, &max, |triangle| {
+        if let Some((depth, normal)) = aabb_vs_triangle(&center, &aabb.extents, triangle) {
+            if deepest.is_none_or(|deepest| depth > deepest.depth) {
+                let closest_point = closest_point_on_triangle(&center, triangle);
+                deepest = Some(KbCollisionContact {
+                    point: closest_point_in_box(&closest_point, &min, &max) + mesh.position,
+                    normal,
+                    depth,
+                });
+            }
+        }
+    });
+    deepest
+}
+
+fn sweep_sphere_vs_mesh(
+    sphere: &KbCollisionSphere,
+    movement: &CgVec3,
+    mesh: &KbCollisionMesh,
+) -> Option<(f32, CgVec3, CgVec3)> {
+    let local_sphere = KbCollisionSphere {
+        position: sphere.position - mesh.position,
+        ..*sphere
+    };
+    let (min, max) = get_swept_bounds(&KbCollisionShape::Sphere(local_sphere), movement);
+    let mut closest: Option<(f32, CgVec3)> = None;
+    mesh.tree.query_bounds(&min, &max, |triangle| {
+        if let Some((t, normal)) = sweep_sphere_vs_triangle(&local_sphere, movement, triangle) {
+            if closest.is_none_or(|(closest_t, _)| t < closest_t) {
+                closest = Some((t, normal));
+            }
+        }
+    });
+
+    let (t, normal) = closest?;
+    let point = sphere.position + movement * t - normal * sphere.radius;
+    Some((t, normal, point))
+}
+
+fn sweep_aabb_vs_mesh(
+    aabb: &KbCollisionAABB,
+    movement: &CgVec3,
+    mesh: &KbCollisionMesh,
+) -> Option<(f32, CgVec3, CgVec3)> {
+    let local_aabb = KbCollisionAABB {
+        position: aabb.position - mesh.position,
+        ..*aabb
+    };
+    let (min, max) = get_swept_bounds(&KbCollisionShape::AABB(local_aabb), movement);
+    let mut closest: Option<(f32, CgVec3, [CgVec3; 3])> = None;
+    mesh.tree.query_bounds(&min, &max, |triangle| {
+        if let Some((t, normal)) =
+            sweep_aabb_vs_triangle(&local_aabb.position, &aabb.extents, movement, triangle)
+        {
+            if closest.is_none_or(|(closest_t, _, _)| t < closest_t) {
+                closest = Some((t, normal, *triangle));
+            }
+        }
+    });
+
+    // The triangle's nearest point, clamped onto the moved box
+    let (t, normal, triangle) = closest?;
+    let moved_center = local_aabb.position + movement * t;
+    let closest_point = closest_point_on_triangle(&moved_center, &triangle);
+    let point = closest_point_in_box(
+        &closest_point,
+        &(moved_center - aabb.extents),
+        &(moved_center + aabb.extents),
+    );
+    Some((t, normal, point + mesh.position))
+}
+
+// dir is normalized, so t is the hit's distance
+fn make_ray_hit(
+    start: &CgVec3,
+    dir: &CgVec3,
+    (t, normal): (f32, CgVec3),
+    handle: &KbCollisionHandle,
+    shape: &KbCollisionShape,
+) -> KbRayHit {
+    KbRayHit {
+        distance: t,
+        point: start + dir * t,
+        normal,
+        handle: *handle,
+        block: shape.blocks(),
+        user_data: shape.user_data(),
+    }
+}
+
+// Replaces closest with any nearer hit within max_distance.  A shape in several grid cells may be
+// tested more than once, which is cheaper than tracking them
+fn find_closest_ray_hit(
+    start: &CgVec3,
+    dir: &CgVec3,
+    max_distance: f32,
+    handles: &[KbCollisionHandle],
+    handles_to_assets: &HashMap<KbCollisionHandle, KbCollisionShape>,
+    filter: &KbCollisionFilter,
+    closest: &mut Option<KbRayHit>,
+) {
+    for handle in handles {
+        let shape = &handles_to_assets[handle];
+        if !filter.accepts(handle, shape) {
+            continue;
+        }
+        match ray_vs_shape(start, dir, shape) {
+            Some(hit) if hit.0 <= closest.map_or(max_distance, |closest| closest.distance) => {
+                *closest = Some(make_ray_hit(start, dir, hit, handle, shape));
+            }
+            _ => {}
+        }
+    }
+}
+
+fn move_shape_to(shape: &KbCollisionShape, new_pos: &CgVec3) -> KbCollisionShape {
+    match shape {
+        KbCollisionShape::Sphere(s) => KbCollisionShape::Sphere(KbCollisionSphere {
+            position: *new_pos,
+            ..*s
+        }),
+        KbCollisionShape::AABB(b) => KbCollisionShape::AABB(KbCollisionAABB {
+            position: *new_pos,
+            ..*b
+        }),
+        KbCollisionShape::Mesh(m) => KbCollisionShape::Mesh(KbCollisionMesh {
+            position: *new_pos,
+            ..m.clone()
+        }),
+    }
+}
+
+fn get_shape_bounds(shape: &KbCollisionShape) -> (CgVec3, CgVec3) {
+    match shape {
+        KbCollisionShape::Sphere(sphere) => {
+            let extents = CgVec3::new(sphere.radius, sphere.radius, sphere.radius);
+            (sphere.position - extents, sphere.position + extents)
+        }
+        KbCollisionShape::AABB(aabb) => (aabb.min(), aabb.max()),
+        KbCollisionShape::Mesh(mesh) => match mesh.tree.nodes.first() {
+            Some(root) => (root.min + mesh.position, root.max + mesh.position),
+            None => (mesh.position, mesh.position),
+        },
+    }
+}
+
+// Bounds of everywhere shape passes through while moving by movement
+fn get_swept_bounds(shape: &KbCollisionShape, movement: &CgVec3) -> (CgVec3, CgVec3) {
+    let (start_min, start_max) = get_shape_bounds(shape);
+    let swept_min = start_min
+        + CgVec3::new(
+            movement.x.min(0.0),
+            movement.y.min(0.0),
+            movement.z.min(0.0),
+        );
+    let swept_max = start_max
+        + CgVec3::new(
+            movement.x.max(0.0),
+            movement.y.max(0.0),
+            movement.z.max(0.0),
+        );
+    (swept_min, swept_max)
+}
+
+type KbGridCell = (i32, i32, i32);
+
+// Uniform grid broadphase.  Each shape is listed in every cell its bounds touch
+struct KbCollisionGrid {
+    cell_size: f32,
+    cells: HashMap<KbGridCell, Vec<KbCollisionHandle>>,
+    shape_cells: HashMap<KbCollisionHandle, (KbGridCell, KbGridCell)>,
+    large_shapes: Vec<KbCollisionHandle>,
+
+    // Number of occupied cells at each coordinate along x, y and z.  The bounds come from these so
+    // they shrink again as shapes are removed
+    axis_cell_counts: [BTreeMap<i32, u32>; 3],
+
+    // Every occupied cell is inside these.  Ray casts stop when they leave them
+    bounds_min: KbGridCell,
+    bounds_max: KbGridCell,
+}
+
+impl KbCollisionGrid {
+    fn new(cell_size: f32) -> Self {
+        KbCollisionGrid {
+            cell_size,
+            cells: HashMap::new(),
+            shape_cells: HashMap::new(),
+            large_shapes: Vec::new(),
+            axis_cell_counts: Default::default(),
+            bounds_min: (i32::MAX, i32::MAX, i32::MAX),
+            bounds_max: (i32::MIN, i32::MIN, i32::MIN),
+        }
+    }
+
+    fn get_cell(&self, position: &CgVec3) -> KbGridCell {
+        (
+            (position.x / self.cell_size).floor() as i32,
+            (position.y / self.cell_size).floor() as i32,
+            (position.z / self.cell_size).floor() as i32,
+        )
+    }
+
+    fn insert(&mut self, handle: KbCollisionHandle, shape: &KbCollisionShape) {
+        let (min, max) = get_shape_bounds(shape);
+        let (min_cell, max_cell) = (self.get_cell(&min), self.get_cell(&max));
+        if max_cell.0 - min_cell.0 >= GRID_MAX_SHAPE_CELLS
+            || max_cell.1 - min_cell.1 >= GRID_MAX_SHAPE_CELLS
+            || max_cell.2 - min_cell.2 >= GRID_MAX_SHAPE_CELLS
+        {
+            self.large_shapes.push(handle);
+            return;
+        }
+
+        for x in min_cell.0..=max_cell.0 {
+            for y in min_cell.1..=max_cell.1 {
+                for z in min_cell.2..=max_cell.2 {
+                    match self.cells.entry((x, y, z)) {
+                        Entry::Occupied(mut cell) => cell.get_mut().push(handle),
+                        Entry::Vacant(cell) => {
+                            cell.insert(vec![handle]);
+                            self.count_cell(&(x, y, z), true);
+                        }
+                    }
+                }
+            }
+        }
+        self.shape_cells.insert(handle, (min_cell, max_cell));
+        self.update_bounds();
+    }
+
+    fn remove(&mut self, handle: &KbCollisionHandle) {
+        let (min_cell, max_cell) = match self.shape_cells.remove(handle) {
+            Some(cell_range) => cell_range,
+            None => {
+                self.large_shapes.retain(|h| h != handle);
+                return;
+            }
+        };
+
+        for x in min_cell.0..=max_cell.0 {
+            for y in min_cell.1..=max_cell.1 {
+                for z in min_cell.2..=max_cell.2 {
+                    if let Some(cell) = self.cells.get_mut(&(x, y, z)) {
+                        cell.retain(|h| h != handle);
+                        if cell.is_empty() {
+                            self.cells.remove(&(x, y, z));
+                            self.count_cell(&(x, y, z), false);
+                        }
+                    }
+                }
+            }
+        }
+        self.update_bounds();
+    }
+
+    // Keeps axis_cell_counts up to date as cells are first used or emptied
+    fn count_cell(&mut self, cell: &KbGridCell, added: bool) {
+        for (axis, coord) in [cell.0, cell.1, cell.2].into_iter().enumerate() {
+            let count = self.axis_cell_counts[axis].entry(coord).or_insert(0);
+            if added {
+                *count += 1;
+            } else {
+                *count -= 1;
+                if *count == 0 {
+                    self.axis_cell_counts[axis].remove(&coord);
+                }
+            }
+        }
+    }
+
+    fn update_bounds(&mut self) {
+        let [x_counts, y_counts, z_counts] = &self.axis_cell_counts;
+        let first = |counts: &BTreeMap<i32, u32>| *counts.keys().next().unwrap_or(&i32::MAX);
+        let last = |counts: &BTreeMap<i32, u32>| *counts.keys().next_back().unwrap_or(&i32::MIN);
+        self.bounds_min = (first(x_counts), first(y_counts), first(z_counts));
+        self.bounds_max = (last(x_counts), last(y_counts), last(z_counts));
+    }
+
+    // Shapes whose cells touch the box from min to max, each listed once
+    fn query_bounds(&self, min: &CgVec3, max: &CgVec3) -> Vec<KbCollisionHandle> {
+        let mut handles = self.large_shapes.clone();
+        let min_cell = self.get_cell(min);
+        let max_cell = self.get_cell(max);
+        let min_cell = (
+            min_cell.0.max(self.bounds_min.0),
+            min_cell.1.max(self.bounds_min.1),
+            min_cell.2.max(self.bounds_min.2),
+        );
+        let max_cell = (
+            max_cell.0.min(self.bounds_max.0),
+            max_cell.1.min(self.bounds_max.1),
+            max_cell.2.min(self.bounds_max.2),
+        );
+        if min_cell.0 > max_cell.0 || min_cell.1 > max_cell.1 || min_cell.2 > max_cell.2 {
+            return handles;
+        }
+
+        let num_cells = (max_cell.0 - min_cell.0 + 1) as usize
+            * (max_cell.1 - min_cell.1 + 1) as usize
+            * (max_cell.2 - min_cell.2 + 1) as usize;
+        if num_cells > self.cells.len() {
+            // Cheaper to check every occupied cell than every cell in the range
+            for (cell, cell_handles) in &self.cells {
+                if (min_cell.0..=max_cell.0).contains(&cell.0)
+                    && (min_cell.1..=max_cell.1).contains(&cell.1)
+                    && (min_cell.2..=max_cell.2).contains(&cell.2)
+                {
+                    handles.extend(cell_handles);
+                }
+            }
+        } else {
+            for x in min_cell.0..=max_cell.0 {
+                for y in min_cell.1..=max_cell.1 {
+                    for z in min_cell.2..=max_cell.2 {
+                        if let Some(cell_handles) = self.cells.get(&(x, y, z)) {
+                            handles.extend(cell_handles);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut seen = HashSet::new();
+        handles.retain(|handle| seen.insert(*handle));
+        handles
+    }
+
+    // Walks the cells along the ray in order, calling visit_cell with each cell's shapes and the
+    // ray distance where the cell starts.  Stops when visit_cell returns false
+    fn walk_ray(
+        &self,
+        start: &CgVec3,
+        dir: &CgVec3,
+        mut visit_cell: impl FnMut(&[KbCollisionHandle], f32) -> bool,
+    ) {
+        if self.cells.is_empty() {
+            return;
+        }
+
+        // Clip the ray to the occupied part of the grid
+        let bounds_min = CgVec3::new(
+            self.bounds_min.0 as f32,
+            self.bounds_min.1 as f32,
+            self.bounds_min.2 as f32,
+        ) * self.cell_size;
+        let bounds_max = CgVec3::new(
+            (self.bounds_max.0 + 1) as f32,
+            (self.bounds_max.1 + 1) as f32,
+            (self.bounds_max.2 + 1) as f32,
+        ) * self.cell_size;
+        let mut t_enter = 0.0_f32;
+        let mut t_exit = f32::MAX;
+        for axis in 0..3 {
+            if dir[axis] == 0.0 {
+                if start[axis] < bounds_min[axis] || start[axis] > bounds_max[axis] {
+                    return;
+                }
+                continue;
+            }
+            let t_0 = (bounds_min[axis] - start[axis]) / dir[axis];
+            let t_1 = (bounds_max[axis] - start[axis]) / dir[axis];
+            t_enter = t_enter.max(t_0.min(t_1));
+            t_exit = t_exit.min(t_0.max(t_1));
+        }
+        if t_enter > t_exit {
+            return;
+        }
+
+        let entry_point = start + dir * t_enter;
+        let entry_cell = self.get_cell(&entry_point);
+        let mut cell = [
+            entry_cell.0.clamp(self.bounds_min.0, self.bounds_max.0),
+            entry_cell.1.clamp(self.bounds_min.1, self.bounds_max.1),
+            entry_cell.2.clamp(self.bounds_min.2, self.bounds_max.2),
+        ];
+        let cell_min = [self.bounds_min.0, self.bounds_min.1, self.bounds_min.2];
+        let cell_max = [self.bounds_max.0, self.bounds_max.1, self.bounds_max.2];
+
+        // Ray distance to the next cell boundary on each axis, and between boundaries
+        let mut step = [0; 3];
+        let mut t_next = [f32::MAX; 3];
+        let mut t_delta = [f32::MAX; 3];
+        for axis in 0..3 {
+            if dir[axis] > 0.0 {
+                step[axis] = 1;
+                let boundary = (cell[axis] + 1) as f32 * self.cell_size;
+                t_next[axis] = (boundary - start[axis]) / dir[axis];
+                t_delta[axis] = self.cell_size / dir[axis];
+            } else if dir[axis] < 0.0 {
+                step[axis] = -1;
+                let boundary = cell[axis] as f32 * self.cell_size;
+                t_next[axis] = (boundary - start[axis]) / dir[axis];
+                t_delta[axis] = -self.cell_size / dir[axis];
+            }
+        }
+
+        let mut t_cell = t_enter;
+        loop {
+            let cell_handles = match self.cells.get(&(cell[0], cell[1], cell[2])) {
+                Some(cell_handles) => cell_handles.as_slice(),
+                None => &[],
+            };
+            if !visit_cell(cell_handles, t_cell) {
+                return;
+            }
+
+            let axis = if t_next[0] <= t_next[1] && t_next[0] <= t_next[2] {
+                0
+            } else if t_next[1] <= t_next[2] {
+                1
+            } else {
+                2
+            };
+            if t_next[axis] > t_exit {
+                return;
+            }
+            cell[axis] += step[axis];
+            if cell[axis] < cell_min[axis] || cell[axis] > cell_max[axis] {
+                return;
+            }
+            t_cell = t_next[axis];
+            t_next[axis] += t_delta[axis];
+        }
+    }
+}
+
+// A shape that reports what overlaps it instead of taking part in queries
+struct KbCollisionTrigger {
+    shape: KbCollisionShape,
+
+    // Shapes overlapping it as of the last update and their user data, which is kept for their
+    // exit events in case they've been removed
+    overlaps: HashMap<KbCollisionHandle, u64>,
+}
+
+pub struct KbCollisionManager {
+    collision_objects: KbCollisionMappings,
+    grid: KbCollisionGrid,
+    triggers: HashMap<KbCollisionHandle, KbCollisionTrigger>,
+    trigger_events: Vec<KbTriggerEvent>,
+}
+
+impl Default for KbCollisionManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KbCollisionManager {
+    pub fn new() -> Self {
+        log!("Initializing KbCollisionManager...");
+        KbCollisionManager {
+            collision_objects: KbCollisionMappings::new(),
+            grid: KbCollisionGrid::new(DEFAULT_GRID_CELL_SIZE),
+            triggers: HashMap::new(),
+            trigger_events: Vec::new(),
+        }
+    }
+
+    // Size of the broadphase grid's cells.  Roughly the size of the typical shape works best
+    pub fn set_grid_cell_size(&mut self, cell_size: f32) {
+        assert!(
+            cell_size > 0.0,
+            "KbCollisionManager::set_grid_cell_size() - cell_size must be greater than 0"
+        );
+
+        self.grid = KbCollisionGrid::new(cell_size);
+        for (handle, shape) in &self.collision_objects.handles_to_assets {
+            self.grid.insert(*handle, shape);
+        }
+    }
+
+    // Triggers and other shapes share handles, so each handle is only used once
+    fn next_handle(&mut self) -> KbCollisionHandle {
+        let mappings = &mut self.collision_objects;
+        if !mappings.next_handle.is_valid() {
+            mappings.next_handle.index = 0;
+        }
+        let new_handle = mappings.next_handle;
+        mappings.next_handle.index += 1;
+        new_handle
+    }
+
+    pub fn add_collision(&mut self, collision: &KbCollisionShape) -> KbCollisionHandle {
+        let new_handle = self.next_handle();
+        self.collision_objects
+            .handles_to_assets
+            .insert(new_handle, collision.clone());
+        self.grid.insert(new_handle, collision);
+        new_handle
+    }
+
+    // Triggers don't block anything or show up in queries.  Instead update_triggers() reports the
+    // shapes on the layers in the trigger's mask that enter, stay in and leave it
+    pub fn add_trigger(&mut self, trigger: &KbCollisionShape) -> KbCollisionHandle {
+        let new_handle = self.next_handle();
+        self.triggers.insert(
+            new_handle,
+            KbCollisionTrigger {
+                shape: trigger.clone(),
+                overlaps: HashMap::new(),
+            },
+        );
+        new_handle
+    }
+
+    // Removes shapes and triggers alike
+    pub fn remove_collision(&mut self, handle: &KbCollisionHandle) {
+        if self
+            .collision_objects
+            .handles_to_assets
+            .remove(handle)
+            .is_some()
+        {
+            self.grid.remove(handle);
+        }
+
+        // Everything inside a removed trigger leaves it
+        if let Some(trigger) = self.triggers.remove(handle) {
+            let trigger_user_data = trigger.shape.user_data();
+            let mut exits: Vec<(KbCollisionHandle, u64)> = trigger.overlaps.into_iter().collect();
+            exits.sort_by_key(|(handle, _)| handle.index);
+            for (other, other_user_data) in exits {
+                self.trigger_events.push(KbTriggerEvent {
+                    event_type: KbTriggerEventType::Exit,
+                    trigger: *handle,
+                    trigger_user_data,
+                    other,
+                    other_user_data,
+                });
+            }
+        }
+    }
+
+    pub fn get_collision(&self, handle: &KbCollisionHandle) -> KbCollisionShape {
+        if let Some(trigger) = self.triggers.get(handle) {
+            return trigger.shape.clone();
+        }
+
+        self.collision_objects
+            .handles_to_assets
+            .get(handle)
+            .unwrap()
+            .clone()
+    }
+
+    pub fn update_collision_position(&mut self, handle: &KbCollisionHandle, new_pos: &CgVec3) {
+        if let Some(trigger) = self.triggers.get_mut(handle) {
+            trigger.shape = move_shape_to(&trigger.shape, new_pos);
+            return;
+        }
+
+        let collision = self
+            .collision_objects
+            .handles_to_assets
+            .get(handle)
+            .expect("Bad collision handle");
+        let new_collision = move_shape_to(collision, new_pos);
+
+        self.grid.remove(handle);
+        self.grid.insert(*handle, &new_collision);
+        self.collision_objects
+            .handles_to_assets
+            .insert(*handle, new_collision);
+    }
+
+    // Nearest shape that filter accepts within max_distance of start.  dir doesn't need to be
+    // normalized
+    pub fn cast_ray(
+        &self,
+        start: &CgVec3,
+        dir: &CgVec3,
+        max_distance: f32,
+        filter: &KbCollisionFilter,
+    ) -> Option<KbRayHit> {
+        if dir.magnitude2() <= f32::EPSILON {
+            return None;
+        }
+
+        let dir = dir.normalize();
+        let handles_to_assets = &self.collision_objects.handles_to_assets;
+        let mut closest: Option<KbRayHit> = None;
+        find_closest_ray_hit(
+            start,
+            &dir,
+            max_distance,
+            &self.grid.large_shapes,
+            handles_to_assets,
+            filter,
+            &mut closest,
+        );
+        self.grid.walk_ray(start, &dir, |cell_handles, t_cell| {
+            // Anything in this cell or beyond is farther than the closest hit
+            if t_cell > closest.map_or(max_distance, |closest| closest.distance) {
+                return false;
+            }
+            find_closest_ray_hit(
+                start,
+                &dir,
+                max_distance,
+                cell_handles,
+                handles_to_assets,
+                filter,
+                &mut closest,
+            );
+            true
+        });
+        closest
+    }
+
+    // Every shape that filter accepts within max_distance of start, nearest first.  Each shape is
+    // only hit once, where the ray enters it
+    pub fn cast_ray_all(
+        &self,
+        start: &CgVec3,
+        dir: &CgVec3,
+        max_distance: f32,
+        filter: &KbCollisionFilter,
+    ) -> Vec<KbRayHit> {
+        let mut hits = Vec::new();
+        if dir.magnitude2() <= f32::EPSILON {
+            return hits;
+        }
+
+        let dir = dir.normalize();
+        let handles_to_assets = &self.collision_objects.handles_to_assets;
+        let mut tested_handles = HashSet::new();
+        let mut test_handles = |handles: &[KbCollisionHandle]| {
+            for handle in handles {
+                if !tested_handles.insert(*handle) {
+                    continue;
+                }
+                let shape = &handles_to_assets[handle];
+                if !filter.accepts(handle, shape) {
+                    continue;
+                }
+                match ray_vs_shape(start, &dir, shape) {
+                    Some(hit) if hit.0 <= max_distance => {
+                        hits.push(make_ray_hit(start, &dir, hit, handle, shape));
+                    }
+                    _ => {}
+                }
+            }
+        };
+        test_handles(&self.grid.large_shapes);
+        self.grid.walk_ray(start, &dir, |cell_handles, t_cell| {
+            if t_cell > max_distance {
+                return false;
+            }
+            test_handles(cell_handles);
+            true
+        });
+
+        hits.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        hits
+    }
+
+    // Every shape overlapping shape that filter accepts, in the order they were added
+    pub fn overlap_shape(
+        &self,
+        shape: &KbCollisionShape,
+        filter: &KbCollisionFilter,
+    ) -> Vec<(KbCollisionHandle, KbCollisionContact)> {
+        let (min, max) = get_shape_bounds(shape);
+        let handles_to_assets = &self.collision_objects.handles_to_assets;
+        let mut overlaps: Vec<(KbCollisionHandle, KbCollisionContact)> = self
+            .grid
+            .query_bounds(&min, &max)
+            .into_iter()
+            .filter_map(|handle| {
+                let other_shape = &handles_to_assets[&handle];
+                if !filter.accepts(&handle, other_shape) {
+                    return None;
+                }
+                let contact = kb_shapes_overlap(shape, other_shape)?;
+                Some((handle, contact))
+            })
+            .collect();
+        overlaps.sort_by_key(|(handle, _)| handle.index);
+        overlaps
+    }
+
+    // Every shape containing point that filter accepts, in the order they were added
+    pub fn overlap_point(
+        &self,
+        point: &CgVec3,
+        filter: &KbCollisionFilter,
+    ) -> Vec<KbCollisionHandle> {
+        let handles_to_assets = &self.collision_objects.handles_to_assets;
+        let mut handles: Vec<KbCollisionHandle> = self
+            .grid
+            .query_bounds(point, point)
+            .into_iter()
+            .filter(|handle| {
+                let shape = &handles_to_assets[handle];
+                filter.accepts(handle, shape) && kb_point_in_shape(point, shape)
+            })
+            .collect();
+        handles.sort_by_key(|handle| handle.index);
+        handles
+    }
+
+    // The first shape that filter accepts which shape touches while moving by movement
+    pub fn sweep_shape(
+        &self,
+        shape: &KbCollisionShape,
+        movement: &CgVec3,
+        filter: &KbCollisionFilter,
+    ) -> Option<KbSweepHit> {
+        if movement.magnitude2() <= f32::EPSILON {
+            return None;
+        }
+
+        let (swept_min, swept_max) = get_swept_bounds(shape, movement);
+
+        let handles_to_assets = &self.collision_objects.handles_to_assets;
+        let mut closest: Option<KbSweepHit> = None;
+        for handle in self.grid.query_bounds(&swept_min, &swept_max) {
+            let other_shape = &handles_to_assets[&handle];
+            if !filter.accepts(&handle, other_shape) {
+                continue;
+            }
+            if let Some((t, normal, point)) = sweep_vs_shape(shape, movement, other_shape) {
+                // Ties go to the oldest shape so results don't depend on HashMap order
+                let is_closer = closest.as_ref().is_none_or(|closest| {
+                    t < closest.t || (t == closest.t && handle.index < closest.handle.index)
+                });
+                if is_closer {
+                    closest = Some(KbSweepHit {
+                        t,
+                        point,
+                        normal,
+                        handle,
+                    });
+                }
+            }
+        }
+        closest
+    }
+
+    // Compares what overlaps each trigger with the last call and queues an event for every shape
+    // that entered, stayed or left.  Call it once a tick after moving things, then drain the events
+    pub fn update_triggers(&mut self) {
+        // Trigger and handle order keeps the events in the same order from run to run
+        let mut trigger_handles: Vec<KbCollisionHandle> = self.triggers.keys().copied().collect();
+        trigger_handles.sort_by_key(|handle| handle.index);
+
+        for trigger_handle in trigger_handles {
+            let trigger = &self.triggers[&trigger_handle];
+            let trigger_user_data = trigger.shape.user_data();
+            let filter = KbCollisionFilter::new(trigger.shape.layer(), trigger.shape.mask());
+            let overlaps: Vec<(KbCollisionHandle, u64)> = self
+                .overlap_shape(&trigger.shape, &filter)
+                .into_iter()
+                .map(|(handle, _)| {
+                    let shape = &self.collision_objects.handles_to_assets[&handle];
+                    (handle, shape.user_data())
+                })
+                .collect();
+
+            let mut push_event =
+                |event_type, (other, other_user_data): (KbCollisionHandle, u64)| {
+                    self.trigger_events.push(KbTriggerEvent {
+                        event_type,
+                        trigger: trigger_handle,
+                        trigger_user_data,
+                        other,
+                        other_user_data,
+                    })
+                };
+            for overlap in &overlaps {
+                if trigger.overlaps.contains_key(&overlap.0) {
+                    push_event(KbTriggerEventType::Stay, *overlap);
+                } else {
+                    push_event(KbTriggerEventType::Enter, *overlap);
+                }
+            }
+
+            let mut exits: Vec<(KbCollisionHandle, u64)> = trigger
+                .overlaps
+                .iter()
+                .filter(|(handle, _)| !overlaps.iter().any(|overlap| overlap.0 == **handle))
+                .map(|(handle, user_data)| (*handle, *user_data))
+                .collect();
+            exits.sort_by_key(|(handle, _)| handle.index);
+            for exit in exits {
+                push_event(KbTriggerEventType::Exit, exit);
+            }
+
+            self.triggers.get_mut(&trigger_handle).unwrap().overlaps =
+                overlaps.into_iter().collect();
+        }
+    }
+
+    // The events queued by update_triggers() and remove_collision() since the last drain, oldest
+    // first
+    pub fn drain_trigger_events(&mut self) -> Vec<KbTriggerEvent> {
+        std::mem::take(&mut self.trigger_events)
+    }
+
+    pub fn num_collision_objects(&self) -> usize {
+        self.collision_objects.handles_to_assets.len()
+    }
+
+    // Shapes are yellow and triggers are green
+    pub fn debug_draw(&mut self, renderer: &mut KbRenderer, config: &KbConfig) {
+        let shape_color = CgVec4::new(1.0, 1.0, 0.0, 1.0);
+        for shape in self.collision_objects.handles_to_assets.values() {
+            debug_draw_shape(shape, &shape_color, renderer, config);
+        }
+
+        let trigger_color = CgVec4::new(0.0, 1.0, 0.0, 1.0);
+        for trigger in self.triggers.values() {
+            debug_draw_shape(&trigger.shape, &trigger_color, renderer, config);
+        }
+    }
+}
+
+fn debug_draw_shape(
+    shape: &KbCollisionShape,
+    color: &CgVec4,
+    renderer: &mut KbRenderer,
+    config: &KbConfig,
+) {
+    match shape {
+        KbCollisionShape::Sphere(sphere) => {
+            // One circle around each axis
+            let axes = [
+                (CgVec3::new(1.0, 0.0, 0.0), CgVec3::new(0.0, 1.0, 0.0)),
+                (CgVec3::new(1.0, 0.0, 0.0), CgVec3::new(0.0, 0.0, 1.0)),
+                (CgVec3::new(0.0, 1.0, 0.0), CgVec3::new(0.0, 0.0, 1.0)),
+            ];
+            for (axis_0, axis_1) in axes {
+                let point_on_circle = |segment: usize| {
+                    let angle =
+                        segment as f32 * std::f32::consts::TAU / DEBUG_SPHERE_SEGMENTS as f32;
+                    sphere.position + (axis_0 * angle.cos() + axis_1 * angle.sin()) * sphere.radius
+                };
+                for segment in 0..DEBUG_SPHERE_SEGMENTS {
+                    let start = point_on_circle(segment);
+                    let end = point_on_circle(segment + 1);
+                    renderer.add_line(&start, &end, color, 0.05, 0.001, config);
+                }
+            }
+        }
+
+        KbCollisionShape::AABB(aabb) => {
+            let extent_0 =
+                aabb.position + CgVec3::new(-aabb.extents.x, aabb.extents.y, aabb.extents.z);
+            let extent_1 =
+                aabb.position + CgVec3::new(aabb.extents.x, aabb.extents.y, aabb.extents.z);
+            let extent_2 =
+                aabb.position + CgVec3::new(aabb.extents.x, -aabb.extents.y, aabb.extents.z);
+            let extent_3 =
+                aabb.position + CgVec3::new(-aabb.extents.x, -aabb.extents.y, aabb.extents.z);
+
+            let extent_4 =
+                aabb.position + CgVec3::new(-aabb.extents.x, aabb.extents.y, -aabb.extents.z);
+            let extent_5 =
+                aabb.position + CgVec3::new(aabb.extents.x, aabb.extents.y, -aabb.extents.z);
+            let extent_6 =
+                aabb.position + CgVec3::new(aabb.extents.x, -aabb.extents.y, -aabb.extents.z);
+            let extent_7 =
+                aabb.position + CgVec3::new(-aabb.extents.x, -aabb.extents.y, -aabb.extents.z);
+
+            renderer.add_line(&extent_0, &extent_1, color, 0.05, 0.001, config);
+            renderer.add_line(&extent_1, &extent_2, color, 0.05, 0.001, config);
+            renderer.add_line(&extent_2, &extent_3, color, 0.05, 0.001, config);
+            renderer.add_line(&extent_3, &extent_0, color, 0.05, 0.001, config);
+
+            renderer.add_line(&extent_4, &extent_5, color, 0.05, 0.001, config);
+            renderer.add_line(&extent_5, &extent_6, color, 0.05, 0.001, config);
+            renderer.add_line(&extent_6, &extent_7, color, 0.05, 0.001, config);
+            renderer.add_line(&extent_7, &extent_4, color, 0.05, 0.001, config);
+
+            renderer.add_line(&extent_0, &extent_4, color, 0.05, 0.001, config);
+            renderer.add_line(&extent_1, &extent_5, color, 0.05, 0.001, config);
+            renderer.add_line(&extent_2, &extent_6, color, 0.05, 0.001, config);
+            renderer.add_line(&extent_3, &extent_7, color, 0.05, 0.001, config);
+        }
+
+        KbCollisionShape::Mesh(mesh) => {
+            for triangle in mesh.get_triangles() {
+                for i in 0..3 {
+                    let start = mesh.position + triangle[i];
+                    let end = mesh.position + triangle[(i + 1) % 3];
+                    renderer.add_line(&start, &end, color, 0.05, 0.001, config);
+                }
+            }
+        }
+    }
+}
+
+const MAX_SLIDE_ITERATIONS: usize = 4;
+const MAX_DEPENETRATION_ITERATIONS: usize = 4;
+
+// Moves a sphere or upright capsule through the collision world.  It slides along walls, steps up
+// ledges no taller than step_height and tracks the ground it's standing on.  The character isn't
+// added to the KbCollisionManager, so games that want others to hit it add a shape of their own and
+// put it in filter's ignore list
+#[derive(Clone, Debug)]
+pub struct KbCharacterController {
+    // Center of the capsule
+    pub position: CgVec3,
+    pub radius: f32,
+
+    // Total height of the capsule.  Heights up to 2 * radius are a sphere
+    pub height: f32,
+    pub step_height: f32,
+
+    // Steepest slope in degrees the character can stand on
+    pub max_slope: f32,
+
+    // Gap kept between the character and the shapes it touches so they don't start out touching on
+    // the next move
+    pub skin_width: f32,
+    pub filter: KbCollisionFilter,
+
+    ground_normal: Option<CgVec3>,
+}
+
+impl KbCharacterController {
+    pub fn new(position: &CgVec3, radius: f32, height: f32) -> Self {
+        KbCharacterController {
+            position: *position,
+            radius,
+            height,
+            step_height: radius * 0.5,
+            max_slope: 45.0,
+            skin_width: 0.01,
+            filter: KbCollisionFilter {
+                blocking_only: true,
+                ..Default::default()
+            },
+            ground_normal: None,
+        }
+    }
+
+    pub fn is_grounded(&self) -> bool {
+        self.ground_normal.is_some()
+    }
+
+    // Normal of the ground under the character, if it's standing on something
+    pub fn get_ground_normal(&self) -> Option<CgVec3> {
+        self.ground_normal
+    }
+
+    // Moves the character by as much of movement as collision allows and returns how far it moved.
+    // Gravity is up to the game, which can add it to movement while the character isn't grounded
+    pub fn move_and_slide(
+        &mut self,
+        collision_manager: &KbCollisionManager,
+        movement: &CgVec3,
+    ) -> CgVec3 {
+        let start_position = self.position;
+        let was_grounded = self.is_grounded();
+        self.depenetrate(collision_manager);
+
+        let horizontal = CgVec3::new(movement.x, 0.0, movement.z);
+        let ground_position = self.position;
+        let can_step = was_grounded && movement.y <= 0.0 && horizontal.magnitude2() > f32::EPSILON;
+        if !can_step || !self.step_and_slide(collision_manager, &horizontal, -movement.y) {
+            self.position = ground_position;
+            self.slide(collision_manager, &horizontal, was_grounded);
+            self.drop_down(collision_manager, -movement.y, was_grounded);
+        }
+
+        let ground_probe = CgVec3::new(0.0, -self.skin_width * 2.0, 0.0);
+        self.ground_normal = match self.sweep(collision_manager, &ground_probe) {
+            Some(hit) if self.is_walkable_hit(collision_manager, &hit) => Some(hit.normal),
+            _ => None,
+        };
+
+        self.position - start_position
+    }
+
+    // Lifts by the step height so the horizontal move glides over ledges, then drops back down.
+    // Returns false without undoing the move if it climbed something taller than step_height
+    fn step_and_slide(
+        &mut self,
+        collision_manager: &KbCollisionManager,
+        horizontal: &CgVec3,
+        drop: f32,
+    ) -> bool {
+        let foot_height = self.position.y - (self.height * 0.5).max(self.radius);
+        let lift = CgVec3::new(0.0, self.step_height, 0.0);
+        let step_up = match self.sweep(collision_manager, &lift) {
+            Some(hit) => (hit.t * self.step_height - self.skin_width).max(0.0),
+            None => self.step_height,
+        };
+        self.position.y += step_up;
+
+        self.slide(collision_manager, horizontal, true);
+        match self.drop_down(collision_manager, step_up + drop, true) {
+            Some(ground) => ground.point.y - foot_height <= self.step_height + self.skin_width,
+            None => true,
+        }
+    }
+
+    // Moves down by drop, or up when it's negative, and returns the ground landed on.  Grounded
+    // characters stick to ground up to step_height further down when walking down slopes and stairs
+    fn drop_down(
+        &mut self,
+        collision_manager: &KbCollisionManager,
+        drop: f32,
+        was_grounded: bool,
+    ) -> Option<KbSweepHit> {
+        if drop < 0.0 || (drop == 0.0 && !was_grounded) {
+            self.slide(collision_manager, &CgVec3::new(0.0, -drop, 0.0), false);
+            return None;
+        }
+
+        let probe = drop + if was_grounded { self.step_height } else { 0.0 };
+        let down = CgVec3::new(0.0, -probe, 0.0);
+        match self.sweep(collision_manager, &down) {
+            Some(hit) if self.is_walkable_hit(collision_manager, &hit) => {
+                self.position.y -= (hit.t * probe - self.skin_width).max(0.0);
+                Some(hit)
+            }
+            _ => {
+                self.slide(collision_manager, &CgVec3::new(0.0, -drop, 0.0), false);
+                None
+            }
+        }
+    }
+
+    fn is_walkable(&self, normal: &CgVec3) -> bool {
+        normal.y >= self.max_slope.to_radians().cos()
+    }
+
+    // Standing on a box's top edge counts as standing on its top face, otherwise characters
+    // stepping onto a ledge would slide back off the rounded edge
+    fn is_walkable_hit(&self, collision_manager: &KbCollisionManager, hit: &KbSweepHit) -> bool {
+        if self.is_walkable(&hit.normal) {
+            return true;
+        }
+        match collision_manager.get_collision(&hit.handle) {
+            KbCollisionShape::AABB(aabb) => (hit.point.y - aabb.max().y).abs() <= self.skin_width,
+            _ => false,
+        }
+    }
+
+    // Capsules are swept as a column of spheres no more than a radius apart
+    fn get_spheres(&self) -> Vec<KbCollisionShape> {
+        let half_length = (self.height * 0.5 - self.radius).max(0.0);
+        let num_spheres = (2.0 * half_length / self.radius).ceil() as usize + 1;
+        (0..num_spheres)
+            .map(|i| {
+                let offset = if num_spheres > 1 {
+                    -half_length + 2.0 * half_length * i as f32 / (num_spheres - 1) as f32
+                } else {
+                    0.0
+                };
+                KbCollisionShape::Sphere(KbCollisionSphere {
+                    position: self.position + CgVec3::new(0.0, offset, 0.0),
+                    radius: self.radius,
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    fn sweep(
+        &self,
+        collision_manager: &KbCollisionManager,
+        movement: &CgVec3,
+    ) -> Option<KbSweepHit> {
+        let mut closest: Option<KbSweepHit> = None;
+        for sphere in self.get_spheres() {
+            if let Some(hit) = collision_manager.sweep_shape(&sphere, movement, &self.filter) {
+                if closest.as_ref().is_none_or(|closest| hit.t < closest.t) {
+                    closest = Some(hit);
+                }
+            }
+        }
+        closest
+    }
+
+    // Pushes the character out of anything it overlaps, like a shape that moved into it
+    fn depenetrate(&mut self, collision_manager: &KbCollisionManager) {
+        for _ in 0..MAX_DEPENETRATION_ITERATIONS {
+            let mut push = CG_VEC3_ZERO;
+            for sphere in self.get_spheres() {
+                for (_, contact) in collision_manager.overlap_shape(&sphere, &self.filter) {
+                    let amount = contact.depth + self.skin_width;
+                    for axis in 0..3 {
+                        let axis_push = contact.normal[axis] * amount;
+                        if axis_push.abs() > push[axis].abs() {
+                            push[axis] = axis_push;
+                        }
+                    }
+                }
+            }
+            if push == CG_VEC3_ZERO {
+                return;
+            }
+            self.position += push;
+        }
+    }
+
+    fn slide(
+        &mut self,
+        collision_manager: &KbCollisionManager,
+        movement: &CgVec3,
+        is_grounded: bool,
+    ) {
+        let mut remaining = *movement;
+        for _ in 0..MAX_SLIDE_ITERATIONS {
+            let distance = remaining.magnitude();
+            if distance <= f32::EPSILON {
+                return;
+            }
+
+            let hit = match self.sweep(collision_manager, &remaining) {
+                Some(hit) => hit,
+                None => {
+                    self.position += remaining;
+                    return;
+                }
+            };
+
+            // Stop short of the hit, then slide the rest of the way along the surface
+            let travel = (hit.t * distance - self.skin_width).max(0.0);
+            self.position += remaining * (travel / distance);
+            remaining *= 1.0 - hit.t;
+
+            // Grounded characters treat steep slopes as walls instead of climbing them
+            let mut normal = hit.normal;
+            if is_grounded && !self.is_walkable(&normal) {
+                normal.y = 0.0;
+                if normal.magnitude2() <= f32::EPSILON {
+                    return;
+                }
+                normal = normal.normalize();
+            }
+            remaining -= normal * remaining.dot(normal);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sphere(position: CgVec3, radius: f32) -> KbCollisionShape {
+        KbCollisionShape::Sphere(KbCollisionSphere {
+            position,
+            radius,
+            ..Default::default()
+        })
+    }
+
+    fn aabb(position: CgVec3, extents: CgVec3) -> KbCollisionShape {
+        KbCollisionShape::AABB(KbCollisionAABB {
+            position,
+            extents,
+            ..Default::default()
+        })
+    }
+
+    // Two triangles at y = 0 covering -half_size to half_size on x and z
+    fn floor_mesh(position: CgVec3, half_size: f32) -> KbCollisionShape {
+        let corner = |x: f32, z: f32| CgVec3::new(x * half_size, 0.0, z * half_size);
+        KbCollisionShape::Mesh(KbCollisionMesh::new(
+            &position,
+            vec![
+                [corner(-1.0, -1.0), corner(1.0, -1.0), corner(1.0, 1.0)],
+                [corner(-1.0, -1.0), corner(1.0, 1.0), corner(-1.0, 1.0)],
+            ],
+        ))
+    }
+
+    fn assert_vec3_near(actual: CgVec3, expected: CgVec3) {
+        assert!(
+            (actual - expected).magnitude() < 1e-4,
+            "{:?} is not {:?}",
+            actual,
+            expected
+        );
+    }
+
+    // Checks the contact's normal and depth, and that pushing shape_a out by them separates the two
+    fn assert_contact(
+        shape_a: &KbCollisionShape,
+        shape_b: &KbCollisionShape,
+        normal: CgVec3,
+        depth: f32,
+    ) -> KbCollisionContact {
+        let contact = kb_shapes_overlap(shape_a, shape_b).expect("Shapes should overlap");
+        assert_vec3_near(contact.normal, normal);
+        assert!(
+            (contact.depth - depth).abs() < 1e-4,
+            "depth {} is not {}",
+            contact.depth,
+            depth
+        );
+
+        let pushed_out = match shape_a {
+            KbCollisionShape::Mesh(_) => {
+                let position = match shape_b {
+                    KbCollisionShape::Sphere(sphere) => sphere.position,
+                    KbCollisionShape::AABB(aabb) => aabb.position,
+                    KbCollisionShape::Mesh(mesh) => mesh.position,
+                };
+                let shape_b = move_shape_to(shape_b, &(position - normal * (depth + 1e-3)));
+                kb_shapes_overlap(shape_a, &shape_b)
+            }
+            _ => {
+                let position = get_shape_bounds(shape_a);
+                let position = (position.0 + position.1) * 0.5;
+                let shape_a = move_shape_to(shape_a, &(position + normal * (depth + 1e-3)));
+                kb_shapes_overlap(&shape_a, shape_b)
+            }
+        };
+        assert!(pushed_out.is_none(), "Pushing out left {:?}", pushed_out);
+        contact
+    }
+
+    #[test]
+    fn sphere_vs_sphere_contacts() {
+        let sphere_b = sphere(CG_VEC3_ZERO, 1.0);
+
+        let contact = assert_contact(
+            &sphere(CgVec3::new(1.5, 0.0, 0.0), 1.0),
+            &sphere_b,
+            CgVec3::new(1.0, 0.0, 0.0),
+            0.5,
+        );
+        assert_vec3_near(contact.point, CgVec3::new(0.75, 0.0, 0.0));
+
+        let diagonal = CgVec3::new(1.0, 1.0, 0.0).normalize();
+        assert_contact(&sphere(diagonal, 1.0), &sphere_b, diagonal, 1.0);
+
+        // Touching isn't overlapping
+        assert!(kb_shapes_overlap(&sphere(CgVec3::new(2.0, 0.0, 0.0), 1.0), &sphere_b).is_none());
+
+        // Fully inside the other sphere
+        assert_contact(
+            &sphere(CgVec3::new(0.0, 0.0, -0.25), 0.5),
+            &sphere_b,
+            CgVec3::new(0.0, 0.0, -1.0),
+            1.25,
+        );
+
+        // Concentric spheres have no direction between them, so they're pushed up
+        assert_contact(&sphere(CG_VEC3_ZERO, 0.5), &sphere_b, CG_VEC3_UP, 1.5);
+    }
+
+    #[test]
+    fn sphere_vs_aabb_contacts() {
+        let aabb_b = aabb(CG_VEC3_ZERO, CG_VEC3_ONE);
+
+        let contact = assert_contact(
+            &sphere(CgVec3::new(1.5, 0.0, 0.0), 1.0),
+            &aabb_b,
+            CgVec3::new(1.0, 0.0, 0.0),
+            0.5,
+        );
+        assert_vec3_near(contact.point, CgVec3::new(1.0, 0.0, 0.0));
+
+        // Nearest to an edge
+        let contact = assert_contact(
+            &sphere(CgVec3::new(1.5, 1.5, 0.0), 1.0),
+            &aabb_b,
+            CgVec3::new(1.0, 1.0, 0.0).normalize(),
+            1.0 - 0.5_f32.sqrt(),
+        );
+        assert_vec3_near(contact.point, CgVec3::new(1.0, 1.0, 0.0));
+
+        assert!(kb_shapes_overlap(&sphere(CgVec3::new(0.0, -2.0, 0.0), 1.0), &aabb_b).is_none());
+
+        // Centers inside the box push out through the nearest face
+        let contact = assert_contact(
+            &sphere(CgVec3::new(0.25, -0.75, 0.0), 0.5),
+            &aabb_b,
+            CgVec3::new(0.0, -1.0, 0.0),
+            0.75,
+        );
+        assert_vec3_near(contact.point, CgVec3::new(0.25, -1.0, 0.0));
+
+        // A center at the box's center is equally near every face.  The +x face is picked
+        assert_contact(
+            &sphere(CG_VEC3_ZERO, 0.5),
+            &aabb_b,
+            CgVec3::new(1.0, 0.0, 0.0),
+            1.5,
+        );
+
+        // Reversing the shapes reverses the normal
+        assert_contact(
+            &aabb_b,
+            &sphere(CgVec3::new(1.5, 0.0, 0.0), 1.0),
+            CgVec3::new(-1.0, 0.0, 0.0),
+            0.5,
+        );
+    }
+
+    #[test]
+    fn aabb_vs_aabb_contacts() {
+        let aabb_b = aabb(CG_VEC3_ZERO, CG_VEC3_ONE);
+
+        let contact = assert_contact(
+            &aabb(CgVec3::new(1.5, 0.25, 0.0), CG_VEC3_ONE),
+            &aabb_b,
+            CgVec3::new(1.0, 0.0, 0.0),
+            0.5,
+        );
+        assert_vec3_near(contact.point, CgVec3::new(0.75, 0.125, 0.0));
+
+        assert_contact(
+            &aabb(CgVec3::new(0.0, 0.0, -1.75), CG_VEC3_ONE),
+            &aabb_b,
+            CgVec3::new(0.0, 0.0, -1.0),
+            0.25,
+        );
+
+        assert!(
+            kb_shapes_overlap(&aabb(CgVec3::new(2.0, 0.0, 0.0), CG_VEC3_ONE), &aabb_b).is_none()
+        );
+
+        // Fully inside the other box
+        assert_contact(
+            &aabb(CgVec3::new(0.0, 0.25, 0.0), CgVec3::new(0.5, 0.5, 0.5)),
+            &aabb_b,
+            CgVec3::new(0.0, 1.0, 0.0),
+            1.25,
+        );
+
+        // Identical boxes are pushed apart along x
+        assert_contact(&aabb_b, &aabb_b, CgVec3::new(1.0, 0.0, 0.0), 2.0);
+    }
+
+    #[test]
+    fn mesh_contacts() {
+        let floor = floor_mesh(CgVec3::new(0.0, 1.0, 0.0), 5.0);
+
+        let contact = assert_contact(
+            &sphere(CgVec3::new(1.0, 1.5, 2.0), 1.0),
+            &floor,
+            CG_VEC3_UP,
+            0.5,
+        );
+        assert_vec3_near(contact.point, CgVec3::new(1.0, 1.0, 2.0));
+
+        // Meshes have no inside, so shapes below the floor are pushed down
+        assert_contact(
+            &sphere(CgVec3::new(0.0, 0.75, 0.0), 1.0),
+            &floor,
+            -CG_VEC3_UP,
+            0.75,
+        );
+        assert_contact(
+            &aabb(CgVec3::new(-2.0, 1.25, 0.0), CG_VEC3_ONE),
+            &floor,
+            CG_VEC3_UP,
+            0.75,
+        );
+
+        // Over the edge of the floor
+        assert_contact(
+            &sphere(CgVec3::new(5.5, 1.5, 0.0), 1.0),
+            &floor,
+            CgVec3::new(1.0, 1.0, 0.0).normalize(),
+            1.0 - 0.5_f32.sqrt(),
+        );
+
+        assert!(kb_shapes_overlap(&sphere(CgVec3::new(0.0, 2.0, 0.0), 1.0), &floor).is_none());
+        assert!(
+            kb_shapes_overlap(&aabb(CgVec3::new(0.0, 2.0, 0.0), CG_VEC3_ONE), &floor).is_none()
+        );
+
+        // Mesh first reverses the normal
+        assert_contact(
+            &floor,
+            &sphere(CgVec3::new(1.0, 1.5, 2.0), 1.0),
+            -CG_VEC3_UP,
+            0.5,
+        );
+        assert!(kb_shapes_overlap(&floor, &floor).is_none());
+    }
+
+    // A floor with its top at y = 0 and a capsule standing on it at x = 0, z = 0
+    fn new_floor_and_character() -> (KbCollisionManager, KbCharacterController) {
+        let mut collision_manager = KbCollisionManager::new();
+        collision_manager.add_collision(&aabb(
+            CgVec3::new(0.0, -1.0, 0.0),
+            CgVec3::new(50.0, 1.0, 50.0),
+        ));
+        let mut character = KbCharacterController::new(&CgVec3::new(0.0, 1.005, 0.0), 0.5, 2.0);
+        character.move_and_slide(&collision_manager, &CG_VEC3_ZERO);
+        assert!(character.is_grounded());
+        (collision_manager, character)
+    }
+
+    fn walk(
+        character: &mut KbCharacterController,
+        collision_manager: &KbCollisionManager,
+        movement: CgVec3,
+        num_steps: usize,
+    ) {
+        for _ in 0..num_steps {
+            character.move_and_slide(collision_manager, &(movement / num_steps as f32));
+        }
+    }
+
+    fn assert_near(actual: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn character_slides_along_walls() {
+        let (mut collision_manager, mut character) = new_floor_and_character();
+        collision_manager.add_collision(&aabb(
+            CgVec3::new(3.5, 2.0, 0.0),
+            CgVec3::new(0.5, 2.0, 20.0),
+        ));
+
+        let moved = character.move_and_slide(&collision_manager, &CgVec3::new(4.0, 0.0, 2.0));
+        assert_near(character.position.x, 2.5, 0.02);
+        assert_near(moved.z, 2.0, 0.02);
+        assert_near(character.position.y, 1.0, 0.02);
+        assert!(character.is_grounded());
+    }
+
+    #[test]
+    fn character_steps_onto_low_ledges() {
+        let (mut collision_manager, mut character) = new_floor_and_character();
+        assert!(0.2 < character.step_height);
+        collision_manager.add_collision(&aabb(
+            CgVec3::new(6.0, 0.1, 0.0),
+            CgVec3::new(4.0, 0.1, 20.0),
+        ));
+
+        walk(
+            &mut character,
+            &collision_manager,
+            CgVec3::new(4.0, 0.0, 0.0),
+            8,
+        );
+        assert_near(character.position.x, 4.0, 0.02);
+        assert_near(character.position.y, 1.2, 0.02);
+        assert!(character.is_grounded());
+    }
+
+    #[test]
+    fn character_stops_at_high_ledges() {
+        let (mut collision_manager, mut character) = new_floor_and_character();
+        assert!(0.4 > character.step_height);
+        collision_manager.add_collision(&aabb(
+            CgVec3::new(6.0, 0.2, 0.0),
+            CgVec3::new(4.0, 0.2, 20.0),
+        ));
+
+        walk(
+            &mut character,
+            &collision_manager,
+            CgVec3::new(4.0, 0.0, 0.0),
+            8,
+        );
+        assert!(character.position.x < 1.51, "{:?}", character.position);
+        assert_near(character.position.y, 1.0, 0.02);
+        assert!(character.is_grounded());
+    }
+
+    #[test]
+    fn character_snaps_down_small_drops() {
+        let (mut collision_manager, mut character) = new_floor_and_character();
+        collision_manager.add_collision(&aabb(
+            CgVec3::new(-4.0, 0.1, 0.0),
+            CgVec3::new(4.0, 0.1, 20.0),
+        ));
+        character.position.y = 1.205;
+        character.move_and_slide(&collision_manager, &CG_VEC3_ZERO);
+        assert!(character.is_grounded());
+
+        // Walking off the ledge keeps the character on the ground instead of in the air
+        character.move_and_slide(&collision_manager, &CgVec3::new(1.0, 0.0, 0.0));
+        assert_near(character.position.y, 1.0, 0.02);
+        assert!(character.is_grounded());
+
+        // Drops taller than step_height are fallen off
+        character.position = CgVec3::new(-1.0, 3.005, 0.0);
+        collision_manager.add_collision(&aabb(
+            CgVec3::new(-4.0, 1.0, 0.0),
+            CgVec3::new(4.0, 1.0, 20.0),
+        ));
+        character.move_and_slide(&collision_manager, &CG_VEC3_ZERO);
+        assert!(character.is_grounded());
+        character.move_and_slide(&collision_manager, &CgVec3::new(2.0, 0.0, 0.0));
+        assert_near(character.position.y, 3.0, 0.02);
+        assert!(!character.is_grounded());
+    }
+
+    #[test]
+    fn character_is_pushed_out_of_overlapping_shapes() {
+        let (mut collision_manager, mut character) = new_floor_and_character();
+        collision_manager.add_collision(&aabb(
+            CgVec3::new(0.75, 1.0, 0.0),
+            CgVec3::new(0.5, 1.0, 1.0),
+        ));
+
+        character.move_and_slide(&collision_manager, &CG_VEC3_ZERO);
+        assert_near(character.position.x, -0.25, 0.02);
+        for sphere in character.get_spheres() {
+            assert!(collision_manager
+                .overlap_shape(&sphere, &character.filter)
+                .is_empty());
+        }
+    }
+
+    #[test]
+    fn fast_shapes_dont_pass_through_thin_boxes() {
+        let (mut collision_manager, mut character) = new_floor_and_character();
+        let wall = collision_manager.add_collision(&aabb(
+            CgVec3::new(3.0, 2.0, 0.0),
+            CgVec3::new(0.01, 2.0, 20.0),
+        ));
+
+        character.move_and_slide(&collision_manager, &CgVec3::new(50.0, 0.0, 0.0));
+        assert_near(character.position.x, 2.49, 0.02);
+
+        let filter = KbCollisionFilter::default();
+        let moving_shapes = [
+            sphere(CgVec3::new(0.0, 2.0, 0.0), 0.25),
+            aabb(CgVec3::new(0.0, 2.0, 0.0), CgVec3::new(0.25, 0.25, 0.25)),
+        ];
+        for moving_shape in &moving_shapes {
+            let hit = collision_manager
+                .sweep_shape(moving_shape, &CgVec3::new(100.0, 0.0, 0.0), &filter)
+                .expect("Sweep should hit the wall");
+            assert_eq!(hit.handle, wall);
+            assert_near(hit.t, 2.74 / 100.0, 1e-4);
+            assert_vec3_near(hit.normal, CgVec3::new(-1.0, 0.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn boxes_sweep_onto_meshes() {
+        let mut collision_manager = KbCollisionManager::new();
+        collision_manager.add_collision(&floor_mesh(CG_VEC3_ZERO, 5.0));
+
+        let filter = KbCollisionFilter::default();
+        let falling_box = aabb(CgVec3::new(1.0, 3.0, 1.0), CG_VEC3_ONE);
+        let hit = collision_manager
+            .sweep_shape(&falling_box, &CgVec3::new(0.0, -4.0, 0.0), &filter)
+            .expect("Box should land on the floor");
+        assert_near(hit.t, 0.5, 1e-4);
+        assert_vec3_near(hit.normal, CG_VEC3_UP);
+
+        // Sliding along the floor while resting on it isn't a hit
+        let resting_box = aabb(CgVec3::new(1.0, 1.0, 1.0), CG_VEC3_ONE);
+        assert!(collision_manager
+            .sweep_shape(&resting_box, &CgVec3::new(2.0, 0.0, 0.0), &filter)
+            .is_none());
+    }
+
+    // What cast_ray_all() should return, found by testing every shape instead of using the grid
+    fn brute_force_ray_hits(
+        collision_manager: &KbCollisionManager,
+        start: &CgVec3,
+        dir: &CgVec3,
+        max_distance: f32,
+    ) -> Vec<KbRayHit> {
+        let dir = dir.normalize();
+        let mut hits: Vec<KbRayHit> = collision_manager
+            .collision_objects
+            .handles_to_assets
+            .iter()
+            .filter_map(|(handle, shape)| {
+                let hit = ray_vs_shape(start, &dir, shape)?;
+                (hit.0 <= max_distance).then(|| make_ray_hit(start, &dir, hit, handle, shape))
+            })
+            .collect();
+        hits.sort_by(|a, b| {
+            a.distance
+                .total_cmp(&b.distance)
+                .then(a.handle.index.cmp(&b.handle.index))
+        });
+        hits
+    }
+
+    fn brute_force_overlaps(
+        collision_manager: &KbCollisionManager,
+        shape: &KbCollisionShape,
+    ) -> Vec<KbCollisionHandle> {
+        let mut handles: Vec<KbCollisionHandle> = collision_manager
+            .collision_objects
+            .handles_to_assets
+            .iter()
+            .filter(|(_, other_shape)| kb_shapes_overlap(shape, other_shape).is_some())
+            .map(|(handle, _)| *handle)
+            .collect();
+        handles.sort_by_key(|handle| handle.index);
+        handles
+    }
+
+    fn assert_ray_matches_brute_force(
+        collision_manager: &KbCollisionManager,
+        start: &CgVec3,
+        dir: &CgVec3,
+        max_distance: f32,
+    ) {
+        let filter = KbCollisionFilter::default();
+        let expected = brute_force_ray_hits(collision_manager, start, dir, max_distance);
+
+        let mut hits = collision_manager.cast_ray_all(start, dir, max_distance, &filter);
+        hits.sort_by(|a, b| {
+            a.distance
+                .total_cmp(&b.distance)
+                .then(a.handle.index.cmp(&b.handle.index))
+        });
+        let hit_keys = |hits: &[KbRayHit]| {
+            hits.iter()
+                .map(|hit| (hit.handle, hit.distance))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            hit_keys(&hits),
+            hit_keys(&expected),
+            "cast_ray_all() from {:?} along {:?}",
+            start,
+            dir
+        );
+
+        let closest = collision_manager.cast_ray(start, dir, max_distance, &filter);
+        match (closest, expected.first()) {
+            (None, None) => {}
+            (Some(closest), Some(nearest)) => {
+                // Shapes hit at exactly the same distance can come back in either order
+                assert_eq!(closest.distance, nearest.distance);
+                assert!(expected
+                    .iter()
+                    .any(|hit| hit.handle == closest.handle && hit.distance == closest.distance));
+            }
+            (closest, nearest) => panic!(
+                "cast_ray() from {:?} along {:?} returned {:?}, expected {:?}",
+                start, dir, closest, nearest
+            ),
+        }
+    }
+
+    // Small shapes of both kinds, walls too long for the grid's cells and a few shapes removed
+    // again so the grid has to forget them
+    fn random_scene(num_shapes: usize) -> KbCollisionManager {
+        let mut collision_manager = KbCollisionManager::new();
+        let mut handles = Vec::<KbCollisionHandle>::new();
+        for i in 0..num_shapes {
+            let position = kb_random_vec3(
+                CgVec3::new(-50.0, -5.0, -50.0),
+                CgVec3::new(50.0, 5.0, 50.0),
+            );
+            let shape = if i % 25 == 0 {
+                aabb(position, CgVec3::new(40.0, 2.0, 0.5))
+            } else if i % 2 == 0 {
+                sphere(position, kb_random_f32(0.25, 3.0))
+            } else {
+                let extents =
+                    kb_random_vec3(CgVec3::new(0.25, 0.25, 0.25), CgVec3::new(3.0, 3.0, 3.0));
+                aabb(position, extents)
+            };
+            handles.push(collision_manager.add_collision(&shape));
+        }
+        for handle in handles.iter().step_by(7) {
+            collision_manager.remove_collision(handle);
+        }
+        collision_manager
+    }
+
+    #[test]
+    fn grid_matches_brute_force() {
+        kb_seed_random(20);
+        let collision_manager = random_scene(400);
+        assert!(!collision_manager.grid.large_shapes.is_empty());
+
+        for i in 0..500 {
+            // Half the rays start well outside the grid
+            let start_range = if i % 2 == 0 { 60.0 } else { 150.0 };
+            let start = kb_random_vec3(
+                CgVec3::new(-start_range, -start_range, -start_range),
+                CgVec3::new(start_range, start_range, start_range),
+            );
+            let mut dir = kb_random_vec3(CgVec3::new(-1.0, -1.0, -1.0), CgVec3::new(1.0, 1.0, 1.0));
+            if i % 5 == 0 {
+                dir[i % 3] = 0.0;
+            }
+            if dir.magnitude2() <= f32::EPSILON {
+                continue;
+            }
+            let max_distance = if i % 3 == 0 { 10.0 } else { 400.0 };
+            assert_ray_matches_brute_force(&collision_manager, &start, &dir, max_distance);
+
+            let query_shape = if i % 2 == 0 {
+                sphere(start, kb_random_f32(0.5, 8.0))
+            } else {
+                aabb(start, CgVec3::new(6.0, 2.0, 1.0))
+            };
+            let overlaps: Vec<KbCollisionHandle> = collision_manager
+                .overlap_shape(&query_shape, &KbCollisionFilter::default())
+                .into_iter()
+                .map(|(handle, _)| handle)
+                .collect();
+            assert_eq!(
+                overlaps,
+                brute_force_overlaps(&collision_manager, &query_shape)
+            );
+        }
+    }
+
+    #[test]
+    fn grid_matches_brute_force_on_cell_boundaries() {
+        // A lattice of boxes whose faces line up with the default 4 unit cells
+        let mut collision_manager = KbCollisionManager::new();
+        for x in -3..3 {
+            for z in -3..3 {
+                let position = CgVec3::new(x as f32 * 8.0 + 2.0, 2.0, z as f32 * 8.0 + 2.0);
+                collision_manager.add_collision(&aabb(position, CgVec3::new(2.0, 2.0, 2.0)));
+            }
+        }
+
+        // Rays that start outside the grid and run exactly along cell boundaries and box faces
+        for boundary in [-24.0, -20.0, -4.0, 0.0, 4.0, 8.0, 20.0] {
+            for height in [0.0, 2.0, 4.0] {
+                let rays = [
+                    (
+                        CgVec3::new(-100.0, height, boundary),
+                        CgVec3::new(1.0, 0.0, 0.0),
+                    ),
+                    (
+                        CgVec3::new(100.0, height, boundary),
+                        CgVec3::new(-1.0, 0.0, 0.0),
+                    ),
+                    (
+                        CgVec3::new(boundary, height, -100.0),
+                        CgVec3::new(0.0, 0.0, 1.0),
+                    ),
+                    (
+                        CgVec3::new(boundary, 100.0, boundary),
+                        CgVec3::new(0.0, -1.0, 0.0),
+                    ),
+                    (
+                        CgVec3::new(-100.0, height, boundary - 100.0),
+                        CgVec3::new(1.0, 0.0, 1.0),
+                    ),
+                ];
+                for (start, dir) in rays {
+                    assert_ray_matches_brute_force(&collision_manager, &start, &dir, 400.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn grid_bounds_shrink_after_removal() {
+        let mut collision_manager = KbCollisionManager::new();
+        collision_manager.add_collision(&sphere(CG_VEC3_ZERO, 1.0));
+        let initial_bounds = (
+            collision_manager.grid.bounds_min,
+            collision_manager.grid.bounds_max,
+        );
+
+        let far_handle =
+            collision_manager.add_collision(&sphere(CgVec3::new(1000.0, 0.0, 0.0), 1.0));
+        assert_ne!(collision_manager.grid.bounds_max, initial_bounds.1);
+
+        collision_manager.remove_collision(&far_handle);
+        assert_eq!(
+            (
+                collision_manager.grid.bounds_min,
+                collision_manager.grid.bounds_max
+            ),
+            initial_bounds
+        );
+
+        collision_manager.update_collision_position(
+            &KbCollisionHandle { index: 0 },
+            &CgVec3::new(-500.0, 0.0, 0.0),
+        );
+        assert!(collision_manager.grid.bounds_max.0 < 0);
+        assert_ray_matches_brute_force(
+            &collision_manager,
+            &CgVec3::new(0.0, 0.0, 0.0),
+            &CgVec3::new(-1.0, 0.0, 0.0),
+            1000.0,
+        );
+    }
+
+    // The same mesh with every triangle in one unbounded leaf, so queries test all of them
+    fn brute_force_mesh(mesh: &KbCollisionMesh) -> KbCollisionMesh {
+        let triangles = mesh.tree.triangles.clone();
+        let root = KbTriangleNode {
+            min: CgVec3::new(f32::MIN, f32::MIN, f32::MIN),
+            max: CgVec3::new(f32::MAX, f32::MAX, f32::MAX),
+            first: 0,
+            count: triangles.len(),
+        };
+        KbCollisionMesh {
+            tree: Arc::new(KbTriangleTree {
+                triangles,
+                nodes: vec![root],
+            }),
+            ..mesh.clone()
+        }
+    }
+
+    // Terrain that's flat on one half and bumpy on the other, a wall, loose triangles and
+    // triangles with no area
+    fn random_mesh() -> KbCollisionMesh {
+        let mut triangles = Vec::new();
+        let heights: Vec<Vec<f32>> = (-8..=8)
+            .map(|x| {
+                (-8..=8)
+                    .map(|_| {
+                        if x <= 0 {
+                            0.0
+                        } else {
+                            kb_random_f32(-1.0, 1.0)
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        for x in -8..8 {
+            for z in -8..8 {
+                let corner = |dx: i32, dz: i32| {
+                    let height = heights[(x + dx + 8) as usize][(z + dz + 8) as usize];
+                    CgVec3::new((x + dx) as f32, height, (z + dz) as f32)
+                };
+                triangles.push([corner(0, 0), corner(1, 0), corner(1, 1)]);
+                triangles.push([corner(0, 0), corner(1, 1), corner(0, 1)]);
+            }
+        }
+
+        let wall = |y: f32, z: f32| CgVec3::new(-4.0, y, z);
+        triangles.push([wall(0.0, -8.0), wall(4.0, -8.0), wall(4.0, 8.0)]);
+        triangles.push([wall(0.0, -8.0), wall(4.0, 8.0), wall(0.0, 8.0)]);
+
+        let range = (CgVec3::new(-8.0, -2.0, -8.0), CgVec3::new(8.0, 4.0, 8.0));
+        for _ in 0..60 {
+            let vertex = kb_random_vec3(range.0, range.1);
+            let offset =
+                |_| kb_random_vec3(CgVec3::new(-2.0, -2.0, -2.0), CgVec3::new(2.0, 2.0, 2.0));
+            let [a, b] = [0, 1].map(offset);
+            triangles.push([vertex, vertex + a, vertex + b]);
+            triangles.push([vertex, vertex + a, vertex + a * 2.0]);
+            triangles.push([vertex, vertex, vertex + b]);
+            triangles.push([vertex, vertex, vertex]);
+        }
+        KbCollisionMesh::new(&CgVec3::new(3.0, -1.0, 2.0), triangles)
+    }
+
+    fn assert_same_t(actual: Option<f32>, expected: Option<f32>, what: &str) {
+        match (actual, expected) {
+            (None, None) => {}
+            (Some(actual), Some(expected)) if (actual - expected).abs() < 1e-5 => {}
+            _ => panic!("{} returned {:?}, expected {:?}", what, actual, expected),
+        }
+    }
+
+    #[test]
+    fn triangle_tree_matches_brute_force() {
+        kb_seed_random(23);
+        let mesh = random_mesh();
+        let brute_force = brute_force_mesh(&mesh);
+        let leaf_triangles: usize = mesh.tree.nodes.iter().map(|node| node.count).sum();
+        assert_eq!(leaf_triangles, mesh.get_triangles().len());
+        assert!(mesh.tree.nodes.len() > 1);
+
+        let shapes = (
+            KbCollisionShape::Mesh(mesh.clone()),
+            KbCollisionShape::Mesh(brute_force),
+        );
+        let range = (CgVec3::new(-6.0, -4.0, -7.0), CgVec3::new(12.0, 4.0, 11.0));
+        let mut num_hits = 0;
+        for i in 0..1000 {
+            let start = kb_random_vec3(range.0, range.1);
+            let mut dir = kb_random_vec3(CgVec3::new(-1.0, -1.0, -1.0), CgVec3::new(1.0, 1.0, 1.0));
+            match i % 4 {
+                // Along the flat half of the terrain and through its flat leaves
+                0 => {
+                    dir.y = 0.0;
+                    if i % 8 == 0 {
+                        dir = CgVec3::new(0.0, 0.0, dir.z);
+                    }
+                }
+                // In the wall's plane
+                1 => dir.x = 0.0,
+                // Straight down
+                2 => dir = CgVec3::new(0.0, -dir.y.abs() - 0.1, 0.0),
+                _ => {}
+            }
+            let start = match i % 4 {
+                0 => CgVec3::new(start.x, mesh.position.y, start.z),
+                1 => CgVec3::new(mesh.position.x - 4.0, start.y, start.z),
+                _ => start,
+            };
+            let dir = dir * kb_random_f32(0.5, 20.0);
+
+            let ray = |shape| ray_vs_shape(&start, &dir, shape).map(|(t, _)| t);
+            let expected = ray(&shapes.1);
+            num_hits += expected.is_some() as u32;
+            assert_same_t(
+                ray(&shapes.0),
+                expected,
+                &format!("ray from {:?} along {:?}", start, dir),
+            );
+
+            let query_shapes = [
+                sphere(start, kb_random_f32(0.1, 2.0)),
+                aabb(
+                    start,
+                    kb_random_vec3(CgVec3::new(0.1, 0.1, 0.1), CgVec3::new(2.0, 2.0, 2.0)),
+                ),
+            ];
+            for shape in &query_shapes {
+                let depth = |mesh| kb_shapes_overlap(shape, mesh).map(|contact| contact.depth);
+                assert_same_t(depth(&shapes.0), depth(&shapes.1), "overlap");
+
+                let movement = dir * 0.25;
+                let sweep = |mesh| sweep_vs_shape(shape, &movement, mesh).map(|(t, _, _)| t);
+                assert_same_t(sweep(&shapes.0), sweep(&shapes.1), "sweep");
+            }
+        }
+        assert!(num_hits > 200, "only {} rays hit the mesh", num_hits);
+    }
+
+    fn sphere_with_user_data(position: CgVec3, radius: f32, user_data: u64) -> KbCollisionShape {
+        KbCollisionShape::Sphere(KbCollisionSphere {
+            position,
+            radius,
+            user_data,
+            ..Default::default()
+        })
+    }
+
+    // Each event as its type, the trigger's user data and the other shape's user data
+    fn drain_events(
+        collision_manager: &mut KbCollisionManager,
+    ) -> Vec<(KbTriggerEventType, u64, u64)> {
+        collision_manager
+            .drain_trigger_events()
+            .iter()
+            .map(|event| {
+                (
+                    event.event_type,
+                    event.trigger_user_data,
+                    event.other_user_data,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn triggers_report_enter_stay_and_exit() {
+        use KbTriggerEventType::*;
+        let mut collision_manager = KbCollisionManager::new();
+        let trigger = collision_manager.add_trigger(&sphere_with_user_data(CG_VEC3_ZERO, 2.0, 100));
+        let inside = collision_manager.add_collision(&sphere_with_user_data(CG_VEC3_ZERO, 0.5, 1));
+        let outside = collision_manager.add_collision(&sphere_with_user_data(
+            CgVec3::new(5.0, 0.0, 0.0),
+            0.5,
+            2,
+        ));
+
+        collision_manager.update_triggers();
+        assert_eq!(drain_events(&mut collision_manager), [(Enter, 100, 1)]);
+
+        collision_manager.update_collision_position(&outside, &CgVec3::new(1.0, 0.0, 0.0));
+        collision_manager.update_triggers();
+        assert_eq!(
+            drain_events(&mut collision_manager),
+            [(Stay, 100, 1), (Enter, 100, 2)]
+        );
+
+        // Events pile up until they're drained
+        collision_manager.update_collision_position(&inside, &CgVec3::new(-5.0, 0.0, 0.0));
+        collision_manager.update_triggers();
+        collision_manager.update_triggers();
+        assert_eq!(
+            drain_events(&mut collision_manager),
+            [(Stay, 100, 2), (Exit, 100, 1), (Stay, 100, 2)]
+        );
+
+        // Touching isn't overlapping
+        collision_manager.update_collision_position(&outside, &CgVec3::new(2.5, 0.0, 0.0));
+        collision_manager.update_triggers();
+        assert_eq!(drain_events(&mut collision_manager), [(Exit, 100, 2)]);
+
+        // Moving the trigger onto a shape
+        collision_manager.update_collision_position(&trigger, &CgVec3::new(-5.0, 0.0, 0.0));
+        collision_manager.update_triggers();
+        assert_eq!(drain_events(&mut collision_manager), [(Enter, 100, 1)]);
+        collision_manager.update_triggers();
+        assert_eq!(drain_events(&mut collision_manager), [(Stay, 100, 1)]);
+    }
+
+    #[test]
+    fn removing_shapes_and_triggers_reports_exits() {
+        use KbTriggerEventType::*;
+        let mut collision_manager = KbCollisionManager::new();
+        let trigger_a =
+            collision_manager.add_trigger(&sphere_with_user_data(CG_VEC3_ZERO, 2.0, 100));
+        collision_manager.add_trigger(&sphere_with_user_data(CG_VEC3_ZERO, 2.0, 200));
+        let shape_1 = collision_manager.add_collision(&sphere_with_user_data(CG_VEC3_ZERO, 0.5, 1));
+        collision_manager.add_collision(&sphere_with_user_data(CG_VEC3_ZERO, 0.5, 2));
+        collision_manager.update_triggers();
+        assert_eq!(
+            drain_events(&mut collision_manager),
+            [
+                (Enter, 100, 1),
+                (Enter, 100, 2),
+                (Enter, 200, 1),
+                (Enter, 200, 2)
+            ]
+        );
+
+        // A removed shape leaves on the next update, still with its user data
+        collision_manager.remove_collision(&shape_1);
+        assert!(drain_events(&mut collision_manager).is_empty());
+        collision_manager.update_triggers();
+        assert_eq!(
+            drain_events(&mut collision_manager),
+            [
+                (Stay, 100, 2),
+                (Exit, 100, 1),
+                (Stay, 200, 2),
+                (Exit, 200, 1)
+            ]
+        );
+
+        // A removed trigger lets go of everything in it straight away and never reports again
+        collision_manager.remove_collision(&trigger_a);
+        assert_eq!(drain_events(&mut collision_manager), [(Exit, 100, 2)]);
+        collision_manager.update_triggers();
+        assert_eq!(drain_events(&mut collision_manager), [(Stay, 200, 2)]);
     }
 }
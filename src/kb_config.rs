@@ -1,24 +1,226 @@
+use std::{collections::HashMap, fmt};
+
+use crate::kb_clock::*;
 use crate::kb_utils::*;
+use crate::log;
 use crate::KbPostProcessMode;
 
+pub use json::JsonValue as KbJsonValue;
+
+// Later layers override earlier ones: Default < File < UserSettings < Environment < CommandLine
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum KbConfigSource {
+    Default,
+    File,
+    UserSettings,
+    Environment,
+    CommandLine,
+}
+
+// Keys whose values changed when the watched config file was reloaded
+#[derive(Clone, Debug, Default)]
+pub struct KbConfigDiff {
+    pub changed_keys: Vec<String>,
+}
+
+impl KbConfigDiff {
+    pub fn contains(&self, key: &str) -> bool {
+        self.changed_keys
+            .iter()
+            .any(|changed_key| changed_key == key)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changed_keys.is_empty()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone)]
+struct KbConfigWatch {
+    file_path: String,
+    last_modified: Option<std::time::SystemTime>,
+    next_check_time: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum KbConfigError {
+    Parse {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    WrongType {
+        key: String,
+        source: KbConfigSource,
+        line: Option<usize>,
+        expected: &'static str,
+    },
+    Io {
+        path: String,
+        message: String,
+    },
+    NoUserSettingsPath,
+}
+
+impl fmt::Display for KbConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KbConfigError::Parse {
+                line,
+                column,
+                message,
+            } => write!(f, "{} at line {}, column {}", message, line, column),
+            KbConfigError::WrongType {
+                key,
+                line: Some(line),
+                expected,
+                ..
+            } => write!(f, "\"{}\" on line {} should be {}", key, line, expected),
+            KbConfigError::WrongType {
+                key,
+                source,
+                line: None,
+                expected,
+            } => match source {
+                KbConfigSource::Environment => write!(
+                    f,
+                    "KB_{} environment variable should be {}",
+                    key.to_uppercase().replace('.', "__"),
+                    expected
+                ),
+                KbConfigSource::CommandLine => {
+                    write!(f, "--{} argument should be {}", key, expected)
+                }
+                KbConfigSource::UserSettings => {
+                    write!(f, "User setting \"{}\" should be {}", key, expected)
+                }
+                _ => write!(f, "\"{}\" should be {}", key, expected),
+            },
+            KbConfigError::Io { path, message } => write!(f, "{} - {}", path, message),
+            KbConfigError::NoUserSettingsPath => {
+                write!(f, "load_user_settings() was never called")
+            }
+        }
+    }
+}
+
+impl std::error::Error for KbConfigError {}
+
+// Conversion from a config file value.  Err holds a description of the expected type
+pub trait KbConfigValue: Sized {
+    fn from_config_value(value: &KbJsonValue) -> Result<Self, &'static str>;
+}
+
+impl KbConfigValue for f32 {
+    fn from_config_value(value: &KbJsonValue) -> Result<Self, &'static str> {
+        value.as_f32().ok_or("a number")
+    }
+}
+
+impl KbConfigValue for u32 {
+    fn from_config_value(value: &KbJsonValue) -> Result<Self, &'static str> {
+        value.as_u32().ok_or("a positive integer")
+    }
+}
+
+impl KbConfigValue for i32 {
+    fn from_config_value(value: &KbJsonValue) -> Result<Self, &'static str> {
+        value.as_i32().ok_or("an integer")
+    }
+}
+
+impl KbConfigValue for bool {
+    fn from_config_value(value: &KbJsonValue) -> Result<Self, &'static str> {
+        value.as_bool().ok_or("true or false")
+    }
+}
+
+impl KbConfigValue for String {
+    fn from_config_value(value: &KbJsonValue) -> Result<Self, &'static str> {
+        value.as_str().map(|s| s.to_string()).ok_or("a string")
+    }
+}
+
+impl KbConfigValue for CgVec3 {
+    fn from_config_value(value: &KbJsonValue) -> Result<Self, &'static str> {
+        const EXPECTED: &str = "an array of 3 numbers";
+        if !value.is_array() || value.len() != 3 {
+            return Err(EXPECTED);
+        }
+        let x = value[0].as_f32().ok_or(EXPECTED)?;
+        let y = value[1].as_f32().ok_or(EXPECTED)?;
+        let z = value[2].as_f32().ok_or(EXPECTED)?;
+        Ok(CgVec3::new(x, y, z))
+    }
+}
+
+impl KbConfigValue for CgVec4 {
+    fn from_config_value(value: &KbJsonValue) -> Result<Self, &'static str> {
+        const EXPECTED: &str = "an array of 4 numbers";
+        if !value.is_array() || value.len() != 4 {
+            return Err(EXPECTED);
+        }
+        let x = value[0].as_f32().ok_or(EXPECTED)?;
+        let y = value[1].as_f32().ok_or(EXPECTED)?;
+        let z = value[2].as_f32().ok_or(EXPECTED)?;
+        let w = value[3].as_f32().ok_or(EXPECTED)?;
+        Ok(CgVec4::new(x, y, z, w))
+    }
+}
+
+// The camera and lighting values a frame is rendered with.  KbRenderer builds these from the
+// config and any values set at runtime through the renderer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KbViewSettings {
+    pub fov: f32,
+    pub foreground_fov: f32,
+    pub near_plane: f32,
+    pub far_plane: f32,
+    pub clear_color: CgVec4,
+    pub sun_color: CgVec4,
+}
+
+// Game-owned settings read from a named section of the config file, e.g. "game": { ... }
+pub trait KbConfigSection: Default {
+    // Returns Ok(false) if the key is unknown and Err(expected type) if the value can't be used
+    fn apply_value(&mut self, key: &str, value: &KbJsonValue) -> Result<bool, &'static str>;
+}
+
 #[derive(Clone)]
 pub struct KbConfig {
     // From file
-    pub enemy_spawn_delay: f32,
-    pub enemy_move_speed: f32,
     pub max_render_instances: u32,
     pub window_width: u32,
     pub window_height: u32,
     pub fov: f32,
     pub foreground_fov: f32,
+    pub near_plane: f32,
+    pub far_plane: f32,
     pub graphics_backend: wgpu::Backends,
     pub graphics_power_pref: wgpu::PowerPreference,
     pub vsync: bool,
+    pub mouse_sensitivity: f32,
+    pub mouse_invert_y: bool,
+
+    // Simulation step in seconds.  0 runs one variable-length step per frame
+    pub fixed_time_step: f32,
+    // Longest frame the simulation will advance by.  Hitches beyond it slow the game down instead
+    pub max_delta_time: f32,
+
+    // Native builds record input to input_record_file or replay input_playback_file when set
+    pub input_record_file: String,
+    pub input_playback_file: String,
 
     // Dynamic
-    pub start_time: instant::Instant,
+    // Engine clock time when the config was created.  See kb_clock.rs
+    pub start_time: f64,
     pub delta_time: f32,
-    pub last_frame_time: f32,
+    pub real_delta_time: f32,
+    // Engine clock time of the last update_frame_times() call
+    pub last_frame_time: f64,
+    pub time_scale: f32,
+    pub paused: bool,
     pub postprocess_mode: KbPostProcessMode,
     pub sunbeams_enabled: bool,
 
@@ -26,105 +228,1012 @@ pub struct KbConfig {
     pub sun_color: CgVec4,
     pub sun_beam_pos_scale: CgVec4,
     pub bullet_holes: bool,
+
+    value_sources: HashMap<String, KbConfigSource>,
+    sections: HashMap<String, json::JsonValue>,
+    file_text: String,
+    file_values: json::JsonValue,
+    #[cfg(not(target_arch = "wasm32"))]
+    file_watch: Option<KbConfigWatch>,
+    time_accumulator: f32,
+    user_settings_path: Option<String>,
+}
+
+impl Default for KbConfig {
+    fn default() -> Self {
+        let graphics_backend = {
+            #[cfg(target_arch = "wasm32")]
+            {
+                wgpu::Backends::GL
+            }
+
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                wgpu::Backends::BROWSER_WEBGPU
+            }
+        };
+
+        let start_time = kb_time_now();
+        KbConfig {
+            max_render_instances: 10000,
+            window_width: 1280,
+            window_height: 720,
+            fov: 75.0,
+            foreground_fov: 50.0,
+            near_plane: 0.1,
+            far_plane: 10000.0,
+            graphics_backend,
+            graphics_power_pref: wgpu::PowerPreference::None,
+            vsync: true,
+            mouse_sensitivity: 1.0,
+            mouse_invert_y: false,
+            fixed_time_step: 0.0,
+            max_delta_time: 0.25,
+            input_record_file: String::new(),
+            input_playback_file: String::new(),
+
+            start_time,
+            delta_time: 0.0,
+            real_delta_time: 0.0,
+            last_frame_time: start_time,
+            time_scale: 1.0,
+            paused: false,
+            postprocess_mode: KbPostProcessMode::Passthrough,
+            sunbeams_enabled: false,
+            clear_color: CG_VEC4_ZERO,
+            sun_color: CgVec4::new(1.0, 1.0, 1.0, 1.0),
+            sun_beam_pos_scale: CgVec4::new(500.0, 550.0, 500.0, 1550.0),
+            bullet_holes: false,
+
+            value_sources: HashMap::<String, KbConfigSource>::new(),
+            sections: HashMap::<String, json::JsonValue>::new(),
+            file_text: String::new(),
+            file_values: json::JsonValue::new_object(),
+            #[cfg(not(target_arch = "wasm32"))]
+            file_watch: None,
+            time_accumulator: 0.0,
+            user_settings_path: None,
+        }
+    }
 }
 
 impl KbConfig {
     pub fn new(config_file_text: &str) -> Self {
-        let mut json_file = json::parse(config_file_text).unwrap();
+        match Self::try_from_str(config_file_text) {
+            Ok(config) => config,
+            Err(error) => panic!("KbConfig::new() - {}", error),
+        }
+    }
 
-        let json_val = json_file["enemy_spawn_delay"].as_f32();
-        let enemy_spawn_delay = json_val.unwrap_or(1.0);
+    pub fn view_settings(&self) -> KbViewSettings {
+        KbViewSettings {
+            fov: self.fov,
+            foreground_fov: self.foreground_fov,
+            near_plane: self.near_plane,
+            far_plane: self.far_plane,
+            clear_color: self.clear_color,
+            sun_color: self.sun_color,
+        }
+    }
 
-        let json_val = json_file["enemy_move_speed"].as_f32();
-        let enemy_move_speed = json_val.unwrap_or(0.01);
+    // Keys beginning with an underscore are treated as comments.  Unknown keys are logged and skipped
+    pub fn try_from_str(config_file_text: &str) -> Result<Self, KbConfigError> {
+        Self::from_layers(config_file_text, &[], &[])
+    }
 
-        let json_val = json_file["max_instances"].as_u32();
-        let max_render_instances = json_val.unwrap_or(10000);
+    // Layers the config file over the built-in defaults, then KB_* environment variables and
+    // --key=value command line arguments over the file
+    pub fn load_layered(config_file_text: &str) -> Result<Self, KbConfigError> {
+        let env_vars = std::env::vars().collect::<Vec<(String, String)>>();
+        let args = std::env::args().skip(1).collect::<Vec<String>>();
+        Self::from_layers(config_file_text, &env_vars, &args)
+    }
 
-        let json_val = json_file["window_width"].as_u32();
-        let window_width: u32 = json_val.unwrap_or(1280);
+    pub fn from_layers(
+        config_file_text: &str,
+        env_vars: &[(String, String)],
+        args: &[String],
+    ) -> Result<Self, KbConfigError> {
+        let json_file = parse_config_text(config_file_text)?;
 
-        let json_val = json_file["window_height"].as_u32();
-        let window_height: u32 = json_val.unwrap_or(720);
+        let mut config = KbConfig::default();
+        for (key, value) in flatten_config_values(&json_file) {
+            let line = find_key_line(config_file_text, &key);
+            config.apply_layer_value(&key, &value, KbConfigSource::File, line)?;
+        }
 
-        let graphics_backend = {
-            #[cfg(target_arch = "wasm32")]
-            {
-                wgpu::Backends::GL
+        // Section keys use a double underscore in the environment, e.g. KB_GAME__ENEMY_SPAWN_DELAY
+        for (name, value) in env_vars {
+            if let Some(key) = name.strip_prefix("KB_") {
+                let key = key.to_lowercase().replace("__", ".");
+                let value = parse_layer_value(value);
+                config.apply_layer_value(&key, &value, KbConfigSource::Environment, None)?;
             }
+        }
 
-            #[cfg(not(target_arch = "wasm32"))]
-            {
-                let json_val = json_file["graphics_back_end"].as_str();
-                match json_val {
-                    Some(val) => match val {
-                        "dx12" => wgpu::Backends::DX12,
-                        "webgpu" => wgpu::Backends::BROWSER_WEBGPU,
-                        "vulkan" => wgpu::Backends::VULKAN,
-                        "gl" => wgpu::Backends::GL,
-                        _ => wgpu::Backends::all(),
-                    },
-                    None => wgpu::Backends::BROWSER_WEBGPU,
+        for arg in args {
+            if let Some((key, value)) = arg.strip_prefix("--").and_then(|a| a.split_once('=')) {
+                let value = parse_layer_value(value);
+                config.apply_layer_value(key, &value, KbConfigSource::CommandLine, None)?;
+            }
+        }
+
+        config.file_text = config_file_text.to_string();
+        config.file_values = json_file;
+        config.check_clip_planes()?;
+        Ok(config)
+    }
+
+    // Native builds poll file_path for changes.  Call reload_if_changed() to pick them up
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn watch_file(&mut self, file_path: &str) {
+        self.file_watch = Some(KbConfigWatch {
+            file_path: file_path.to_string(),
+            last_modified: None,
+            next_check_time: 0.0,
+        });
+    }
+
+    // Web builds have no file to poll
+    #[cfg(target_arch = "wasm32")]
+    pub fn watch_file(&mut self, _file_path: &str) {}
+
+    // Re-parses the watched file if it was modified and applies the values that changed.  Values
+    // supplied by user settings, the environment or the command line keep priority over the file
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn reload_if_changed(&mut self) -> Option<KbConfigDiff> {
+        let cur_time = kb_time_since(self.start_time);
+        let file_watch = self.file_watch.as_mut()?;
+        if cur_time < file_watch.next_check_time {
+            return None;
+        }
+        file_watch.next_check_time = cur_time + 0.5;
+
+        let modified = std::fs::metadata(&file_watch.file_path)
+            .and_then(|metadata| metadata.modified())
+            .ok()?;
+        if file_watch.last_modified == Some(modified) {
+            return None;
+        }
+        file_watch.last_modified = Some(modified);
+
+        let file_path = file_watch.file_path.clone();
+        let config_file_text = match std::fs::read_to_string(&file_path) {
+            Ok(text) => text,
+            Err(error) => {
+                log!("KbConfig - Failed to read {} - {}", file_path, error);
+                return None;
+            }
+        };
+        let json_file = match parse_config_text(&config_file_text) {
+            Ok(json_file) => json_file,
+            Err(error) => {
+                log!("KbConfig - Ignoring changes to {} - {}", file_path, error);
+                return None;
+            }
+        };
+
+        let old_values = flatten_config_values(&self.file_values);
+        let new_values = flatten_config_values(&json_file);
+
+        let clip_planes = (self.near_plane, self.far_plane);
+        let mut diff = KbConfigDiff::default();
+        for (key, value) in &new_values {
+            if old_values.iter().any(|(k, v)| k == key && v == value) {
+                continue;
+            }
+            if self.get_value_source(key) > KbConfigSource::File {
+                continue;
+            }
+
+            let line = find_key_line(&config_file_text, key);
+            match self.apply_layer_value(key, value, KbConfigSource::File, line) {
+                Ok(_) => diff.changed_keys.push(key.to_string()),
+                Err(error) => {
+                    log!("KbConfig - Ignoring change in {} - {}", file_path, error);
                 }
             }
+        }
+
+        for (key, _) in &old_values {
+            if !new_values.iter().any(|(k, _)| k == key) {
+                log!(
+                    "KbConfig - \"{}\" was removed from {}.  Its current value is kept until restart",
+                    key,
+                    file_path
+                );
+            }
+        }
+
+        self.file_text = config_file_text;
+        self.file_values = json_file;
+        if let Err(error) = self.check_clip_planes() {
+            log!("KbConfig - Ignoring change in {} - {}", file_path, error);
+            (self.near_plane, self.far_plane) = clip_planes;
+            diff.changed_keys
+                .retain(|key| key != "near_plane" && key != "far_plane");
+        }
+        if diff.is_empty() {
+            None
+        } else {
+            Some(diff)
+        }
+    }
+
+    // Layers the user settings saved next to config_file_path (game_config.txt ->
+    // game_config_user.txt) over the file.  The environment and command line still take priority.
+    // A missing overlay is not an error.  save() writes the overlay back to the same place
+    pub fn load_user_settings(&mut self, config_file_path: &str) -> Result<(), KbConfigError> {
+        let user_settings_path = make_user_settings_path(config_file_path);
+        self.user_settings_path = Some(user_settings_path.clone());
+
+        let json_user_settings = match read_user_settings(&user_settings_path)? {
+            Some(json_user_settings) => json_user_settings,
+            None => return Ok(()),
         };
 
-        let json_val = json_file["graphics_power_pref"].as_str();
-        let graphics_power_pref = match json_val {
-            Some(val) => match val {
-                "high" => wgpu::PowerPreference::HighPerformance,
-                "low" => wgpu::PowerPreference::LowPower,
-                _ => wgpu::PowerPreference::None,
-            },
-            None => wgpu::PowerPreference::None,
+        for key in USER_SETTINGS_KEYS {
+            let value = &json_user_settings[*key];
+            if value.is_null() || self.get_value_source(key) > KbConfigSource::UserSettings {
+                continue;
+            }
+            self.apply_layer_value(key, value, KbConfigSource::UserSettings, None)?;
+        }
+
+        Ok(())
+    }
+
+    // Writes the player-changeable settings to the user settings overlay.  The base config file is
+    // never touched.  Other keys already in the overlay are kept, as are its values for keys
+    // currently overridden by the environment or command line.  An overlay that can't be read or
+    // parsed is left alone and reported rather than replaced
+    pub fn save(&self) -> Result<(), KbConfigError> {
+        let user_settings_path = self
+            .user_settings_path
+            .as_ref()
+            .ok_or(KbConfigError::NoUserSettingsPath)?;
+
+        let mut json_user_settings =
+            read_user_settings(user_settings_path)?.unwrap_or_else(json::JsonValue::new_object);
+
+        for key in USER_SETTINGS_KEYS {
+            if self.get_value_source(key) > KbConfigSource::UserSettings {
+                continue;
+            }
+            json_user_settings[*key] = self.get_user_setting(key);
+        }
+
+        std::fs::write(user_settings_path, json_user_settings.pretty(4)).map_err(|error| {
+            KbConfigError::Io {
+                path: user_settings_path.clone(),
+                message: error.to_string(),
+            }
+        })
+    }
+
+    pub fn get_user_settings_path(&self) -> Option<&str> {
+        self.user_settings_path.as_deref()
+    }
+
+    fn get_user_setting(&self, key: &str) -> json::JsonValue {
+        match key {
+            "window_width" => self.window_width.into(),
+            "window_height" => self.window_height.into(),
+            "vsync" => self.vsync.into(),
+            "mouse_sensitivity" => self.mouse_sensitivity.into(),
+            "mouse_invert_y" => self.mouse_invert_y.into(),
+            "postprocess_mode" => match self.postprocess_mode {
+                KbPostProcessMode::Passthrough => "passthrough",
+                KbPostProcessMode::Desaturation => "desaturation",
+                KbPostProcessMode::ScanLines => "scan_lines",
+                KbPostProcessMode::Warp => "warp",
+            }
+            .into(),
+            "graphics_power_pref" => match self.graphics_power_pref {
+                wgpu::PowerPreference::None => "default",
+                wgpu::PowerPreference::HighPerformance => "high",
+                wgpu::PowerPreference::LowPower => "low",
+            }
+            .into(),
+            _ => json::JsonValue::Null,
+        }
+    }
+
+    // Returns which layer supplied the current value of key
+    pub fn get_value_source(&self, key: &str) -> KbConfigSource {
+        match self.value_sources.get(key) {
+            Some(source) => *source,
+            None => KbConfigSource::Default,
+        }
+    }
+
+    // Reads key from a game-defined section.  Returns Ok(None) if the section or key is missing
+    pub fn get_value<T: KbConfigValue>(
+        &self,
+        section: &str,
+        key: &str,
+    ) -> Result<Option<T>, KbConfigError> {
+        let value = match self.sections.get(section) {
+            Some(section_values) => &section_values[key],
+            None => return Ok(None),
         };
+        if value.is_null() {
+            return Ok(None);
+        }
 
-        let json_val = json_file["vsync"].as_bool();
-        let vsync = json_val.unwrap_or(true);
+        match T::from_config_value(value) {
+            Ok(value) => Ok(Some(value)),
+            Err(expected) => Err(self.section_error(section, key, expected)),
+        }
+    }
 
-        let sunbeams_enabled = json_file["sunbeams"].as_bool().unwrap_or(false);
-        let sun_beam_pos_scale = {
-            if json_file["sun_beam_pos_scale"].is_array() {
-                let x = json_file["sun_beam_pos_scale"].pop().as_f32().unwrap();
-                let y = json_file["sun_beam_pos_scale"].pop().as_f32().unwrap();
-                let z = json_file["sun_beam_pos_scale"].pop().as_f32().unwrap();
-                let w = json_file["sun_beam_pos_scale"].pop().as_f32().unwrap();
-                CgVec4::new(x, y, z, w)
-            } else {
-                CgVec4::new(500.0, 550.0, 500.0, 1550.0)
+    pub fn get_value_or<T: KbConfigValue>(
+        &self,
+        section: &str,
+        key: &str,
+        default_value: T,
+    ) -> Result<T, KbConfigError> {
+        Ok(self.get_value(section, key)?.unwrap_or(default_value))
+    }
+
+    // Builds a game-owned settings struct from section.  Missing keys keep T::default() values
+    pub fn get_section<T: KbConfigSection>(&self, section: &str) -> Result<T, KbConfigError> {
+        let mut section_values = T::default();
+        let json_section = match self.sections.get(section) {
+            Some(json_section) => json_section,
+            None => return Ok(section_values),
+        };
+
+        for (key, value) in json_section.entries() {
+            if key.starts_with('_') {
+                continue;
+            }
+
+            match section_values.apply_value(key, value) {
+                Ok(true) => {}
+                Ok(false) => {
+                    log!(
+                        "KbConfig - Unknown key \"{}\" in section \"{}\"",
+                        key,
+                        section
+                    );
+                }
+                Err(expected) => return Err(self.section_error(section, key, expected)),
             }
+        }
+
+        Ok(section_values)
+    }
+
+    fn section_error(&self, section: &str, key: &str, expected: &'static str) -> KbConfigError {
+        self.value_error(&format!("{}.{}", section, key), expected)
+    }
+
+    // Reports key's current value as wrong, pointing at the layer it came from
+    fn value_error(&self, key: &str, expected: &'static str) -> KbConfigError {
+        let source = self.get_value_source(key);
+        let line = if source == KbConfigSource::File {
+            find_key_line(&self.file_text, key)
+        } else {
+            None
         };
 
-        let json_val = json_file["bullet_holes"].as_bool();
-        let bullet_holes = json_val.unwrap_or_default();
+        KbConfigError::WrongType {
+            key: key.to_string(),
+            source,
+            line,
+            expected,
+        }
+    }
 
-        KbConfig {
-            enemy_spawn_delay,
-            enemy_move_speed,
-            max_render_instances,
-            window_width,
-            window_height,
-            fov: 75.0,
-            foreground_fov: 50.0,
-            graphics_backend,
-            graphics_power_pref,
-            vsync,
+    fn check_clip_planes(&self) -> Result<(), KbConfigError> {
+        if self.near_plane < self.far_plane {
+            return Ok(());
+        }
 
-            start_time: instant::Instant::now(),
-            delta_time: 0.0,
-            last_frame_time: 0.0,
-            postprocess_mode: KbPostProcessMode::Passthrough,
-            sunbeams_enabled,
-            clear_color: CG_VEC4_ZERO,
-            sun_color: CgVec4::new(1.0, 1.0, 1.0, 1.0),
-            sun_beam_pos_scale,
-            bullet_holes,
+        // Blame whichever plane came from the later layer
+        if self.get_value_source("near_plane") >= self.get_value_source("far_plane") {
+            Err(self.value_error("near_plane", "a number less than far_plane"))
+        } else {
+            Err(self.value_error("far_plane", "a number greater than near_plane"))
+        }
+    }
+
+    fn apply_layer_value(
+        &mut self,
+        key: &str,
+        value: &json::JsonValue,
+        source: KbConfigSource,
+        line: Option<usize>,
+    ) -> Result<(), KbConfigError> {
+        let applied = match key.split_once('.') {
+            Some((section, section_key)) => {
+                let section_values = self
+                    .sections
+                    .entry(section.to_string())
+                    .or_insert_with(json::JsonValue::new_object);
+                section_values[section_key] = value.clone();
+                Ok(true)
+            }
+            None => self.apply_value(key, value),
+        };
+
+        match applied {
+            Ok(true) => {
+                self.value_sources.insert(key.to_string(), source);
+            }
+            Ok(false) => match line {
+                Some(line) => {
+                    log!("KbConfig - Unknown key \"{}\" on line {}", key, line);
+                }
+                None => {
+                    log!("KbConfig - Unknown key \"{}\" from {:?}", key, source);
+                }
+            },
+            Err(expected) => {
+                return Err(KbConfigError::WrongType {
+                    key: key.to_string(),
+                    source,
+                    line,
+                    expected,
+                })
+            }
+        }
+
+        Ok(())
+    }
+
+    // Returns Ok(false) if the key is unknown and Err(expected type) if the value can't be used
+    fn apply_value(&mut self, key: &str, value: &json::JsonValue) -> Result<bool, &'static str> {
+        match key {
+            "max_instances" => self.max_render_instances = u32::from_config_value(value)?,
+            "window_width" => self.window_width = u32::from_config_value(value)?,
+            "window_height" => self.window_height = u32::from_config_value(value)?,
+            "graphics_back_end" => {
+                const EXPECTED: &str =
+                    "one of \"default\", \"dx12\", \"webgpu\", \"vulkan\" or \"gl\"";
+                let graphics_backend = match value.as_str().ok_or(EXPECTED)? {
+                    "default" => wgpu::Backends::all(),
+                    "dx12" => wgpu::Backends::DX12,
+                    "webgpu" => wgpu::Backends::BROWSER_WEBGPU,
+                    "vulkan" => wgpu::Backends::VULKAN,
+                    "gl" => wgpu::Backends::GL,
+                    _ => return Err(EXPECTED),
+                };
+
+                // Web builds always run on GL
+                if cfg!(not(target_arch = "wasm32")) {
+                    self.graphics_backend = graphics_backend;
+                }
+            }
+            "graphics_power_pref" => {
+                const EXPECTED: &str = "one of \"default\", \"low\" or \"high\"";
+                self.graphics_power_pref = match value.as_str().ok_or(EXPECTED)? {
+                    "default" => wgpu::PowerPreference::None,
+                    "high" => wgpu::PowerPreference::HighPerformance,
+                    "low" => wgpu::PowerPreference::LowPower,
+                    _ => return Err(EXPECTED),
+                };
+            }
+            "fov" => self.fov = f32::from_config_value(value)?,
+            "foreground_fov" => self.foreground_fov = f32::from_config_value(value)?,
+            // Whether near_plane is less than far_plane is checked once every layer is applied
+            "near_plane" | "far_plane" => {
+                const EXPECTED: &str = "a number greater than 0";
+                let plane = f32::from_config_value(value).map_err(|_| EXPECTED)?;
+                if plane <= 0.0 {
+                    return Err(EXPECTED);
+                }
+                if key == "near_plane" {
+                    self.near_plane = plane;
+                } else {
+                    self.far_plane = plane;
+                }
+            }
+            "vsync" => self.vsync = bool::from_config_value(value)?,
+            "mouse_sensitivity" => self.mouse_sensitivity = f32::from_config_value(value)?,
+            "mouse_invert_y" => self.mouse_invert_y = bool::from_config_value(value)?,
+            "fixed_time_step" => {
+                const EXPECTED: &str = "a number of seconds, or 0 for a variable step";
+                let fixed_time_step = f32::from_config_value(value).map_err(|_| EXPECTED)?;
+                if fixed_time_step < 0.0 {
+                    return Err(EXPECTED);
+                }
+                self.fixed_time_step = fixed_time_step;
+            }
+            "max_delta_time" => {
+                const EXPECTED: &str = "a number of seconds greater than 0";
+                let max_delta_time = f32::from_config_value(value).map_err(|_| EXPECTED)?;
+                if max_delta_time <= 0.0 {
+                    return Err(EXPECTED);
+                }
+                self.max_delta_time = max_delta_time;
+            }
+            "time_scale" => {
+                const EXPECTED: &str = "a number greater than or equal to 0";
+                let time_scale = f32::from_config_value(value).map_err(|_| EXPECTED)?;
+                if time_scale < 0.0 {
+                    return Err(EXPECTED);
+                }
+                self.time_scale = time_scale;
+            }
+            "paused" => self.paused = bool::from_config_value(value)?,
+            "input_record_file" => self.input_record_file = String::from_config_value(value)?,
+            "input_playback_file" => self.input_playback_file = String::from_config_value(value)?,
+            "postprocess_mode" => {
+                const EXPECTED: &str =
+                    "one of \"passthrough\", \"desaturation\", \"scan_lines\" or \"warp\"";
+                self.postprocess_mode = match value.as_str().ok_or(EXPECTED)? {
+                    "passthrough" => KbPostProcessMode::Passthrough,
+                    "desaturation" => KbPostProcessMode::Desaturation,
+                    "scan_lines" => KbPostProcessMode::ScanLines,
+                    "warp" => KbPostProcessMode::Warp,
+                    _ => return Err(EXPECTED),
+                };
+            }
+            "sunbeams" => self.sunbeams_enabled = bool::from_config_value(value)?,
+            "clear_color" => self.clear_color = CgVec4::from_config_value(value)?,
+            "sun_color" => self.sun_color = CgVec4::from_config_value(value)?,
+            "sun_beam_pos_scale" => self.sun_beam_pos_scale = CgVec4::from_config_value(value)?,
+            "bullet_holes" => self.bullet_holes = bool::from_config_value(value)?,
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    // Advances the frame clock and returns how many simulation steps to run this frame, each
+    // delta_time seconds long.  Paused games get a single step with a delta_time of 0 so they can
+    // still read input
+    pub fn update_frame_times(&mut self) -> u32 {
+        let frame_time = kb_time_now();
+        self.real_delta_time = (frame_time - self.last_frame_time) as f32;
+        self.last_frame_time = frame_time;
+
+        if self.paused {
+            self.delta_time = 0.0;
+            return 1;
+        }
+
+        let frame_time = self.real_delta_time.min(self.max_delta_time) * self.time_scale;
+        if self.fixed_time_step <= 0.0 {
+            self.delta_time = frame_time;
+            return 1;
+        }
+
+        self.time_accumulator += frame_time;
+        let num_steps = (self.time_accumulator / self.fixed_time_step) as u32;
+        self.time_accumulator -= num_steps as f32 * self.fixed_time_step;
+        self.delta_time = self.fixed_time_step;
+        num_steps
+    }
+
+    // How far the simulation is between its last step and the next one, from 0 to 1.  Useful for
+    // interpolating positions when rendering with a fixed time step
+    pub fn get_step_alpha(&self) -> f32 {
+        if self.fixed_time_step <= 0.0 || self.paused {
+            return 1.0;
+        }
+        self.time_accumulator / self.fixed_time_step
+    }
+}
+
+// Settings the player can change in-game.  These are the only keys save() writes
+const USER_SETTINGS_KEYS: &[&str] = &[
+    "window_width",
+    "window_height",
+    "vsync",
+    "mouse_sensitivity",
+    "mouse_invert_y",
+    "postprocess_mode",
+    "graphics_power_pref",
+];
+
+fn make_user_settings_path(config_file_path: &str) -> String {
+    let config_path = std::path::Path::new(config_file_path);
+    let file_stem = config_path
+        .file_stem()
+        .and_then(|file_stem| file_stem.to_str())
+        .unwrap_or("game_config");
+    config_path
+        .with_file_name(format!("{}_user.txt", file_stem))
+        .to_string_lossy()
+        .to_string()
+}
+
+// Flattens the file into (key, value) pairs.  Object values are game sections whose keys
+// become "section.key".  Keys beginning with an underscore are comments and are skipped
+fn flatten_config_values(json_file: &json::JsonValue) -> Vec<(String, json::JsonValue)> {
+    let mut values = Vec::<(String, json::JsonValue)>::new();
+    for (key, value) in json_file.entries() {
+        if key.starts_with('_') {
+            continue;
         }
+
+        if value.is_object() {
+            for (section_key, section_value) in value.entries() {
+                if !section_key.starts_with('_') {
+                    values.push((format!("{}.{}", key, section_key), section_value.clone()));
+                }
+            }
+        } else {
+            values.push((key.to_string(), value.clone()));
+        }
+    }
+    values
+}
+
+// Reads the user settings overlay.  A missing overlay is Ok(None); any other read failure or
+// a file that doesn't parse is an error naming the overlay's path
+fn read_user_settings(user_settings_path: &str) -> Result<Option<json::JsonValue>, KbConfigError> {
+    let io_error = |message: String| KbConfigError::Io {
+        path: user_settings_path.to_string(),
+        message,
+    };
+    let user_settings_text = match std::fs::read_to_string(user_settings_path) {
+        Ok(text) => text,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(io_error(error.to_string())),
+    };
+    parse_config_text(&user_settings_text)
+        .map(Some)
+        .map_err(|error| io_error(error.to_string()))
+}
+
+pub(crate) fn parse_config_text(config_file_text: &str) -> Result<json::JsonValue, KbConfigError> {
+    let json_file = json::parse(config_file_text).map_err(|error| match error {
+        json::Error::UnexpectedCharacter { ch, line, column } => KbConfigError::Parse {
+            line,
+            column,
+            message: format!("Unexpected character '{}'", ch),
+        },
+        _ => {
+            let line = config_file_text.lines().count().max(1);
+            KbConfigError::Parse {
+                line,
+                column: 1,
+                message: error.to_string(),
+            }
+        }
+    })?;
+
+    if !json_file.is_object() {
+        return Err(KbConfigError::Parse {
+            line: 1,
+            column: 1,
+            message: "Expected a JSON object".to_string(),
+        });
+    }
+
+    Ok(json_file)
+}
+
+// Environment and command line values are JSON when they parse as JSON and plain strings otherwise,
+// so --vsync=false and --graphics_back_end=vulkan both work
+fn parse_layer_value(value: &str) -> json::JsonValue {
+    match json::parse(value) {
+        Ok(json_value) => json_value,
+        Err(_) => json::JsonValue::from(value),
+    }
+}
+
+// Returns the 1-based line number on which key is first declared.  Section keys ("section.key")
+// are searched for after the line that opens the section
+pub(crate) fn find_key_line(config_file_text: &str, key: &str) -> Option<usize> {
+    let (start_line, key) = match key.split_once('.') {
+        Some((section, section_key)) => (find_key_line(config_file_text, section)?, section_key),
+        None => (0, key),
+    };
+
+    let quoted_key = format!("\"{}\"", key);
+    for (line_index, line) in config_file_text.lines().enumerate().skip(start_line) {
+        if let Some(key_pos) = line.find(&quoted_key) {
+            let remainder = &line[key_pos + quoted_key.len()..];
+            if remainder.trim_start().starts_with(':') {
+                return Some(line_index + 1);
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    const CONFIG_TEXT: &str = r#"{
+    "window_width": 800,
+    "max_instances": 6000,
+    "vsync": true,
+    "bullet_holes": true,
+    "_comment": "Not a key",
+    "game": {
+        "speed": 1.0,
+        "name": "level 1"
     }
+}"#;
+
+    fn env_vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    // Unique to this test run so parallel runs don't share files
+    fn temp_path(name: &str) -> String {
+        let file_name = format!("kb_config_{}_{}", std::process::id(), name);
+        std::env::temp_dir()
+            .join(file_name)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn later_layers_override_earlier_ones() {
+        let config = KbConfig::from_layers(
+            CONFIG_TEXT,
+            &env_vars(&[
+                ("KB_WINDOW_WIDTH", "1024"),
+                ("KB_MAX_INSTANCES", "7000"),
+                ("KB_GAME__SPEED", "2.5"),
+                ("PATH", "/usr/bin"),
+            ]),
+            &args(&[
+                "--window_width=1920",
+                "--vsync=false",
+                "--graphics_power_pref=high",
+                "--not_a_pair",
+                "positional",
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(config.window_width, 1920);
+        assert_eq!(config.max_render_instances, 7000);
+        assert!(!config.vsync);
+        assert!(config.bullet_holes);
+        assert_eq!(config.window_height, 720);
+        assert_eq!(
+            config.graphics_power_pref,
+            wgpu::PowerPreference::HighPerformance
+        );
+        assert_eq!(config.get_value::<f32>("game", "speed"), Ok(Some(2.5)));
+        assert_eq!(
+            config.get_value::<String>("game", "name"),
+            Ok(Some("level 1".to_string()))
+        );
+        assert_eq!(config.get_value::<f32>("game", "missing"), Ok(None));
+        assert_eq!(config.get_value_or("other", "speed", 3.0), Ok(3.0));
+
+        let sources = [
+            ("window_width", KbConfigSource::CommandLine),
+            ("vsync", KbConfigSource::CommandLine),
+            ("max_instances", KbConfigSource::Environment),
+            ("game.speed", KbConfigSource::Environment),
+            ("game.name", KbConfigSource::File),
+            ("bullet_holes", KbConfigSource::File),
+            ("window_height", KbConfigSource::Default),
+            ("_comment", KbConfigSource::Default),
+        ];
+        for (key, source) in sources {
+            assert_eq!(config.get_value_source(key), source, "{}", key);
+        }
+    }
+
+    #[test]
+    fn errors_say_where_the_bad_value_came_from() {
+        let error = KbConfig::try_from_str("{\n    \"vsync\": true,\n    oops\n}").err();
+        assert!(
+            matches!(error, Some(KbConfigError::Parse { line: 3, .. })),
+            "{:?}",
+            error
+        );
+        assert!(KbConfig::try_from_str("[1, 2]").is_err());
+
+        let bad_file = CONFIG_TEXT.replace("\"vsync\": true", "\"vsync\": \"yes\"");
+        let error = KbConfig::try_from_str(&bad_file).err().unwrap();
+        assert_eq!(
+            error,
+            KbConfigError::WrongType {
+                key: "vsync".to_string(),
+                source: KbConfigSource::File,
+                line: Some(4),
+                expected: "true or false",
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "\"vsync\" on line 4 should be true or false"
+        );
+
+        let error =
+            KbConfig::from_layers(CONFIG_TEXT, &env_vars(&[("KB_MAX_INSTANCES", "lots")]), &[]);
+        assert_eq!(
+            error.err().unwrap().to_string(),
+            "KB_MAX_INSTANCES environment variable should be a positive integer"
+        );
+        let error = KbConfig::from_layers(CONFIG_TEXT, &[], &args(&["--window_width=-5"]));
+        assert_eq!(
+            error.err().unwrap().to_string(),
+            "--window_width argument should be a positive integer"
+        );
+
+        // Section values are only checked when the game reads them
+        let config =
+            KbConfig::from_layers(CONFIG_TEXT, &env_vars(&[("KB_GAME__NAME", "7")]), &[]).unwrap();
+        assert_eq!(
+            config
+                .get_value::<bool>("game", "speed")
+                .err()
+                .unwrap()
+                .to_string(),
+            "\"game.speed\" on line 8 should be true or false"
+        );
+        assert_eq!(
+            config
+                .get_value::<String>("game", "name")
+                .err()
+                .unwrap()
+                .to_string(),
+            "KB_GAME__NAME environment variable should be a string"
+        );
+    }
+
+    #[test]
+    fn clip_planes_are_checked_once_every_layer_is_applied() {
+        for config_text in [
+            r#"{ "far_plane": 0.05, "near_plane": 0.01 }"#,
+            r#"{ "near_plane": 20000, "far_plane": 50000 }"#,
+        ] {
+            let config = KbConfig::try_from_str(config_text).unwrap();
+            assert!(config.near_plane < config.far_plane, "{}", config_text);
+        }
+        let config = KbConfig::from_layers(
+            "{}",
+            &env_vars(&[("KB_NEAR_PLANE", "20000"), ("KB_FAR_PLANE", "50000")]),
+            &[],
+        )
+        .unwrap();
+        assert_eq!((config.near_plane, config.far_plane), (20000.0, 50000.0));
+
+        let error = KbConfig::try_from_str("{\n    \"near_plane\": 0\n}").err();
+        assert_eq!(
+            error.unwrap().to_string(),
+            "\"near_plane\" on line 2 should be a number greater than 0"
+        );
+        let error =
+            KbConfig::try_from_str("{\n    \"near_plane\": 600,\n    \"far_plane\": 500\n}");
+        assert_eq!(
+            error.err().unwrap().to_string(),
+            "\"near_plane\" on line 2 should be a number less than far_plane"
+        );
+
+        // The plane from the later layer is the one reported
+        let config_text = r#"{ "near_plane": 1.0, "far_plane": 500 }"#;
+        let error = KbConfig::from_layers(config_text, &[], &args(&["--near_plane=600"]));
+        assert_eq!(
+            error.err().unwrap().to_string(),
+            "--near_plane argument should be a number less than far_plane"
+        );
+        let error = KbConfig::from_layers(config_text, &env_vars(&[("KB_FAR_PLANE", "0.5")]), &[]);
+        assert_eq!(
+            error.err().unwrap().to_string(),
+            "KB_FAR_PLANE environment variable should be a number greater than near_plane"
+        );
+    }
+
+    #[test]
+    fn user_settings_round_trip() {
+        let config_path = temp_path("round_trip.txt");
+        let user_settings_path = temp_path("round_trip_user.txt");
+        let _ = std::fs::remove_file(&user_settings_path);
+
+        let mut config = KbConfig::try_from_str(CONFIG_TEXT).unwrap();
+        assert_eq!(config.save(), Err(KbConfigError::NoUserSettingsPath));
+
+        // A missing overlay is fine
+        config.load_user_settings(&config_path).unwrap();
+        assert_eq!(
+            config.get_user_settings_path(),
+            Some(user_settings_path.as_str())
+        );
+        config.window_width = 1600;
+        config.vsync = false;
+        config.postprocess_mode = KbPostProcessMode::Warp;
+        config.max_render_instances = 9000;
+        config.save().unwrap();
+
+        let mut loaded = KbConfig::try_from_str(CONFIG_TEXT).unwrap();
+        loaded.load_user_settings(&config_path).unwrap();
+        assert_eq!(loaded.window_width, 1600);
+        assert!(!loaded.vsync);
+        assert!(matches!(loaded.postprocess_mode, KbPostProcessMode::Warp));
+        assert_eq!(loaded.max_render_instances, 6000);
+        assert_eq!(
+            loaded.get_value_source("window_width"),
+            KbConfigSource::UserSettings
+        );
+
+        // The command line beats the overlay, and saving keeps the overlay's own value
+        let mut overridden =
+            KbConfig::from_layers(CONFIG_TEXT, &[], &args(&["--window_width=640"])).unwrap();
+        overridden.load_user_settings(&config_path).unwrap();
+        assert_eq!(overridden.window_width, 640);
+        assert!(!overridden.vsync);
+        overridden.vsync = true;
+        overridden.save().unwrap();
+
+        let mut reloaded = KbConfig::try_from_str(CONFIG_TEXT).unwrap();
+        reloaded.load_user_settings(&config_path).unwrap();
+        assert_eq!(reloaded.window_width, 1600);
+        assert!(reloaded.vsync);
+
+        // A broken overlay is reported by both loading and saving, and saving leaves it alone
+        std::fs::write(&user_settings_path, "{ not json").unwrap();
+        let load_error = reloaded.load_user_settings(&config_path);
+        let save_error = reloaded.save();
+        let overlay_text = std::fs::read_to_string(&user_settings_path).unwrap();
+        let _ = std::fs::remove_file(&user_settings_path);
+        assert!(matches!(load_error, Err(KbConfigError::Io { .. })));
+        assert!(matches!(save_error, Err(KbConfigError::Io { .. })));
+        assert_eq!(overlay_text, "{ not json");
+
+        // So is an overlay that exists but can't be read
+        std::fs::create_dir_all(&user_settings_path).unwrap();
+        let load_error = reloaded.load_user_settings(&config_path);
+        let save_error = reloaded.save();
+        let _ = std::fs::remove_dir(&user_settings_path);
+        assert!(matches!(load_error, Err(KbConfigError::Io { .. })));
+        assert!(matches!(save_error, Err(KbConfigError::Io { .. })));
+    }
+
+    #[test]
+    fn reload_reports_the_keys_that_changed() {
+        let clock = Rc::new(KbManualClock::new());
+        kb_set_clock(clock.clone());
+        let config_path = temp_path("reload.txt");
+        std::fs::write(&config_path, CONFIG_TEXT).unwrap();
+
+        let mut config = KbConfig::from_layers(CONFIG_TEXT, &[], &args(&["--vsync=true"])).unwrap();
+        config.watch_file(&config_path);
+        assert!(config.reload_if_changed().is_none());
+
+        let changed_text = CONFIG_TEXT
+            .replace("\"max_instances\": 6000", "\"max_instances\": 9000")
+            .replace("\"vsync\": true", "\"vsync\": false")
+            .replace("\"window_width\": 800", "\"window_width\": \"wide\"")
+            .replace("\"speed\": 1.0", "\"speed\": 2.0");
+        std::fs::write(&config_path, &changed_text).unwrap();
+        let modified = std::time::SystemTime::now() + std::time::Duration::from_secs(10);
+        let set_modified = std::fs::File::options()
+            .write(true)
+            .open(&config_path)
+            .and_then(|file| file.set_modified(modified));
+
+        // Files are only checked twice a second
+        assert!(config.reload_if_changed().is_none());
+        clock.advance(1.0);
+        let diff = config.reload_if_changed();
+        let _ = std::fs::remove_file(&config_path);
+        set_modified.unwrap();
+
+        let diff = diff.expect("The file changed");
+        let mut changed_keys = diff.changed_keys.clone();
+        changed_keys.sort();
+        assert_eq!(changed_keys, ["game.speed", "max_instances"]);
+        assert!(diff.contains("max_instances") && !diff.contains("vsync"));
+        assert_eq!(config.max_render_instances, 9000);
+        assert!(config.vsync);
+        assert_eq!(config.window_width, 800);
+        assert_eq!(config.get_value::<f32>("game", "speed"), Ok(Some(2.0)));
 
-    pub fn update_frame_times(&mut self) {
-        let elapsed_time = self.start_time.elapsed().as_secs_f32();
-        self.delta_time = elapsed_time - self.last_frame_time;
-        self.last_frame_time = elapsed_time;
+        clock.advance(1.0);
+        assert!(config.reload_if_changed().is_none());
     }
 }
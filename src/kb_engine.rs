@@ -5,6 +5,9 @@ pub trait KbGameEngine {
 
     fn get_game_objects(&self) -> &Vec<GameObject>;
 
+    // Called once after new().  Load input bindings here
+    fn initialize_input(&mut self, _input_manager: &mut KbInputManager) {}
+
     #[allow(async_fn_in_trait)]
     async fn initialize_world<'a>(
         &mut self,
@@ -19,15 +22,35 @@ pub trait KbGameEngine {
         input_manager: &mut KbInputManager,
         game_config: &mut KbConfig,
     ) {
-        game_config.update_frame_times();
-        self.tick_frame_internal(renderer, input_manager, game_config);
-        input_manager.update_key_states();
+        input_manager.begin_frame();
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(config_diff) = game_config.reload_if_changed() {
+            self.config_changed(&config_diff, game_config);
+        }
+
+        // Input edges are consumed by the first step.  When no step runs they carry over to the
+        // next frame so presses aren't lost
+        let num_steps = game_config.update_frame_times();
+        for step in 0..num_steps {
+            self.tick_frame_internal(renderer, input_manager, game_config);
+            if step == 0 {
+                input_manager.update_key_states();
+            }
+        }
+
+        renderer.set_ime_allowed(input_manager.is_text_input_active());
     }
 
+    // Called when a watched config file is edited.  game_config already holds the new values
+    fn config_changed(&mut self, _config_diff: &KbConfigDiff, _game_config: &KbConfig) {}
+
+    // Called once per simulation step.  game_config.delta_time is the length of the step.
+    // input_manager is mutable so games can start and stop text input
     fn tick_frame_internal(
         &mut self,
         renderer: &mut KbRenderer<'_>,
-        input_manager: &KbInputManager,
+        input_manager: &mut KbInputManager,
         game_config: &KbConfig,
     );
 }
@@ -1,8 +1,8 @@
-use instant::Instant;
 //use cgmath::InnerSpace;
 
 use crate::{
-    kb_assets::*, kb_config::*, kb_resource::*, kb_utils::*, render_groups::kb_model_group::*,
+    kb_assets::*, kb_clock::*, kb_config::*, kb_resource::*, kb_utils::*,
+    render_groups::kb_model_group::*,
 };
 
 static mut NEXT_ACTOR_ID: u32 = 0;
@@ -115,13 +115,42 @@ pub struct KbParticle {
     pub life_time: f32,
 }
 
+impl KbParticle {
+    // elapsed_time is seconds since the owning actor started.  Returns false once the particle's
+    // life is over
+    pub fn update(
+        &mut self,
+        elapsed_time: f32,
+        delta_time: f32,
+        start_color: &CgVec4,
+        end_color: &CgVec4,
+    ) -> bool {
+        if elapsed_time > self.start_time + self.life_time {
+            return false;
+        }
+
+        let t = ((elapsed_time - self.start_time) / self.life_time).clamp(0.0, 1.0);
+        self.velocity += self.acceleration * delta_time;
+        self.position += self.velocity * delta_time;
+
+        self.rotation += self.rotation_rate * delta_time;
+        self.scale = self.start_scale + (self.end_scale - self.start_scale) * t;
+        self.color = *start_color + (*end_color - *start_color) * t;
+        self.color.x = self.color.x.clamp(0.0, 999999.0);
+        self.color.y = self.color.y.clamp(0.0, 999999.0);
+        self.color.z = self.color.z.clamp(0.0, 999999.0);
+
+        true
+    }
+}
+
 #[allow(dead_code)]
 pub struct KbParticleActor {
     pub params: KbParticleParams,
     pub model: KbModel,
     pub transform: KbActorTransform,
     spawn_rate: f32,
-    start_time: Instant,
+    start_time: f64,
     next_spawn_time: f32,
     pub particles: Vec<KbParticle>,
     pub particle_handle: KbParticleHandle,
@@ -141,8 +170,8 @@ impl KbParticleActor {
             KbModel::new_particle(&params.texture_file, device_resources, asset_manager).await;
         let spawn_rate = kb_random_f32(params.min_start_spawn_rate, params.max_start_spawn_rate);
         let params = (*params).clone();
-        let start_time = instant::Instant::now();
-        let next_spawn_time = spawn_rate + start_time.elapsed().as_secs_f32();
+        let start_time = kb_time_now();
+        let next_spawn_time = spawn_rate;
         let particles = Vec::<KbParticle>::new();
         let transform = (*transform).clone();
 
@@ -160,7 +189,7 @@ impl KbParticleActor {
     }
 
     pub fn tick(&mut self, game_config: &KbConfig) {
-        let elapsed_time = self.start_time.elapsed().as_secs_f32();
+        let elapsed_time = kb_time_since(self.start_time);
         if self.params._min_actor_life > 0.0 && elapsed_time > self.params._min_actor_life {
             self.set_active(false);
             return;
@@ -200,26 +229,14 @@ impl KbParticleActor {
         }
 
         let delta_time = game_config.delta_time;
-
+        let params = &self.params;
         self.particles.retain_mut(|particle| {
-            if elapsed_time > particle.start_time + particle.life_time {
-                false
-            } else {
-                let t = ((elapsed_time - particle.start_time) / particle.life_time).clamp(0.0, 1.0);
-                particle.velocity += particle.acceleration * delta_time;
-                particle.position += particle.velocity * delta_time;
-
-                particle.rotation += particle.rotation_rate * delta_time;
-                particle.scale =
-                    particle.start_scale + (particle.end_scale - particle.start_scale) * t;
-                particle.color = self.params.start_color_0
-                    + (self.params.end_color_0 - self.params.start_color_0) * t;
-                particle.color.x = particle.color.x.clamp(0.0, 999999.0);
-                particle.color.y = particle.color.y.clamp(0.0, 999999.0);
-                particle.color.z = particle.color.z.clamp(0.0, 999999.0);
-
-                true
-            }
+            particle.update(
+                elapsed_time,
+                delta_time,
+                &params.start_color_0,
+                &params.end_color_0,
+            )
         });
     }
 
@@ -253,7 +270,7 @@ impl KbParticleActor {
         if active {
             let count = kb_random_u32(self.params.min_burst_count, self.params.max_burst_count);
 
-            self.start_time = Instant::now();
+            self.start_time = kb_time_now();
             for _ in 0..count {
                 let params = &self.params;
                 let position = kb_random_vec3(params.min_start_pos, params.max_start_pos);
@@ -282,7 +299,7 @@ impl KbParticleActor {
                     rotation_rate,
                     color,
                     life_time,
-                    start_time: self.start_time.elapsed().as_secs_f32(),
+                    start_time: kb_time_since(self.start_time),
                 };
                 self.particles.push(particle);
             }
@@ -498,8 +515,8 @@ pub struct GameObject {
     pub sprite_index: i32,
     pub uv_tiles: (f32, f32),
     pub anim_frame: i32,
-    pub life_start_time: Instant,
-    pub state_start_time: Instant,
+    pub life_start_time: f64,
+    pub state_start_time: f64,
     pub gravity_scale: f32,
     pub is_enemy: bool,
     pub random_val: f32,
@@ -526,8 +543,8 @@ impl GameObject {
             sprite_index,
             uv_tiles: (1.0, 1.0),
             anim_frame: 0,
-            life_start_time: Instant::now(),
-            state_start_time: Instant::now(),
+            life_start_time: kb_time_now(),
+            state_start_time: kb_time_now(),
             gravity_scale: 3.1,
             random_val: kb_random_f32(0.0, 1000.0),
             is_enemy: false,
@@ -536,7 +553,7 @@ impl GameObject {
 
     fn set_state(&mut self, next_state: GameObjectState) {
         self.object_state = next_state;
-        self.state_start_time = Instant::now();
+        self.state_start_time = kb_time_now();
     }
 
     fn update_movement(&mut self, delta_time: f32) {
@@ -555,7 +572,7 @@ impl GameObject {
 
         match self.object_state {
             GameObjectState::Running => {
-                let duration = self.state_start_time.elapsed().as_secs_f32() * 5.0;
+                let duration = kb_time_since(self.state_start_time) * 5.0;
                 self.anim_frame = 1 + (duration as i32) % 4;
             }
 
@@ -566,12 +583,12 @@ impl GameObject {
 
         match self.object_type {
             GameObjectType::Projectile => {
-                let duration = self.state_start_time.elapsed().as_secs_f32() * 15.0;
+                let duration = kb_time_since(self.state_start_time) * 15.0;
                 self.anim_frame = (duration as i32) % 3;
             }
 
             GameObjectType::Skybox => {
-                let duration = self.state_start_time.elapsed().as_secs_f32() * 1.2;
+                let duration = kb_time_since(self.state_start_time) * 1.2;
                 self.anim_frame = (duration as i32) % 2;
             }
 
@@ -627,7 +644,7 @@ impl GameObject {
     }
 
     pub fn start_attack(&mut self) -> bool {
-        let cur_time = self.life_start_time.elapsed().as_secs_f32();
+        let cur_time = kb_time_since(self.life_start_time);
         if self.next_attack_time > cur_time {
             return false;
         }
@@ -636,3 +653,108 @@ impl GameObject {
         true
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    // Starts the engine clock hours in so the tests also cover precision late in a session
+    fn use_manual_clock() -> Rc<KbManualClock> {
+        let clock = Rc::new(KbManualClock::new());
+        clock.set_time(100000.0);
+        kb_set_clock(clock.clone());
+        clock
+    }
+
+    fn new_particle(life_time: f32) -> KbParticle {
+        KbParticle {
+            position: CG_VEC3_ZERO,
+            acceleration: CG_VEC3_ZERO,
+            velocity: CG_VEC3_ZERO,
+            color: CG_VEC4_ONE,
+            scale: CG_VEC3_ONE,
+            rotation: 0.0,
+            rotation_rate: 0.0,
+            start_time: 0.0,
+            start_scale: CG_VEC3_ONE,
+            end_scale: CG_VEC3_ONE,
+            life_time,
+        }
+    }
+
+    #[test]
+    fn particle_expires_after_its_life_time() {
+        let clock = use_manual_clock();
+        let actor_start_time = kb_time_now();
+        let mut particle = new_particle(1.0);
+
+        clock.advance(0.5);
+        assert!(particle.update(
+            kb_time_since(actor_start_time),
+            0.5,
+            &CG_VEC4_ONE,
+            &CG_VEC4_ZERO
+        ));
+        assert_eq!(particle.color, CgVec4::new(0.5, 0.5, 0.5, 0.5));
+
+        clock.advance(0.499);
+        assert!(particle.update(
+            kb_time_since(actor_start_time),
+            0.499,
+            &CG_VEC4_ONE,
+            &CG_VEC4_ZERO
+        ));
+
+        clock.advance(0.002);
+        assert!(!particle.update(
+            kb_time_since(actor_start_time),
+            0.002,
+            &CG_VEC4_ONE,
+            &CG_VEC4_ZERO
+        ));
+    }
+
+    #[test]
+    fn running_animation_follows_the_clock() {
+        let clock = use_manual_clock();
+        let mut character = GameObject::new(
+            GameObjectType::Character,
+            0,
+            CG_VEC3_ZERO,
+            CG_VEC3_ZERO,
+            CG_VEC3_ONE,
+        );
+        character.set_velocity(CgVec3::new(1.0, 0.0, 0.0));
+
+        // Five frames a second, cycling through frames 1 to 4
+        let mut anim_frames = Vec::<i32>::new();
+        for _ in 0..6 {
+            character.update(0.0);
+            anim_frames.push(character.anim_frame);
+            clock.advance(0.25);
+        }
+        assert_eq!(anim_frames, vec![1, 2, 3, 4, 2, 3]);
+    }
+
+    #[test]
+    fn projectile_animation_follows_the_clock() {
+        let clock = use_manual_clock();
+        let mut projectile = GameObject::new(
+            GameObjectType::Projectile,
+            0,
+            CG_VEC3_ZERO,
+            CG_VEC3_ZERO,
+            CG_VEC3_ONE,
+        );
+
+        // Fifteen frames a second, cycling through frames 0 to 2
+        let mut anim_frames = Vec::<i32>::new();
+        for _ in 0..4 {
+            projectile.update(0.0);
+            anim_frames.push(projectile.anim_frame);
+            clock.advance(0.125);
+        }
+        assert_eq!(anim_frames, vec![0, 1, 0, 2]);
+    }
+}
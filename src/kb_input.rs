@@ -1,17 +1,382 @@
-use std::collections::HashMap;
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    fmt,
+    io::{BufWriter, Write},
+    rc::Rc,
+};
 
 use winit::{
-    event::{ElementState, MouseButton},
-    keyboard::{KeyCode, PhysicalKey},
+    event::{ElementState, Ime, MouseButton, TouchPhase},
+    keyboard::{Key, KeyCode, NamedKey, PhysicalKey},
+};
+
+use crate::{
+    kb_clock::*,
+    kb_config::{find_key_line, parse_config_text, KbConfig, KbConfigError},
+    kb_utils::kb_seed_random,
 };
 
-#[derive(Clone, Debug, Default, Eq, PartialEq)]
+const KEY_NAMES: &[(KeyCode, &str)] = &[
+    (KeyCode::Backquote, "backquote"),
+    (KeyCode::Backslash, "backslash"),
+    (KeyCode::BracketLeft, "left_bracket"),
+    (KeyCode::BracketRight, "right_bracket"),
+    (KeyCode::Comma, "comma"),
+    (KeyCode::Digit0, "0"),
+    (KeyCode::Digit1, "1"),
+    (KeyCode::Digit2, "2"),
+    (KeyCode::Digit3, "3"),
+    (KeyCode::Digit4, "4"),
+    (KeyCode::Digit5, "5"),
+    (KeyCode::Digit6, "6"),
+    (KeyCode::Digit7, "7"),
+    (KeyCode::Digit8, "8"),
+    (KeyCode::Digit9, "9"),
+    (KeyCode::Equal, "equal"),
+    (KeyCode::IntlBackslash, "intl_backslash"),
+    (KeyCode::IntlRo, "intl_ro"),
+    (KeyCode::IntlYen, "intl_yen"),
+    (KeyCode::KeyA, "a"),
+    (KeyCode::KeyB, "b"),
+    (KeyCode::KeyC, "c"),
+    (KeyCode::KeyD, "d"),
+    (KeyCode::KeyE, "e"),
+    (KeyCode::KeyF, "f"),
+    (KeyCode::KeyG, "g"),
+    (KeyCode::KeyH, "h"),
+    (KeyCode::KeyI, "i"),
+    (KeyCode::KeyJ, "j"),
+    (KeyCode::KeyK, "k"),
+    (KeyCode::KeyL, "l"),
+    (KeyCode::KeyM, "m"),
+    (KeyCode::KeyN, "n"),
+    (KeyCode::KeyO, "o"),
+    (KeyCode::KeyP, "p"),
+    (KeyCode::KeyQ, "q"),
+    (KeyCode::KeyR, "r"),
+    (KeyCode::KeyS, "s"),
+    (KeyCode::KeyT, "t"),
+    (KeyCode::KeyU, "u"),
+    (KeyCode::KeyV, "v"),
+    (KeyCode::KeyW, "w"),
+    (KeyCode::KeyX, "x"),
+    (KeyCode::KeyY, "y"),
+    (KeyCode::KeyZ, "z"),
+    (KeyCode::Minus, "minus"),
+    (KeyCode::Period, "period"),
+    (KeyCode::Quote, "quote"),
+    (KeyCode::Semicolon, "semicolon"),
+    (KeyCode::Slash, "slash"),
+    (KeyCode::AltLeft, "left_alt"),
+    (KeyCode::AltRight, "right_alt"),
+    (KeyCode::Backspace, "backspace"),
+    (KeyCode::CapsLock, "caps_lock"),
+    (KeyCode::ContextMenu, "context_menu"),
+    (KeyCode::ControlLeft, "left_control"),
+    (KeyCode::ControlRight, "right_control"),
+    (KeyCode::Enter, "enter"),
+    (KeyCode::SuperLeft, "left_super"),
+    (KeyCode::SuperRight, "right_super"),
+    (KeyCode::ShiftLeft, "left_shift"),
+    (KeyCode::ShiftRight, "right_shift"),
+    (KeyCode::Space, "space"),
+    (KeyCode::Tab, "tab"),
+    (KeyCode::Convert, "convert"),
+    (KeyCode::KanaMode, "kana_mode"),
+    (KeyCode::Lang1, "lang_1"),
+    (KeyCode::Lang2, "lang_2"),
+    (KeyCode::Lang3, "lang_3"),
+    (KeyCode::Lang4, "lang_4"),
+    (KeyCode::Lang5, "lang_5"),
+    (KeyCode::NonConvert, "non_convert"),
+    (KeyCode::Delete, "delete"),
+    (KeyCode::End, "end"),
+    (KeyCode::Help, "help"),
+    (KeyCode::Home, "home"),
+    (KeyCode::Insert, "insert"),
+    (KeyCode::PageDown, "page_down"),
+    (KeyCode::PageUp, "page_up"),
+    (KeyCode::ArrowDown, "down_arrow"),
+    (KeyCode::ArrowLeft, "left_arrow"),
+    (KeyCode::ArrowRight, "right_arrow"),
+    (KeyCode::ArrowUp, "up_arrow"),
+    (KeyCode::NumLock, "num_lock"),
+    (KeyCode::Numpad0, "numpad_0"),
+    (KeyCode::Numpad1, "numpad_1"),
+    (KeyCode::Numpad2, "numpad_2"),
+    (KeyCode::Numpad3, "numpad_3"),
+    (KeyCode::Numpad4, "numpad_4"),
+    (KeyCode::Numpad5, "numpad_5"),
+    (KeyCode::Numpad6, "numpad_6"),
+    (KeyCode::Numpad7, "numpad_7"),
+    (KeyCode::Numpad8, "numpad_8"),
+    (KeyCode::Numpad9, "numpad_9"),
+    (KeyCode::NumpadAdd, "numpad_add"),
+    (KeyCode::NumpadBackspace, "numpad_backspace"),
+    (KeyCode::NumpadClear, "numpad_clear"),
+    (KeyCode::NumpadClearEntry, "numpad_clear_entry"),
+    (KeyCode::NumpadComma, "numpad_comma"),
+    (KeyCode::NumpadDecimal, "numpad_decimal"),
+    (KeyCode::NumpadDivide, "numpad_divide"),
+    (KeyCode::NumpadEnter, "numpad_enter"),
+    (KeyCode::NumpadEqual, "numpad_equal"),
+    (KeyCode::NumpadHash, "numpad_hash"),
+    (KeyCode::NumpadMemoryAdd, "numpad_memory_add"),
+    (KeyCode::NumpadMemoryClear, "numpad_memory_clear"),
+    (KeyCode::NumpadMemoryRecall, "numpad_memory_recall"),
+    (KeyCode::NumpadMemoryStore, "numpad_memory_store"),
+    (KeyCode::NumpadMemorySubtract, "numpad_memory_subtract"),
+    (KeyCode::NumpadMultiply, "numpad_multiply"),
+    (KeyCode::NumpadParenLeft, "numpad_left_paren"),
+    (KeyCode::NumpadParenRight, "numpad_right_paren"),
+    (KeyCode::NumpadStar, "numpad_star"),
+    (KeyCode::NumpadSubtract, "numpad_subtract"),
+    (KeyCode::Escape, "escape"),
+    (KeyCode::Fn, "fn"),
+    (KeyCode::FnLock, "fn_lock"),
+    (KeyCode::PrintScreen, "print_screen"),
+    (KeyCode::ScrollLock, "scroll_lock"),
+    (KeyCode::Pause, "pause"),
+    (KeyCode::BrowserBack, "browser_back"),
+    (KeyCode::BrowserFavorites, "browser_favorites"),
+    (KeyCode::BrowserForward, "browser_forward"),
+    (KeyCode::BrowserHome, "browser_home"),
+    (KeyCode::BrowserRefresh, "browser_refresh"),
+    (KeyCode::BrowserSearch, "browser_search"),
+    (KeyCode::BrowserStop, "browser_stop"),
+    (KeyCode::Eject, "eject"),
+    (KeyCode::LaunchApp1, "launch_app_1"),
+    (KeyCode::LaunchApp2, "launch_app_2"),
+    (KeyCode::LaunchMail, "launch_mail"),
+    (KeyCode::MediaPlayPause, "media_play_pause"),
+    (KeyCode::MediaSelect, "media_select"),
+    (KeyCode::MediaStop, "media_stop"),
+    (KeyCode::MediaTrackNext, "media_track_next"),
+    (KeyCode::MediaTrackPrevious, "media_track_previous"),
+    (KeyCode::Power, "power"),
+    (KeyCode::Sleep, "sleep"),
+    (KeyCode::AudioVolumeDown, "audio_volume_down"),
+    (KeyCode::AudioVolumeMute, "audio_volume_mute"),
+    (KeyCode::AudioVolumeUp, "audio_volume_up"),
+    (KeyCode::WakeUp, "wake_up"),
+    (KeyCode::Meta, "meta"),
+    (KeyCode::Hyper, "hyper"),
+    (KeyCode::Turbo, "turbo"),
+    (KeyCode::Abort, "abort"),
+    (KeyCode::Resume, "resume"),
+    (KeyCode::Suspend, "suspend"),
+    (KeyCode::Again, "again"),
+    (KeyCode::Copy, "copy"),
+    (KeyCode::Cut, "cut"),
+    (KeyCode::Find, "find"),
+    (KeyCode::Open, "open"),
+    (KeyCode::Paste, "paste"),
+    (KeyCode::Props, "props"),
+    (KeyCode::Select, "select"),
+    (KeyCode::Undo, "undo"),
+    (KeyCode::Hiragana, "hiragana"),
+    (KeyCode::Katakana, "katakana"),
+    (KeyCode::F1, "f1"),
+    (KeyCode::F2, "f2"),
+    (KeyCode::F3, "f3"),
+    (KeyCode::F4, "f4"),
+    (KeyCode::F5, "f5"),
+    (KeyCode::F6, "f6"),
+    (KeyCode::F7, "f7"),
+    (KeyCode::F8, "f8"),
+    (KeyCode::F9, "f9"),
+    (KeyCode::F10, "f10"),
+    (KeyCode::F11, "f11"),
+    (KeyCode::F12, "f12"),
+    (KeyCode::F13, "f13"),
+    (KeyCode::F14, "f14"),
+    (KeyCode::F15, "f15"),
+    (KeyCode::F16, "f16"),
+    (KeyCode::F17, "f17"),
+    (KeyCode::F18, "f18"),
+    (KeyCode::F19, "f19"),
+    (KeyCode::F20, "f20"),
+    (KeyCode::F21, "f21"),
+    (KeyCode::F22, "f22"),
+    (KeyCode::F23, "f23"),
+    (KeyCode::F24, "f24"),
+    (KeyCode::F25, "f25"),
+    (KeyCode::F26, "f26"),
+    (KeyCode::F27, "f27"),
+    (KeyCode::F28, "f28"),
+    (KeyCode::F29, "f29"),
+    (KeyCode::F30, "f30"),
+    (KeyCode::F31, "f31"),
+    (KeyCode::F32, "f32"),
+    (KeyCode::F33, "f33"),
+    (KeyCode::F34, "f34"),
+    (KeyCode::F35, "f35"),
+];
+
+// Older names that are still accepted anywhere a key name is
+const KEY_NAME_ALIASES: &[(&str, &str)] = &[("+", "equal"), ("-", "minus")];
+
+fn resolve_key_alias(name: &str) -> &str {
+    match KEY_NAME_ALIASES.iter().find(|(alias, _)| *alias == name) {
+        Some((_, key_name)) => key_name,
+        None => name,
+    }
+}
+
+fn find_key_name(name: &str) -> Option<&'static str> {
+    let name = resolve_key_alias(name);
+    KEY_NAMES
+        .iter()
+        .find(|(_, key_name)| *key_name == name)
+        .map(|(_, key_name)| *key_name)
+}
+
+const MOUSE_BUTTON_NAMES: &[(MouseButton, &str)] = &[
+    (MouseButton::Left, "mouse_left"),
+    (MouseButton::Right, "mouse_right"),
+    (MouseButton::Middle, "mouse_middle"),
+];
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum KbGamepadButton {
+    South,
+    East,
+    West,
+    North,
+    LeftBumper,
+    RightBumper,
+    Select,
+    Start,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+// Sticks are -1 to 1 with +y down, like screen space.  Triggers are 0 to 1
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum KbGamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KbGamepadStick {
+    Left,
+    Right,
+}
+
+const GAMEPAD_BUTTON_NAMES: &[(KbGamepadButton, &str)] = &[
+    (KbGamepadButton::South, "pad_south"),
+    (KbGamepadButton::East, "pad_east"),
+    (KbGamepadButton::West, "pad_west"),
+    (KbGamepadButton::North, "pad_north"),
+    (KbGamepadButton::LeftBumper, "pad_left_bumper"),
+    (KbGamepadButton::RightBumper, "pad_right_bumper"),
+    (KbGamepadButton::Select, "pad_select"),
+    (KbGamepadButton::Start, "pad_start"),
+    (KbGamepadButton::LeftStick, "pad_left_stick_press"),
+    (KbGamepadButton::RightStick, "pad_right_stick_press"),
+    (KbGamepadButton::DPadUp, "pad_dpad_up"),
+    (KbGamepadButton::DPadDown, "pad_dpad_down"),
+    (KbGamepadButton::DPadLeft, "pad_dpad_left"),
+    (KbGamepadButton::DPadRight, "pad_dpad_right"),
+];
+
+const GAMEPAD_AXIS_NAMES: &[(KbGamepadAxis, &str)] = &[
+    (KbGamepadAxis::LeftStickX, "left_stick_x"),
+    (KbGamepadAxis::LeftStickY, "left_stick_y"),
+    (KbGamepadAxis::RightStickX, "right_stick_x"),
+    (KbGamepadAxis::RightStickY, "right_stick_y"),
+    (KbGamepadAxis::LeftTrigger, "left_trigger"),
+    (KbGamepadAxis::RightTrigger, "right_trigger"),
+];
+
+// Axes pushed in one direction act as buttons that can be bound to actions
+const GAMEPAD_AXIS_BUTTON_NAMES: &[(KbGamepadAxis, f32, &str)] = &[
+    (KbGamepadAxis::LeftStickX, -1.0, "pad_left_stick_left"),
+    (KbGamepadAxis::LeftStickX, 1.0, "pad_left_stick_right"),
+    (KbGamepadAxis::LeftStickY, -1.0, "pad_left_stick_up"),
+    (KbGamepadAxis::LeftStickY, 1.0, "pad_left_stick_down"),
+    (KbGamepadAxis::RightStickX, -1.0, "pad_right_stick_left"),
+    (KbGamepadAxis::RightStickX, 1.0, "pad_right_stick_right"),
+    (KbGamepadAxis::RightStickY, -1.0, "pad_right_stick_up"),
+    (KbGamepadAxis::RightStickY, 1.0, "pad_right_stick_down"),
+    (KbGamepadAxis::LeftTrigger, 1.0, "pad_left_trigger"),
+    (KbGamepadAxis::RightTrigger, 1.0, "pad_right_trigger"),
+];
+
+// How far an axis has to be pushed before its axis button is down
+const GAMEPAD_AXIS_BUTTON_THRESHOLD: f32 = 0.5;
+
+pub type KbGamepadId = usize;
+
+// What a gamepad backend reports.  Axis values are raw, before the dead zone
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KbGamepadEvent {
+    Connected {
+        id: KbGamepadId,
+    },
+    Disconnected {
+        id: KbGamepadId,
+    },
+    Button {
+        id: KbGamepadId,
+        button: KbGamepadButton,
+        pressed: bool,
+    },
+    Axis {
+        id: KbGamepadId,
+        axis: KbGamepadAxis,
+        value: f32,
+    },
+}
+
+// Source of gamepad input.  poll() is called once per frame and adds everything that happened
+// since the last call to events
+pub trait KbGamepadBackend: fmt::Debug {
+    fn poll(&mut self, events: &mut Vec<KbGamepadEvent>);
+}
+
+#[derive(Debug, Default)]
+struct KbGamepad {
+    // Gamepad buttons and axis buttons by binding name
+    button_states: HashMap<&'static str, KbButtonState>,
+    axis_values: HashMap<KbGamepadAxis, f32>,
+}
+
+// Something that triggers an action.  Touch regions are in normalized screen space, (0, 0) being
+// the top left corner and (1, 1) the bottom right
+#[derive(Clone, Debug, PartialEq)]
+pub enum KbInputBinding {
+    Key(&'static str),
+    MouseButton(&'static str),
+    TouchRegion { min: (f32, f32), max: (f32, f32) },
+    // A gamepad button or axis button on any connected gamepad
+    GamepadButton(&'static str),
+}
+
+// Times are from the engine clock.  See kb_clock.rs
+#[derive(Clone, Debug, Default, PartialEq)]
 pub enum KbButtonState {
     #[default]
     None,
-    JustPressed,
+    JustPressed {
+        press_time: f64,
+    },
     Down {
         mouse_start: (i32, i32),
+        press_time: f64,
+    },
+    JustReleased {
+        time_held: f32,
     },
 }
 
@@ -21,10 +386,34 @@ impl KbButtonState {
     }
 
     pub fn just_pressed(&self) -> bool {
-        *self == KbButtonState::JustPressed
+        matches!(*self, KbButtonState::JustPressed { .. })
+    }
+
+    pub fn just_released(&self) -> bool {
+        matches!(*self, KbButtonState::JustReleased { .. })
+    }
+
+    // Seconds the button has been held.  On the frame it's released, how long it was held for
+    pub fn time_held(&self) -> f32 {
+        match *self {
+            KbButtonState::None => 0.0,
+            KbButtonState::JustPressed { press_time } | KbButtonState::Down { press_time, .. } => {
+                kb_time_since(press_time)
+            }
+            KbButtonState::JustReleased { time_held } => time_held,
+        }
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct KbModifiers {
+    pub shift: bool,
+    pub control: bool,
+    pub alt: bool,
+    pub logo: bool,
+}
+
+// Ended touches stay in the touch map as JustReleased until the next frame
 #[derive(Debug, Default)]
 pub struct KbTouchInfo {
     pub start_pos: (f64, f64),
@@ -33,12 +422,330 @@ pub struct KbTouchInfo {
     pub touch_state: KbButtonState,
 }
 
+// A layer of input handling such as a menu, console or gameplay.  Contexts are kept in a stack and
+// input consumed by a context is hidden from every context below it
+#[derive(Clone, Debug, Default)]
+pub struct KbInputContext {
+    pub name: String,
+    pub consume_keys: bool,
+    // Mouse buttons, scrolling and mouse motion.  The cursor position is always visible
+    pub consume_mouse: bool,
+    pub consume_touches: bool,
+    pub consume_gamepads: bool,
+    // Individual keys, mouse buttons and gamepad buttons by binding name, e.g. "escape"
+    pub consumed_buttons: Vec<String>,
+}
+
+impl KbInputContext {
+    pub fn new(name: &str) -> Self {
+        KbInputContext {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    // Consumes keys, mouse, touches and gamepads
+    pub fn new_exclusive(name: &str) -> Self {
+        KbInputContext {
+            name: name.to_string(),
+            consume_keys: true,
+            consume_mouse: true,
+            consume_touches: true,
+            consume_gamepads: true,
+            consumed_buttons: Vec::new(),
+        }
+    }
+}
+
+// Everything consumed by the contexts above the one being queried.  Borrows the stack, so views are
+// cheap enough to make for every query
+#[derive(Clone, Copy, Debug)]
+struct KbConsumedInput<'a> {
+    keys: bool,
+    mouse: bool,
+    touches: bool,
+    gamepads: bool,
+    input_contexts: &'a [KbInputContext],
+}
+
+impl<'a> KbConsumedInput<'a> {
+    fn new(input_contexts: &'a [KbInputContext]) -> Self {
+        KbConsumedInput {
+            keys: input_contexts.iter().any(|c| c.consume_keys),
+            mouse: input_contexts.iter().any(|c| c.consume_mouse),
+            touches: input_contexts.iter().any(|c| c.consume_touches),
+            gamepads: input_contexts.iter().any(|c| c.consume_gamepads),
+            input_contexts,
+        }
+    }
+
+    // name is a key, mouse button or gamepad button binding name
+    fn is_button_consumed(&self, name: &str) -> bool {
+        let mut consumed_buttons = self
+            .input_contexts
+            .iter()
+            .flat_map(|input_context| &input_context.consumed_buttons);
+        if consumed_buttons.any(|button| resolve_key_alias(button) == name) {
+            return true;
+        }
+
+        if MOUSE_BUTTON_NAMES.iter().any(|(_, n)| *n == name) {
+            self.mouse
+        } else if GAMEPAD_BUTTON_NAMES.iter().any(|(_, n)| *n == name)
+            || GAMEPAD_AXIS_BUTTON_NAMES.iter().any(|(_, _, n)| *n == name)
+        {
+            self.gamepads
+        } else {
+            self.keys
+        }
+    }
+}
+
+// Why an input recording or bindings couldn't be used.  Lines are 1-based
+#[derive(Clone, Debug, PartialEq)]
+pub enum KbInputError {
+    Io {
+        path: String,
+        message: String,
+    },
+    // Bindings text that isn't a JSON object
+    Parse {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    BadBinding {
+        action: String,
+        line: Option<usize>,
+    },
+    BadRecordingLine {
+        line: usize,
+        text: String,
+    },
+}
+
+impl fmt::Display for KbInputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const BINDING_TYPES: &str =
+            "an array of key names, mouse button names, gamepad button names or touch regions";
+        match self {
+            KbInputError::Io { path, message } => write!(f, "{} - {}", path, message),
+            KbInputError::Parse {
+                line,
+                column,
+                message,
+            } => write!(f, "{} at line {}, column {}", message, line, column),
+            KbInputError::BadBinding {
+                action,
+                line: Some(line),
+            } => write!(
+                f,
+                "\"{}\" on line {} should be {}",
+                action, line, BINDING_TYPES
+            ),
+            KbInputError::BadBinding { action, line: None } => {
+                write!(f, "\"{}\" should be {}", action, BINDING_TYPES)
+            }
+            KbInputError::BadRecordingLine { line, text } => {
+                write!(f, "Unrecognized input recording line {} \"{}\"", line, text)
+            }
+        }
+    }
+}
+
+impl std::error::Error for KbInputError {}
+
+// Input as it is recorded and played back.  Keys and mouse buttons use their binding names
+#[derive(Clone, Debug, PartialEq)]
+pub enum KbInputEvent {
+    Button {
+        name: &'static str,
+        pressed: bool,
+    },
+    MouseMove {
+        x: f64,
+        y: f64,
+    },
+    MouseScroll {
+        delta: f32,
+    },
+    MouseMotion {
+        delta_x: f64,
+        delta_y: f64,
+    },
+    Touch {
+        id: u64,
+        phase: TouchPhase,
+        x: f64,
+        y: f64,
+    },
+    Modifiers(KbModifiers),
+    Gamepad(KbGamepadEvent),
+    // Typed, pasted or IME committed text
+    Text(String),
+    TextEdit(KbTextEdit),
+    // IME text that is still being composed
+    ImePreedit(String),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KbTextEdit {
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Home,
+    End,
+    Submit,
+}
+
+const TEXT_EDIT_NAMES: &[(KbTextEdit, &str)] = &[
+    (KbTextEdit::Backspace, "backspace"),
+    (KbTextEdit::Delete, "delete"),
+    (KbTextEdit::Left, "left"),
+    (KbTextEdit::Right, "right"),
+    (KbTextEdit::Home, "home"),
+    (KbTextEdit::End, "end"),
+    (KbTextEdit::Submit, "submit"),
+];
+
+// Text is recorded as character codes so spaces and newlines survive the text format
+fn write_text_codes(f: &mut fmt::Formatter, event_name: &str, text: &str) -> fmt::Result {
+    write!(f, "{}", event_name)?;
+    for c in text.chars() {
+        write!(f, " {}", c as u32)?;
+    }
+    Ok(())
+}
+
+fn parse_text_codes(codes: &[&str]) -> Option<String> {
+    codes
+        .iter()
+        .map(|code| char::from_u32(code.parse().ok()?))
+        .collect()
+}
+
+// The field being typed into between start_text_input() and stop_text_input()
+#[derive(Debug, Default)]
+struct KbTextInput {
+    text: String,
+    // Byte offset into text
+    cursor: usize,
+    composition: String,
+    frame_text: String,
+    submitted: bool,
+}
+
+impl fmt::Display for KbInputEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KbInputEvent::Button { name, pressed } => {
+                write!(f, "button {} {}", name, *pressed as u32)
+            }
+            KbInputEvent::MouseMove { x, y } => write!(f, "mouse_move {} {}", x, y),
+            KbInputEvent::MouseScroll { delta } => write!(f, "scroll {}", delta),
+            KbInputEvent::MouseMotion { delta_x, delta_y } => {
+                write!(f, "mouse_motion {} {}", delta_x, delta_y)
+            }
+            KbInputEvent::Touch { id, phase, x, y } => {
+                let phase = match phase {
+                    TouchPhase::Started => "started",
+                    TouchPhase::Moved => "moved",
+                    TouchPhase::Ended => "ended",
+                    TouchPhase::Cancelled => "cancelled",
+                };
+                write!(f, "touch {} {} {} {}", id, phase, x, y)
+            }
+            KbInputEvent::Modifiers(modifiers) => write!(
+                f,
+                "modifiers {} {} {} {}",
+                modifiers.shift as u32,
+                modifiers.control as u32,
+                modifiers.alt as u32,
+                modifiers.logo as u32
+            ),
+            KbInputEvent::Gamepad(gamepad_event) => match gamepad_event {
+                KbGamepadEvent::Connected { id } => write!(f, "pad_connected {}", id),
+                KbGamepadEvent::Disconnected { id } => write!(f, "pad_disconnected {}", id),
+                KbGamepadEvent::Button {
+                    id,
+                    button,
+                    pressed,
+                } => write!(
+                    f,
+                    "pad_button {} {} {}",
+                    id,
+                    get_gamepad_button_name(*button),
+                    *pressed as u32
+                ),
+                KbGamepadEvent::Axis { id, axis, value } => {
+                    let axis_name = GAMEPAD_AXIS_NAMES
+                        .iter()
+                        .find(|(a, _)| a == axis)
+                        .map(|(_, name)| *name)
+                        .unwrap();
+                    write!(f, "pad_axis {} {} {}", id, axis_name, value)
+                }
+            },
+            KbInputEvent::Text(text) => write_text_codes(f, "text", text),
+            KbInputEvent::TextEdit(text_edit) => {
+                let edit_name = TEXT_EDIT_NAMES
+                    .iter()
+                    .find(|(e, _)| e == text_edit)
+                    .map(|(_, name)| *name)
+                    .unwrap();
+                write!(f, "text_edit {}", edit_name)
+            }
+            KbInputEvent::ImePreedit(text) => write_text_codes(f, "ime_preedit", text),
+        }
+    }
+}
+
+// While recording, engine time only moves at the start of a frame so the game sees exactly the
+// times that are written out
+#[derive(Debug)]
+struct KbInputRecording {
+    writer: BufWriter<std::fs::File>,
+    real_clock: KbSystemClock,
+    clock: Rc<KbManualClock>,
+}
+
+#[derive(Debug)]
+struct KbInputPlayback {
+    events: VecDeque<(u64, KbInputEvent)>,
+    frame_times: VecDeque<(u64, f64)>,
+    last_frame: u64,
+    clock: Rc<KbManualClock>,
+}
+
 #[derive(Debug, Default)]
 pub struct KbInputManager {
     touch_id_to_info: HashMap<u64, KbTouchInfo>,
     mouse_scroll_delta: f32,
+    mouse_delta: (f32, f32),
     cursor_position: (i32, i32),
+    screen_size: (u32, u32),
+    modifiers: KbModifiers,
     key_map: HashMap<&'static str, KbButtonState>,
+    action_bindings: HashMap<String, Vec<KbInputBinding>>,
+
+    gesture_recognizer: KbGestureRecognizer,
+    gestures: Vec<KbGesture>,
+
+    text_input: Option<KbTextInput>,
+    paste_requested: bool,
+
+    gamepad_backend: Option<Box<dyn KbGamepadBackend>>,
+    gamepads: HashMap<KbGamepadId, KbGamepad>,
+    gamepad_dead_zone: f32,
+
+    // Bottom to top
+    input_contexts: Vec<KbInputContext>,
+    empty_touch_map: HashMap<u64, KbTouchInfo>,
+
+    frame_index: u64,
+    recording: Option<KbInputRecording>,
+    playback: Option<KbInputPlayback>,
 }
 
 #[allow(dead_code)]
@@ -47,12 +754,13 @@ impl KbInputManager {
         let key_map = HashMap::<&str, KbButtonState>::new();
         KbInputManager {
             key_map,
+            gamepad_dead_zone: 0.15,
             ..Default::default()
         }
     }
 
     pub fn update_mouse_scroll(&mut self, y_delta: f32) {
-        self.mouse_scroll_delta += y_delta;
+        self.handle_live_event(KbInputEvent::MouseScroll { delta: y_delta });
     }
 
     pub fn update_touch(
@@ -61,148 +769,2691 @@ impl KbInputManager {
         id: u64,
         location: winit::dpi::PhysicalPosition<f64>,
     ) {
-        if phase == winit::event::TouchPhase::Started {
-            let touch_info = KbTouchInfo {
-                start_pos: location.into(),
-                current_pos: location.into(),
-                frame_delta: (0.0, 0.0),
-                touch_state: KbButtonState::JustPressed,
-            };
-            self.touch_id_to_info.insert(id, touch_info);
-        } else if phase == winit::event::TouchPhase::Cancelled
-            || phase == winit::event::TouchPhase::Ended
-        {
-            self.touch_id_to_info.remove(&id);
-        } else if phase == winit::event::TouchPhase::Moved {
-            let touch_info = &mut self.touch_id_to_info.get_mut(&id).unwrap();
-            touch_info.frame_delta.0 = touch_info.current_pos.0 - location.x;
-            touch_info.frame_delta.1 = touch_info.current_pos.1 - location.y;
-            touch_info.current_pos.0 = location.x;
-            touch_info.current_pos.1 = location.y;
-        }
+        self.handle_live_event(KbInputEvent::Touch {
+            id,
+            phase,
+            x: location.x,
+            y: location.y,
+        });
     }
 
     pub fn set_mouse_button_state(&mut self, button: &MouseButton, state: &ElementState) -> bool {
-        let button_name = match button {
-            MouseButton::Left => "mouse_left",
-            MouseButton::Right => "mouse_right",
-            MouseButton::Middle => "mouse_middle",
-            _ => "none",
+        let button_name = match MOUSE_BUTTON_NAMES.iter().find(|(b, _)| b == button) {
+            Some((_, button_name)) => *button_name,
+            None => return false,
+        };
+
+        self.handle_live_event(KbInputEvent::Button {
+            name: button_name,
+            pressed: *state == ElementState::Pressed,
+        })
+    }
+
+    pub fn set_key_state(&mut self, key: PhysicalKey, state: ElementState) -> bool {
+        let key_name = match key {
+            PhysicalKey::Code(key_code) => match KEY_NAMES.iter().find(|(k, _)| *k == key_code) {
+                Some((_, key_name)) => *key_name,
+                None => return false,
+            },
+            _ => return false,
         };
-        if button_name == "none" {
+
+        self.handle_live_event(KbInputEvent::Button {
+            name: key_name,
+            pressed: state == ElementState::Pressed,
+        })
+    }
+
+    // Live events are ignored during playback.  Returns false if the event was dropped
+    fn handle_live_event(&mut self, event: KbInputEvent) -> bool {
+        if self.playback.is_some() {
             return false;
         }
 
-        let key_pair = self.key_map.get_mut(&button_name);
-        if key_pair.is_some() {
-            let key_pair = key_pair.unwrap();
-            if *state == ElementState::Pressed {
-                if *key_pair == KbButtonState::None {
-                    *key_pair = KbButtonState::JustPressed;
-                }
-            } else {
-                *key_pair = KbButtonState::None;
-            };
-        } else {
-            self.key_map.insert(button_name, KbButtonState::JustPressed);
+        if let Some(recording) = &mut self.recording {
+            let _ = writeln!(recording.writer, "{} {}", self.frame_index, event);
         }
-
+        self.apply_event(&event);
         true
     }
 
-    pub fn set_key_state(&mut self, key: PhysicalKey, state: ElementState) -> bool {
-        let key_name = match key {
-            PhysicalKey::Code(KeyCode::ArrowUp) => "up_arrow",
-            PhysicalKey::Code(KeyCode::ArrowDown) => "down_arrow",
-            PhysicalKey::Code(KeyCode::ArrowLeft) => "left_arrow",
-            PhysicalKey::Code(KeyCode::ArrowRight) => "right_arrow",
-            PhysicalKey::Code(KeyCode::Digit1) => "1",
-            PhysicalKey::Code(KeyCode::Digit2) => "2",
-            PhysicalKey::Code(KeyCode::Digit3) => "3",
-            PhysicalKey::Code(KeyCode::Digit4) => "4",
-            PhysicalKey::Code(KeyCode::Digit5) => "5",
-            PhysicalKey::Code(KeyCode::Digit6) => "6",
-            PhysicalKey::Code(KeyCode::Digit7) => "7",
-            PhysicalKey::Code(KeyCode::Digit8) => "8",
-            PhysicalKey::Code(KeyCode::Digit9) => "9",
-            PhysicalKey::Code(KeyCode::Digit0) => "0",
-            PhysicalKey::Code(KeyCode::Equal) => "+",
-            PhysicalKey::Code(KeyCode::Minus) => "-",
-            PhysicalKey::Code(KeyCode::KeyW) => "w",
-            PhysicalKey::Code(KeyCode::KeyA) => "a",
-            PhysicalKey::Code(KeyCode::KeyS) => "s",
-            PhysicalKey::Code(KeyCode::KeyD) => "d",
-            PhysicalKey::Code(KeyCode::Space) => "space",
-            PhysicalKey::Code(KeyCode::KeyH) => "h",
-            PhysicalKey::Code(KeyCode::KeyM) => "m",
-            PhysicalKey::Code(KeyCode::KeyY) => "y",
-            PhysicalKey::Code(KeyCode::KeyV) => "v",
-            PhysicalKey::Code(KeyCode::ShiftLeft) => "left_shift",
-            _ => "none",
-        };
-        if key_name == "none" {
-            return false;
+    fn apply_event(&mut self, event: &KbInputEvent) {
+        match *event {
+            KbInputEvent::Button { name, pressed } => self.set_button_state(name, pressed),
+            KbInputEvent::MouseMove { x, y } => {
+                self.cursor_position.0 = x as i32;
+                self.cursor_position.1 = y as i32;
+            }
+            KbInputEvent::MouseScroll { delta } => self.mouse_scroll_delta += delta,
+            KbInputEvent::MouseMotion { delta_x, delta_y } => {
+                self.mouse_delta.0 += delta_x as f32;
+                self.mouse_delta.1 += delta_y as f32;
+            }
+            KbInputEvent::Touch { id, phase, x, y } => {
+                self.gesture_recognizer.on_touch(
+                    id,
+                    phase,
+                    (x, y),
+                    self.screen_size.1,
+                    &mut self.gestures,
+                );
+                self.apply_touch(id, phase, x, y);
+            }
+            KbInputEvent::Modifiers(modifiers) => self.modifiers = modifiers,
+            KbInputEvent::Gamepad(gamepad_event) => self.apply_gamepad_event(&gamepad_event),
+            KbInputEvent::Text(ref text) => {
+                if let Some(text_input) = &mut self.text_input {
+                    let text: String = text.chars().filter(|c| !c.is_control()).collect();
+                    text_input.text.insert_str(text_input.cursor, &text);
+                    text_input.cursor += text.len();
+                    text_input.frame_text.push_str(&text);
+                    text_input.composition.clear();
+                }
+            }
+            KbInputEvent::TextEdit(text_edit) => {
+                if let Some(text_input) = &mut self.text_input {
+                    apply_text_edit(text_input, text_edit);
+                }
+            }
+            KbInputEvent::ImePreedit(ref text) => {
+                if let Some(text_input) = &mut self.text_input {
+                    text_input.composition = text.clone();
+                }
+            }
         }
+    }
+
+    fn apply_gamepad_event(&mut self, gamepad_event: &KbGamepadEvent) {
+        match *gamepad_event {
+            KbGamepadEvent::Connected { id } => {
+                self.gamepads.entry(id).or_default();
+            }
+            KbGamepadEvent::Disconnected { id } => {
+                self.gamepads.remove(&id);
+            }
+            KbGamepadEvent::Button {
+                id,
+                button,
+                pressed,
+            } => {
+                let gamepad = self.gamepads.entry(id).or_default();
+                let button_state = gamepad
+                    .button_states
+                    .entry(get_gamepad_button_name(button))
+                    .or_default();
+                update_button_state(button_state, pressed);
+            }
+            KbGamepadEvent::Axis { id, axis, value } => {
+                self.gamepads
+                    .entry(id)
+                    .or_default()
+                    .axis_values
+                    .insert(axis, value);
 
-        let key_pair = self.key_map.get_mut(&key_name);
-        if key_pair.is_some() {
-            let key_pair = key_pair.unwrap();
-            if state == ElementState::Pressed {
-                if *key_pair == KbButtonState::None {
-                    *key_pair = KbButtonState::JustPressed;
+                // The stick's dead zone depends on both of its axes, so update all axis buttons
+                for (axis, sign, name) in GAMEPAD_AXIS_BUTTON_NAMES {
+                    let axis_value = self.get_dead_zoned_axis(id, *axis) * sign;
+                    let gamepad = self.gamepads.get_mut(&id).unwrap();
+                    let button_state = gamepad.button_states.entry(name).or_default();
+                    update_button_state(button_state, axis_value > GAMEPAD_AXIS_BUTTON_THRESHOLD);
                 }
-            } else {
-                *key_pair = KbButtonState::None;
-            };
-        } else {
-            self.key_map.insert(key_name, KbButtonState::JustPressed);
+            }
         }
-        true
     }
 
-    pub fn update_key_states(&mut self) {
-        for button_pair in &mut self.key_map {
-            if *button_pair.1 == KbButtonState::JustPressed {
-                *button_pair.1 = KbButtonState::Down {
-                    mouse_start: (self.cursor_position.0, self.cursor_position.1),
+    fn apply_touch(&mut self, id: u64, phase: TouchPhase, x: f64, y: f64) {
+        match phase {
+            TouchPhase::Started => {
+                let touch_info = KbTouchInfo {
+                    start_pos: (x, y),
+                    current_pos: (x, y),
+                    frame_delta: (0.0, 0.0),
+                    touch_state: KbButtonState::JustPressed {
+                        press_time: kb_time_now(),
+                    },
+                };
+                self.touch_id_to_info.insert(id, touch_info);
+            }
+            TouchPhase::Moved => {
+                if let Some(touch_info) = self.touch_id_to_info.get_mut(&id) {
+                    touch_info.frame_delta.0 = touch_info.current_pos.0 - x;
+                    touch_info.frame_delta.1 = touch_info.current_pos.1 - y;
+                    touch_info.current_pos.0 = x;
+                    touch_info.current_pos.1 = y;
+                }
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                if let Some(touch_info) = self.touch_id_to_info.get_mut(&id) {
+                    update_button_state(&mut touch_info.touch_state, false);
                 }
             }
         }
+    }
 
-        for touch in &mut self.touch_id_to_info {
-            touch.1.frame_delta.0 = 0.0;
-            touch.1.frame_delta.1 = 0.0;
-            touch.1.touch_state = KbButtonState::Down {
-                mouse_start: (0, 0),
+    fn set_button_state(&mut self, name: &'static str, pressed: bool) {
+        update_button_state(self.key_map.entry(name).or_default(), pressed);
+    }
+
+    pub fn set_modifiers(&mut self, modifiers: &winit::keyboard::ModifiersState) {
+        self.handle_live_event(KbInputEvent::Modifiers(KbModifiers {
+            shift: modifiers.shift_key(),
+            control: modifiers.control_key(),
+            alt: modifiers.alt_key(),
+            logo: modifiers.super_key(),
+        }));
+    }
+
+    pub fn get_modifiers(&self) -> KbModifiers {
+        self.modifiers
+    }
+
+    // Starts typing into a text field.  Actions read as up until stop_text_input() so typing doesn't
+    // also play the game
+    pub fn start_text_input(&mut self, initial_text: &str) {
+        self.text_input = Some(KbTextInput {
+            text: initial_text.to_string(),
+            cursor: initial_text.len(),
+            ..Default::default()
+        });
+    }
+
+    // Returns the text that was typed
+    pub fn stop_text_input(&mut self) -> String {
+        match self.text_input.take() {
+            Some(text_input) => text_input.text,
+            None => String::new(),
+        }
+    }
+
+    pub fn is_text_input_active(&self) -> bool {
+        self.text_input.is_some()
+    }
+
+    pub fn get_text_input(&self) -> &str {
+        match &self.text_input {
+            Some(text_input) => &text_input.text,
+            None => "",
+        }
+    }
+
+    // Byte offset of the cursor into get_text_input()
+    pub fn get_text_cursor(&self) -> usize {
+        match &self.text_input {
+            Some(text_input) => text_input.cursor,
+            None => 0,
+        }
+    }
+
+    // IME text that hasn't been committed yet.  Draw it at the cursor
+    pub fn get_text_composition(&self) -> &str {
+        match &self.text_input {
+            Some(text_input) => &text_input.composition,
+            None => "",
+        }
+    }
+
+    // Text typed since the last frame
+    pub fn get_frame_text(&self) -> &str {
+        match &self.text_input {
+            Some(text_input) => &text_input.frame_text,
+            None => "",
+        }
+    }
+
+    // True on the frame enter is pressed
+    pub fn text_input_submitted(&self) -> bool {
+        match &self.text_input {
+            Some(text_input) => text_input.submitted,
+            None => false,
+        }
+    }
+
+    // Inserts text at the cursor as if it was typed
+    pub fn paste_text(&mut self, text: &str) {
+        self.paste_requested = false;
+        if self.text_input.is_some() {
+            self.handle_live_event(KbInputEvent::Text(text.to_string()));
+        }
+    }
+
+    // True after control+v or command+v during text input.  The platform layer answers it with
+    // paste_text()
+    pub fn is_paste_requested(&self) -> bool {
+        self.paste_requested
+    }
+
+    // Turns key presses into typed text and edits while text input is active.  Unlike
+    // set_key_state(), key repeats count
+    pub fn update_text_input(
+        &mut self,
+        logical_key: &Key,
+        text: Option<&str>,
+        state: ElementState,
+    ) {
+        if self.text_input.is_none() || state != ElementState::Pressed {
+            return;
+        }
+
+        let text_edit = match logical_key {
+            Key::Named(NamedKey::Backspace) => Some(KbTextEdit::Backspace),
+            Key::Named(NamedKey::Delete) => Some(KbTextEdit::Delete),
+            Key::Named(NamedKey::ArrowLeft) => Some(KbTextEdit::Left),
+            Key::Named(NamedKey::ArrowRight) => Some(KbTextEdit::Right),
+            Key::Named(NamedKey::Home) => Some(KbTextEdit::Home),
+            Key::Named(NamedKey::End) => Some(KbTextEdit::End),
+            Key::Named(NamedKey::Enter) => Some(KbTextEdit::Submit),
+            _ => None,
+        };
+        if let Some(text_edit) = text_edit {
+            self.handle_live_event(KbInputEvent::TextEdit(text_edit));
+            return;
+        }
+
+        // Control and command are shortcuts, but AltGr arrives as control+alt on Windows and types
+        let is_shortcut = (self.modifiers.control && !self.modifiers.alt) || self.modifiers.logo;
+        if is_shortcut {
+            if let Key::Character(c) = logical_key {
+                if c.eq_ignore_ascii_case("v") {
+                    self.paste_requested = true;
+                }
             }
+            return;
         }
 
-        self.mouse_scroll_delta = 0.0;
+        if let Some(text) = text {
+            if text.chars().any(|c| !c.is_control()) {
+                self.handle_live_event(KbInputEvent::Text(text.to_string()));
+            }
+        }
     }
 
-    pub fn get_key_state(&self, key: &str) -> KbButtonState {
-        let key_state_pair = self.key_map.get(key);
-        if let Some(key_state) = key_state_pair {
-            return key_state.clone();
+    pub fn update_ime(&mut self, ime: &Ime) {
+        if self.text_input.is_none() {
+            return;
         }
 
-        KbButtonState::None
+        match ime {
+            Ime::Preedit(text, _) => {
+                self.handle_live_event(KbInputEvent::ImePreedit(text.clone()));
+            }
+            Ime::Commit(text) => {
+                self.handle_live_event(KbInputEvent::Text(text.clone()));
+            }
+            Ime::Enabled | Ime::Disabled => {
+                self.handle_live_event(KbInputEvent::ImePreedit(String::new()));
+            }
+        }
     }
 
-    pub fn get_touch_map(&self) -> &HashMap<u64, KbTouchInfo> {
-        &self.touch_id_to_info
+    // Writes every input event to file_path until stop_recording().  Engine time and kb_random_*()
+    // are made repeatable so start_playback() can reproduce the session.  On error nothing changes
+    pub fn start_recording(&mut self, file_path: &str) -> Result<(), KbInputError> {
+        let io_error = |error: std::io::Error| KbInputError::Io {
+            path: file_path.to_string(),
+            message: error.to_string(),
+        };
+        let mut writer = BufWriter::new(std::fs::File::create(file_path).map_err(io_error)?);
+
+        let mut seed_bytes = [0u8; 8];
+        let _ = getrandom::getrandom(&mut seed_bytes);
+        let seed = u64::from_le_bytes(seed_bytes);
+        let start_time = kb_time_now();
+        writeln!(writer, "seed {}", seed).map_err(io_error)?;
+        writeln!(writer, "start_time {}", start_time).map_err(io_error)?;
+        writer.flush().map_err(io_error)?;
+
+        kb_seed_random(seed);
+        let clock = Rc::new(KbManualClock::new());
+        clock.set_time(start_time);
+        kb_set_clock(clock.clone());
+        self.frame_index = 0;
+        self.recording = Some(KbInputRecording {
+            writer,
+            real_clock: KbSystemClock::starting_at(start_time),
+            clock,
+        });
+        Ok(())
     }
 
-    pub fn get_mouse_scroll_delta(&self) -> f32 {
-        self.mouse_scroll_delta
+    pub fn stop_recording(&mut self) {
+        if let Some(mut recording) = self.recording.take() {
+            let _ = recording.writer.flush();
+            kb_set_clock(Rc::new(KbSystemClock::starting_at(kb_time_now())));
+        }
     }
 
-    pub fn set_mouse_position(&mut self, position: &winit::dpi::PhysicalPosition<f64>) {
-        self.cursor_position.0 = position.x as i32;
-        self.cursor_position.1 = position.y as i32;
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
     }
 
-    pub fn get_mouse_position(&self) -> (i32, i32) {
-        self.cursor_position
+    // Replaces live input with a file written by start_recording().  Engine time and kb_random_*()
+    // follow the recording, so call this before the game is created.  On error nothing changes
+    pub fn start_playback(&mut self, file_path: &str) -> Result<(), KbInputError> {
+        let recording_text =
+            std::fs::read_to_string(file_path).map_err(|error| KbInputError::Io {
+                path: file_path.to_string(),
+                message: error.to_string(),
+            })?;
+
+        let mut playback = KbInputPlayback {
+            events: VecDeque::<(u64, KbInputEvent)>::new(),
+            frame_times: VecDeque::<(u64, f64)>::new(),
+            last_frame: 0,
+            clock: Rc::new(KbManualClock::new()),
+        };
+        let mut seed = None;
+        let mut start_time = 0.0;
+        // Recorded times can't go backwards, so the playback clock never has to
+        let mut last_time = 0.0;
+        for (line_index, line) in recording_text.lines().enumerate() {
+            let words = line.split_whitespace().collect::<Vec<&str>>();
+            let parsed = match words.as_slice() {
+                [] => Some(()),
+                ["seed", recorded_seed] => {
+                    recorded_seed.parse::<u64>().ok().map(|s| seed = Some(s))
+                }
+                ["start_time", time] => time
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|time| *time >= last_time)
+                    .map(|time| {
+                        start_time = time;
+                        last_time = time;
+                    }),
+                [frame, "time", time] => frame
+                    .parse::<u64>()
+                    .ok()
+                    .zip(time.parse::<f64>().ok().filter(|time| *time >= last_time))
+                    .map(|(frame, time)| {
+                        last_time = time;
+                        playback.frame_times.push_back((frame, time));
+                    }),
+                [frame, event @ ..] => frame
+                    .parse::<u64>()
+                    .ok()
+                    .zip(parse_input_event(event))
+                    .map(|frame_event| playback.events.push_back(frame_event)),
+            };
+            if parsed.is_none() {
+                return Err(KbInputError::BadRecordingLine {
+                    line: line_index + 1,
+                    text: line.to_string(),
+                });
+            }
+        }
+
+        if let Some(seed) = seed {
+            kb_seed_random(seed);
+        }
+        playback.clock.set_time(start_time);
+        playback.last_frame = playback.frame_times.back().map_or(0, |(frame, _)| *frame);
+        kb_set_clock(playback.clock.clone());
+        self.frame_index = 0;
+        self.playback = Some(playback);
+        Ok(())
+    }
+
+    pub fn is_playing_back(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    // True once every recorded frame has been played
+    pub fn is_playback_finished(&self) -> bool {
+        match &self.playback {
+            Some(playback) => self.frame_index > playback.last_frame,
+            None => false,
+        }
+    }
+
+    // Called by run_game() before each tick.  Steps the recording clock or plays back the events
+    // recorded for this frame
+    pub fn begin_frame(&mut self) {
+        let mut gamepad_events = Vec::<KbGamepadEvent>::new();
+        if let Some(gamepad_backend) = &mut self.gamepad_backend {
+            gamepad_backend.poll(&mut gamepad_events);
+        }
+        for gamepad_event in gamepad_events {
+            self.handle_live_event(KbInputEvent::Gamepad(gamepad_event));
+        }
+
+        if let Some(recording) = &mut self.recording {
+            let frame_time = recording.real_clock.now();
+            recording.clock.set_time(frame_time);
+            let _ = writeln!(recording.writer, "{} time {}", self.frame_index, frame_time);
+            let _ = recording.writer.flush();
+        }
+
+        // Recorded events arrived before this frame's time step, so they're applied at the previous
+        // frame's time like they were live
+        let mut frame_events = Vec::<KbInputEvent>::new();
+        if let Some(playback) = &mut self.playback {
+            while let Some((frame, _)) = playback.events.front() {
+                if *frame > self.frame_index {
+                    break;
+                }
+                frame_events.push(playback.events.pop_front().unwrap().1);
+            }
+        }
+        for event in &frame_events {
+            self.apply_event(event);
+        }
+        if let Some(playback) = &mut self.playback {
+            while let Some((frame, frame_time)) = playback.frame_times.front() {
+                if *frame > self.frame_index {
+                    break;
+                }
+                playback.clock.set_time(*frame_time);
+                playback.frame_times.pop_front();
+            }
+        }
+
+        self.gesture_recognizer.update(&mut self.gestures);
+
+        self.frame_index += 1;
+    }
+
+    pub fn update_key_states(&mut self) {
+        for button_state in self.key_map.values_mut() {
+            advance_button_state(button_state, self.cursor_position);
+        }
+
+        for gamepad in self.gamepads.values_mut() {
+            for button_state in gamepad.button_states.values_mut() {
+                advance_button_state(button_state, (0, 0));
+            }
+        }
+
+        self.touch_id_to_info
+            .retain(|_, touch| !touch.touch_state.just_released());
+        for touch in &mut self.touch_id_to_info {
+            touch.1.frame_delta.0 = 0.0;
+            touch.1.frame_delta.1 = 0.0;
+            if let KbButtonState::JustPressed { press_time } = touch.1.touch_state {
+                touch.1.touch_state = KbButtonState::Down {
+                    mouse_start: (0, 0),
+                    press_time,
+                }
+            }
+        }
+
+        self.mouse_scroll_delta = 0.0;
+        self.mouse_delta = (0.0, 0.0);
+        self.gestures.clear();
+        self.paste_requested = false;
+        if let Some(text_input) = &mut self.text_input {
+            text_input.frame_text.clear();
+            text_input.submitted = false;
+        }
+    }
+
+    // Adds a context on top of the stack.  Names must be unique
+    pub fn push_input_context(&mut self, input_context: KbInputContext) {
+        assert!(
+            !self.has_input_context(&input_context.name),
+            "KbInputManager::push_input_context() - {} is already on the stack",
+            input_context.name
+        );
+        self.input_contexts.push(input_context);
+    }
+
+    pub fn pop_input_context(&mut self) -> Option<KbInputContext> {
+        self.input_contexts.pop()
+    }
+
+    // Removes the named context from anywhere in the stack
+    pub fn remove_input_context(&mut self, name: &str) -> Option<KbInputContext> {
+        let index = self.input_contexts.iter().position(|c| c.name == name)?;
+        Some(self.input_contexts.remove(index))
+    }
+
+    pub fn has_input_context(&self, name: &str) -> bool {
+        self.input_contexts.iter().any(|c| c.name == name)
+    }
+
+    pub fn get_top_input_context(&self) -> Option<&KbInputContext> {
+        self.input_contexts.last()
+    }
+
+    pub fn is_top_input_context(&self, name: &str) -> bool {
+        match self.input_contexts.last() {
+            Some(input_context) => input_context.name == name,
+            None => false,
+        }
+    }
+
+    // Input as seen by the named context, without anything consumed by the contexts above it.  A
+    // name that isn't on the stack sees the same input as the manager's own queries
+    pub fn get_input_context(&self, name: &str) -> KbInputContextView<'_> {
+        let above = match self.input_contexts.iter().position(|c| c.name == name) {
+            Some(index) => &self.input_contexts[index + 1..],
+            None => &self.input_contexts[..],
+        };
+
+        KbInputContextView {
+            input_manager: self,
+            consumed: KbConsumedInput::new(above),
+        }
+    }
+
+    // The manager's queries see input that no context on the stack has consumed, so gameplay code
+    // doesn't need to know about menus and consoles
+    fn get_base_view(&self) -> KbInputContextView<'_> {
+        KbInputContextView {
+            input_manager: self,
+            consumed: KbConsumedInput::new(&self.input_contexts),
+        }
+    }
+
+    pub fn get_key_state(&self, key: &str) -> KbButtonState {
+        self.get_base_view().get_key_state(key)
+    }
+
+    pub fn get_touch_map(&self) -> &HashMap<u64, KbTouchInfo> {
+        self.get_base_view().get_touch_map()
+    }
+
+    // Replaces the source of gamepad input, e.g. with a KbSyntheticGamepadBackend in tests
+    pub fn set_gamepad_backend(&mut self, gamepad_backend: Box<dyn KbGamepadBackend>) {
+        self.gamepad_backend = Some(gamepad_backend);
+    }
+
+    // Connected gamepads, lowest id first
+    pub fn get_gamepad_ids(&self) -> Vec<KbGamepadId> {
+        let mut gamepad_ids: Vec<KbGamepadId> = self.gamepads.keys().copied().collect();
+        gamepad_ids.sort();
+        gamepad_ids
+    }
+
+    pub fn is_gamepad_connected(&self, id: KbGamepadId) -> bool {
+        self.gamepads.contains_key(&id)
+    }
+
+    pub fn get_gamepad_button(&self, id: KbGamepadId, button: KbGamepadButton) -> KbButtonState {
+        self.get_base_view().get_gamepad_button(id, button)
+    }
+
+    // Axis value after the dead zone
+    pub fn get_gamepad_axis(&self, id: KbGamepadId, axis: KbGamepadAxis) -> f32 {
+        self.get_base_view().get_gamepad_axis(id, axis)
+    }
+
+    // Stick position after the dead zone.  The dead zone is round so diagonals aren't favored
+    pub fn get_gamepad_stick(&self, id: KbGamepadId, stick: KbGamepadStick) -> (f32, f32) {
+        self.get_base_view().get_gamepad_stick(id, stick)
+    }
+
+    fn get_dead_zoned_axis(&self, id: KbGamepadId, axis: KbGamepadAxis) -> f32 {
+        match axis {
+            KbGamepadAxis::LeftStickX => self.get_dead_zoned_stick(id, KbGamepadStick::Left).0,
+            KbGamepadAxis::LeftStickY => self.get_dead_zoned_stick(id, KbGamepadStick::Left).1,
+            KbGamepadAxis::RightStickX => self.get_dead_zoned_stick(id, KbGamepadStick::Right).0,
+            KbGamepadAxis::RightStickY => self.get_dead_zoned_stick(id, KbGamepadStick::Right).1,
+            KbGamepadAxis::LeftTrigger | KbGamepadAxis::RightTrigger => {
+                let value = self.get_raw_gamepad_axis(id, axis).clamp(0.0, 1.0);
+                if value <= self.gamepad_dead_zone {
+                    0.0
+                } else {
+                    (value - self.gamepad_dead_zone) / (1.0 - self.gamepad_dead_zone)
+                }
+            }
+        }
+    }
+
+    fn get_dead_zoned_stick(&self, id: KbGamepadId, stick: KbGamepadStick) -> (f32, f32) {
+        let (x_axis, y_axis) = match stick {
+            KbGamepadStick::Left => (KbGamepadAxis::LeftStickX, KbGamepadAxis::LeftStickY),
+            KbGamepadStick::Right => (KbGamepadAxis::RightStickX, KbGamepadAxis::RightStickY),
+        };
+        let x = self.get_raw_gamepad_axis(id, x_axis);
+        let y = self.get_raw_gamepad_axis(id, y_axis);
+
+        let length = (x * x + y * y).sqrt();
+        if length <= self.gamepad_dead_zone {
+            return (0.0, 0.0);
+        }
+        let scale =
+            (length.min(1.0) - self.gamepad_dead_zone) / (1.0 - self.gamepad_dead_zone) / length;
+        (x * scale, y * scale)
+    }
+
+    // Fraction of the stick or trigger's range that is ignored.  Defaults to 0.15
+    pub fn set_gamepad_dead_zone(&mut self, dead_zone: f32) {
+        assert!(
+            (0.0..1.0).contains(&dead_zone),
+            "KbInputManager::set_gamepad_dead_zone() - dead_zone must be from 0 up to 1"
+        );
+        self.gamepad_dead_zone = dead_zone;
+    }
+
+    fn get_raw_gamepad_axis(&self, id: KbGamepadId, axis: KbGamepadAxis) -> f32 {
+        match self.gamepads.get(&id) {
+            Some(gamepad) => *gamepad.axis_values.get(&axis).unwrap_or(&0.0),
+            None => 0.0,
+        }
+    }
+
+    // Gestures recognized since the last frame, in the order they happened
+    pub fn get_gestures(&self) -> &[KbGesture] {
+        self.get_base_view().get_gestures()
+    }
+
+    pub fn get_mouse_scroll_delta(&self) -> f32 {
+        self.get_base_view().get_mouse_scroll_delta()
+    }
+
+    // Raw mouse movement from DeviceEvent::MouseMotion.  Unlike the cursor position, it keeps
+    // coming while the cursor is locked
+    pub fn add_mouse_motion(&mut self, delta: (f64, f64)) {
+        self.handle_live_event(KbInputEvent::MouseMotion {
+            delta_x: delta.0,
+            delta_y: delta.1,
+        });
+    }
+
+    // Mouse movement since the last frame
+    pub fn get_mouse_delta(&self) -> (f32, f32) {
+        self.get_base_view().get_mouse_delta()
+    }
+
+    // get_mouse_delta() scaled by the player's mouse_sensitivity and flipped by mouse_invert_y
+    pub fn get_mouse_look(&self, game_config: &KbConfig) -> (f32, f32) {
+        self.get_base_view().get_mouse_look(game_config)
+    }
+
+    pub fn set_mouse_position(&mut self, position: &winit::dpi::PhysicalPosition<f64>) {
+        self.handle_live_event(KbInputEvent::MouseMove {
+            x: position.x,
+            y: position.y,
+        });
+    }
+
+    pub fn get_mouse_position(&self) -> (i32, i32) {
+        self.cursor_position
+    }
+
+    // Used to normalize touch positions for touch region bindings
+    pub fn set_screen_size(&mut self, width: u32, height: u32) {
+        self.screen_size = (width, height);
+    }
+
+    pub fn get_screen_size(&self) -> (u32, u32) {
+        self.screen_size
+    }
+
+    // Replaces all action bindings with the ones in bindings_text, a JSON object mapping action
+    // names to arrays of bindings, e.g. "fire": ["space", "mouse_left", { "touch": [0, 0, 1, 1] }]
+    pub fn load_bindings(&mut self, bindings_text: &str) -> Result<(), KbInputError> {
+        let json_bindings = parse_config_text(bindings_text).map_err(|error| match error {
+            KbConfigError::Parse {
+                line,
+                column,
+                message,
+            } => KbInputError::Parse {
+                line,
+                column,
+                message,
+            },
+            error => KbInputError::Parse {
+                line: 1,
+                column: 1,
+                message: error.to_string(),
+            },
+        })?;
+
+        let mut action_bindings = HashMap::<String, Vec<KbInputBinding>>::new();
+        for (action, json_action) in json_bindings.entries() {
+            if action.starts_with('_') {
+                continue;
+            }
+
+            let mut bindings = Vec::<KbInputBinding>::new();
+            for json_binding in json_action.members() {
+                match parse_binding(json_binding) {
+                    Some(binding) => bindings.push(binding),
+                    None => {
+                        return Err(KbInputError::BadBinding {
+                            action: action.to_string(),
+                            line: find_key_line(bindings_text, action),
+                        })
+                    }
+                }
+            }
+            action_bindings.insert(action.to_string(), bindings);
+        }
+
+        self.action_bindings = action_bindings;
+        Ok(())
+    }
+
+    pub fn set_bindings(&mut self, action: &str, bindings: &[KbInputBinding]) {
+        self.action_bindings
+            .insert(action.to_string(), bindings.to_vec());
+    }
+
+    pub fn get_bindings(&self, action: &str) -> &[KbInputBinding] {
+        match self.action_bindings.get(action) {
+            Some(bindings) => bindings,
+            None => &[],
+        }
+    }
+
+    // True while any of the action's bindings are held, including the frame they were pressed
+    pub fn action_down(&self, action: &str) -> bool {
+        self.get_base_view().action_down(action)
+    }
+
+    pub fn action_just_pressed(&self, action: &str) -> bool {
+        self.get_base_view().action_just_pressed(action)
+    }
+
+    pub fn action_just_released(&self, action: &str) -> bool {
+        self.get_base_view().action_just_released(action)
+    }
+
+    // 0.0 to 1.0.  Gamepad axis buttons give how far the axis is pushed, other bindings give 1.0
+    // while down
+    pub fn action_value(&self, action: &str) -> f32 {
+        self.get_base_view().action_value(action)
+    }
+
+    // Combines two opposing actions into a value from -1 to 1, e.g. action_axis("left", "right")
+    pub fn action_axis(&self, negative_action: &str, positive_action: &str) -> f32 {
+        self.get_base_view()
+            .action_axis(negative_action, positive_action)
+    }
+}
+
+// Input as seen by one context on the stack.  Get one with KbInputManager::get_input_context()
+#[derive(Clone, Debug)]
+pub struct KbInputContextView<'a> {
+    input_manager: &'a KbInputManager,
+    consumed: KbConsumedInput<'a>,
+}
+
+impl<'a> KbInputContextView<'a> {
+    pub fn get_key_state(&self, key: &str) -> KbButtonState {
+        let key = resolve_key_alias(key);
+        if self.consumed.is_button_consumed(key) {
+            return KbButtonState::None;
+        }
+
+        match self.input_manager.key_map.get(key) {
+            Some(key_state) => key_state.clone(),
+            None => KbButtonState::None,
+        }
+    }
+
+    pub fn get_touch_map(&self) -> &'a HashMap<u64, KbTouchInfo> {
+        if self.consumed.touches {
+            return &self.input_manager.empty_touch_map;
+        }
+        &self.input_manager.touch_id_to_info
+    }
+
+    pub fn get_gestures(&self) -> &'a [KbGesture] {
+        if self.consumed.touches {
+            return &[];
+        }
+        &self.input_manager.gestures
+    }
+
+    pub fn get_mouse_scroll_delta(&self) -> f32 {
+        if self.consumed.mouse {
+            return 0.0;
+        }
+        self.input_manager.mouse_scroll_delta
+    }
+
+    pub fn get_mouse_delta(&self) -> (f32, f32) {
+        if self.consumed.mouse {
+            return (0.0, 0.0);
+        }
+        self.input_manager.mouse_delta
+    }
+
+    pub fn get_mouse_look(&self, game_config: &KbConfig) -> (f32, f32) {
+        let mouse_delta = self.get_mouse_delta();
+        let y_sign = if game_config.mouse_invert_y {
+            -1.0
+        } else {
+            1.0
+        };
+        (
+            mouse_delta.0 * game_config.mouse_sensitivity,
+            mouse_delta.1 * game_config.mouse_sensitivity * y_sign,
+        )
+    }
+
+    pub fn get_gamepad_button(&self, id: KbGamepadId, button: KbGamepadButton) -> KbButtonState {
+        let button_name = get_gamepad_button_name(button);
+        if self.consumed.is_button_consumed(button_name) {
+            return KbButtonState::None;
+        }
+
+        match self.input_manager.gamepads.get(&id) {
+            Some(gamepad) => gamepad
+                .button_states
+                .get(button_name)
+                .cloned()
+                .unwrap_or_default(),
+            None => KbButtonState::None,
+        }
+    }
+
+    pub fn get_gamepad_axis(&self, id: KbGamepadId, axis: KbGamepadAxis) -> f32 {
+        if self.consumed.gamepads {
+            return 0.0;
+        }
+        self.input_manager.get_dead_zoned_axis(id, axis)
+    }
+
+    pub fn get_gamepad_stick(&self, id: KbGamepadId, stick: KbGamepadStick) -> (f32, f32) {
+        if self.consumed.gamepads {
+            return (0.0, 0.0);
+        }
+        self.input_manager.get_dead_zoned_stick(id, stick)
+    }
+
+    pub fn action_down(&self, action: &str) -> bool {
+        self.get_active_bindings(action).iter().any(|binding| {
+            let binding_state = self.get_binding_state(binding);
+            binding_state.is_down() || binding_state.just_pressed()
+        })
+    }
+
+    pub fn action_just_pressed(&self, action: &str) -> bool {
+        self.get_active_bindings(action)
+            .iter()
+            .any(|binding| self.get_binding_state(binding).just_pressed())
+    }
+
+    pub fn action_just_released(&self, action: &str) -> bool {
+        !self.action_down(action)
+            && self
+                .get_active_bindings(action)
+                .iter()
+                .any(|binding| self.get_binding_state(binding).just_released())
+    }
+
+    pub fn action_value(&self, action: &str) -> f32 {
+        self.get_active_bindings(action)
+            .iter()
+            .map(|binding| self.get_binding_value(binding))
+            .fold(0.0, f32::max)
+    }
+
+    pub fn action_axis(&self, negative_action: &str, positive_action: &str) -> f32 {
+        self.action_value(positive_action) - self.action_value(negative_action)
+    }
+
+    // No bindings are active while typing
+    fn get_active_bindings(&self, action: &str) -> &[KbInputBinding] {
+        if self.input_manager.text_input.is_some() {
+            return &[];
+        }
+        self.input_manager.get_bindings(action)
+    }
+
+    fn get_binding_value(&self, binding: &KbInputBinding) -> f32 {
+        if let KbInputBinding::GamepadButton(name) = binding {
+            if self.consumed.is_button_consumed(name) {
+                return 0.0;
+            }
+            if let Some((axis, sign, _)) = GAMEPAD_AXIS_BUTTON_NAMES
+                .iter()
+                .find(|(_, _, axis_name)| axis_name == name)
+            {
+                return self
+                    .input_manager
+                    .gamepads
+                    .keys()
+                    .map(|id| (self.get_gamepad_axis(*id, *axis) * sign).max(0.0))
+                    .fold(0.0, f32::max);
+            }
+        }
+
+        let binding_state = self.get_binding_state(binding);
+        if binding_state.is_down() || binding_state.just_pressed() {
+            1.0
+        } else {
+            0.0
+        }
+    }
+
+    fn get_binding_state(&self, binding: &KbInputBinding) -> KbButtonState {
+        match binding {
+            KbInputBinding::GamepadButton(name) => {
+                if self.consumed.is_button_consumed(name) {
+                    return KbButtonState::None;
+                }
+
+                // Most recently pressed first, then held, then released
+                let mut binding_state = KbButtonState::None;
+                for gamepad in self.input_manager.gamepads.values() {
+                    let button_state = match gamepad.button_states.get(name) {
+                        Some(button_state) => button_state,
+                        None => continue,
+                    };
+                    match button_state {
+                        KbButtonState::JustPressed { .. } => return button_state.clone(),
+                        KbButtonState::Down { .. } => binding_state = button_state.clone(),
+                        KbButtonState::JustReleased { .. } if !binding_state.is_down() => {
+                            binding_state = button_state.clone()
+                        }
+                        _ => {}
+                    }
+                }
+                binding_state
+            }
+            KbInputBinding::Key(name) | KbInputBinding::MouseButton(name) => {
+                self.get_key_state(name)
+            }
+            KbInputBinding::TouchRegion { min, max } => {
+                if self.input_manager.screen_size.0 == 0 || self.input_manager.screen_size.1 == 0 {
+                    return KbButtonState::None;
+                }
+
+                let mut binding_state = KbButtonState::None;
+                for touch in self.get_touch_map().values() {
+                    let x = touch.current_pos.0 as f32 / self.input_manager.screen_size.0 as f32;
+                    let y = touch.current_pos.1 as f32 / self.input_manager.screen_size.1 as f32;
+                    if x < min.0 || x > max.0 || y < min.1 || y > max.1 {
+                        continue;
+                    }
+
+                    // Like gamepad buttons, pressed beats held beats released
+                    match touch.touch_state {
+                        KbButtonState::JustPressed { .. } => return touch.touch_state.clone(),
+                        KbButtonState::Down { .. } => binding_state = touch.touch_state.clone(),
+                        KbButtonState::JustReleased { .. } if !binding_state.is_down() => {
+                            binding_state = touch.touch_state.clone()
+                        }
+                        _ => {}
+                    }
+                }
+                binding_state
+            }
+        }
+    }
+}
+
+// The touch that started inside the region and has been held the longest.  Touches are matched on
+// where they started so a thumb can slide off a control without letting go of it
+fn find_control_touch(
+    input_manager: &KbInputManager,
+    is_inside: impl Fn((f32, f32)) -> bool,
+) -> Option<&KbTouchInfo> {
+    let (width, height) = input_manager.get_screen_size();
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    input_manager
+        .get_touch_map()
+        .values()
+        .filter(|touch| {
+            if touch.touch_state.just_released() {
+                return false;
+            }
+            let start_pos = (
+                touch.start_pos.0 as f32 / width as f32,
+                touch.start_pos.1 as f32 / height as f32,
+            );
+            is_inside(start_pos)
+        })
+        .max_by(|a, b| {
+            a.touch_state
+                .time_held()
+                .total_cmp(&b.touch_state.time_held())
+        })
+}
+
+// On-screen thumbstick for touch devices.  Positions are in normalized screen space like touch
+// region bindings.  The radius is a fraction of the screen height so the stick stays round
+#[derive(Clone, Debug)]
+pub struct KbVirtualStick {
+    // Touches that start in this region drive the stick
+    pub region_min: (f32, f32),
+    pub region_max: (f32, f32),
+    pub center: (f32, f32),
+    pub radius: f32,
+    // Fraction of the radius that is ignored
+    pub dead_zone: f32,
+    axis: (f32, f32),
+    is_active: bool,
+}
+
+impl KbVirtualStick {
+    pub fn new(
+        region_min: (f32, f32),
+        region_max: (f32, f32),
+        center: (f32, f32),
+        radius: f32,
+    ) -> Self {
+        KbVirtualStick {
+            region_min,
+            region_max,
+            center,
+            radius,
+            dead_zone: 0.2,
+            axis: (0.0, 0.0),
+            is_active: false,
+        }
+    }
+
+    // Call once per tick before reading the axis
+    pub fn update(&mut self, input_manager: &KbInputManager) {
+        let (min, max) = (self.region_min, self.region_max);
+        let touch = find_control_touch(input_manager, |(x, y)| {
+            x >= min.0 && x <= max.0 && y >= min.1 && y <= max.1
+        });
+        let touch = match touch {
+            Some(touch) => touch,
+            None => {
+                self.axis = (0.0, 0.0);
+                self.is_active = false;
+                return;
+            }
+        };
+
+        let (width, height) = input_manager.get_screen_size();
+        let radius_pixels = self.radius * height as f32;
+        let offset_x = (touch.current_pos.0 as f32 - self.center.0 * width as f32) / radius_pixels;
+        let offset_y = (touch.current_pos.1 as f32 - self.center.1 * height as f32) / radius_pixels;
+
+        let length = (offset_x * offset_x + offset_y * offset_y).sqrt();
+        self.axis = if length <= self.dead_zone {
+            (0.0, 0.0)
+        } else {
+            let scale = (length.min(1.0) - self.dead_zone) / (1.0 - self.dead_zone) / length;
+            (offset_x * scale, offset_y * scale)
+        };
+        self.is_active = true;
+    }
+
+    // -1 to 1 on each axis.  Like screen space, +y is down
+    pub fn get_axis(&self) -> (f32, f32) {
+        self.axis
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.is_active
+    }
+}
+
+// On-screen button for touch devices.  Positioned like KbVirtualStick
+#[derive(Clone, Debug)]
+pub struct KbVirtualButton {
+    pub center: (f32, f32),
+    pub radius: f32,
+    button_state: KbButtonState,
+}
+
+impl KbVirtualButton {
+    pub fn new(center: (f32, f32), radius: f32) -> Self {
+        KbVirtualButton {
+            center,
+            radius,
+            button_state: KbButtonState::None,
+        }
+    }
+
+    // Call once per tick before reading the state
+    pub fn update(&mut self, input_manager: &KbInputManager) {
+        let (width, height) = input_manager.get_screen_size();
+        let aspect_ratio = width as f32 / height.max(1) as f32;
+        let (center, radius) = (self.center, self.radius);
+        let touch = find_control_touch(input_manager, |(x, y)| {
+            let offset_x = (x - center.0) * aspect_ratio;
+            let offset_y = y - center.1;
+            offset_x * offset_x + offset_y * offset_y <= radius * radius
+        });
+
+        self.button_state = match (touch, &self.button_state) {
+            (Some(touch), _) => touch.touch_state.clone(),
+            (None, KbButtonState::JustPressed { .. } | KbButtonState::Down { .. }) => {
+                KbButtonState::JustReleased {
+                    time_held: self.button_state.time_held(),
+                }
+            }
+            (None, _) => KbButtonState::None,
+        };
+    }
+
+    pub fn get_state(&self) -> KbButtonState {
+        self.button_state.clone()
+    }
+}
+
+// Gamepad input from code instead of hardware, for tests and machines without a controller.  Clones
+// share their events, so keep a clone to drive the one given to set_gamepad_backend()
+#[derive(Clone, Debug, Default)]
+pub struct KbSyntheticGamepadBackend {
+    events: Rc<RefCell<Vec<KbGamepadEvent>>>,
+}
+
+impl KbSyntheticGamepadBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn connect(&self, id: KbGamepadId) {
+        self.events
+            .borrow_mut()
+            .push(KbGamepadEvent::Connected { id });
+    }
+
+    pub fn disconnect(&self, id: KbGamepadId) {
+        self.events
+            .borrow_mut()
+            .push(KbGamepadEvent::Disconnected { id });
+    }
+
+    pub fn set_button(&self, id: KbGamepadId, button: KbGamepadButton, pressed: bool) {
+        self.events.borrow_mut().push(KbGamepadEvent::Button {
+            id,
+            button,
+            pressed,
+        });
+    }
+
+    pub fn set_axis(&self, id: KbGamepadId, axis: KbGamepadAxis, value: f32) {
+        self.events
+            .borrow_mut()
+            .push(KbGamepadEvent::Axis { id, axis, value });
+    }
+}
+
+impl KbGamepadBackend for KbSyntheticGamepadBackend {
+    fn poll(&mut self, events: &mut Vec<KbGamepadEvent>) {
+        events.append(&mut self.events.borrow_mut());
+    }
+}
+
+// Real controllers through gilrs.  Enabled with the "gamepad" feature on native builds
+#[cfg(all(feature = "gamepad", not(target_arch = "wasm32")))]
+#[derive(Debug)]
+pub struct KbGilrsGamepadBackend {
+    gilrs: gilrs::Gilrs,
+    connected_events: Vec<KbGamepadEvent>,
+}
+
+#[cfg(all(feature = "gamepad", not(target_arch = "wasm32")))]
+impl KbGilrsGamepadBackend {
+    pub fn new() -> Result<Self, String> {
+        let gilrs = gilrs::Gilrs::new().map_err(|error| error.to_string())?;
+
+        // Pads plugged in before the game started don't send Connected events
+        let connected_events = gilrs
+            .gamepads()
+            .map(|(id, _)| KbGamepadEvent::Connected { id: id.into() })
+            .collect();
+        Ok(KbGilrsGamepadBackend {
+            gilrs,
+            connected_events,
+        })
+    }
+}
+
+#[cfg(all(feature = "gamepad", not(target_arch = "wasm32")))]
+impl KbGamepadBackend for KbGilrsGamepadBackend {
+    fn poll(&mut self, events: &mut Vec<KbGamepadEvent>) {
+        use gilrs::{Axis, Button, EventType};
+
+        events.append(&mut self.connected_events);
+        while let Some(gilrs::Event { id, event, .. }) = self.gilrs.next_event() {
+            let id: KbGamepadId = id.into();
+            let gamepad_event = match event {
+                EventType::Connected => KbGamepadEvent::Connected { id },
+                EventType::Disconnected => KbGamepadEvent::Disconnected { id },
+                // Analog triggers
+                EventType::ButtonChanged(Button::LeftTrigger2, value, _) => KbGamepadEvent::Axis {
+                    id,
+                    axis: KbGamepadAxis::LeftTrigger,
+                    value,
+                },
+                EventType::ButtonChanged(Button::RightTrigger2, value, _) => KbGamepadEvent::Axis {
+                    id,
+                    axis: KbGamepadAxis::RightTrigger,
+                    value,
+                },
+                EventType::ButtonPressed(button, _) | EventType::ButtonReleased(button, _) => {
+                    let button = match button {
+                        Button::South => KbGamepadButton::South,
+                        Button::East => KbGamepadButton::East,
+                        Button::West => KbGamepadButton::West,
+                        Button::North => KbGamepadButton::North,
+                        Button::LeftTrigger => KbGamepadButton::LeftBumper,
+                        Button::RightTrigger => KbGamepadButton::RightBumper,
+                        Button::Select => KbGamepadButton::Select,
+                        Button::Start => KbGamepadButton::Start,
+                        Button::LeftThumb => KbGamepadButton::LeftStick,
+                        Button::RightThumb => KbGamepadButton::RightStick,
+                        Button::DPadUp => KbGamepadButton::DPadUp,
+                        Button::DPadDown => KbGamepadButton::DPadDown,
+                        Button::DPadLeft => KbGamepadButton::DPadLeft,
+                        Button::DPadRight => KbGamepadButton::DPadRight,
+                        _ => continue,
+                    };
+                    KbGamepadEvent::Button {
+                        id,
+                        button,
+                        pressed: matches!(event, EventType::ButtonPressed(..)),
+                    }
+                }
+                // gilrs sticks are +y up
+                EventType::AxisChanged(axis, value, _) => {
+                    let (axis, value) = match axis {
+                        Axis::LeftStickX => (KbGamepadAxis::LeftStickX, value),
+                        Axis::LeftStickY => (KbGamepadAxis::LeftStickY, -value),
+                        Axis::RightStickX => (KbGamepadAxis::RightStickX, value),
+                        Axis::RightStickY => (KbGamepadAxis::RightStickY, -value),
+                        _ => continue,
+                    };
+                    KbGamepadEvent::Axis { id, axis, value }
+                }
+                _ => continue,
+            };
+            events.push(gamepad_event);
+        }
+    }
+}
+
+// Times are in seconds.  Distances are fractions of the screen height
+const TAP_MAX_TIME: f32 = 0.25;
+const TAP_MAX_DISTANCE: f64 = 0.02;
+const DOUBLE_TAP_MAX_TIME: f32 = 0.3;
+const LONG_PRESS_TIME: f32 = 0.5;
+const SWIPE_MAX_TIME: f32 = 0.5;
+const SWIPE_MIN_DISTANCE: f64 = 0.1;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KbSwipeDirection {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+// Positions are in pixels like KbTouchInfo
+#[derive(Clone, Debug, PartialEq)]
+pub enum KbGesture {
+    Tap {
+        position: (f64, f64),
+    },
+    // Sent after the second tap's Tap
+    DoubleTap {
+        position: (f64, f64),
+    },
+    // Sent once while the touch is still held.  The touch won't also be a tap or swipe
+    LongPress {
+        position: (f64, f64),
+    },
+    Swipe {
+        direction: KbSwipeDirection,
+        start: (f64, f64),
+        end: (f64, f64),
+    },
+    // Two fingers moved apart or together.  scale is the change in distance since the last Pinch,
+    // e.g. 1.1 when the fingers are 10% further apart
+    Pinch {
+        scale: f64,
+        center: (f64, f64),
+    },
+    // Two fingers twisted.  radians is the change in angle since the last Rotate, clockwise on
+    // screen
+    Rotate {
+        radians: f64,
+        center: (f64, f64),
+    },
+}
+
+#[derive(Debug)]
+struct KbGestureTouch {
+    start_pos: (f64, f64),
+    current_pos: (f64, f64),
+    start_time: f64,
+    moved: bool,
+    long_pressed: bool,
+    // Touches that were ever part of a two finger gesture aren't taps or swipes
+    multi_touch: bool,
+}
+
+#[derive(Debug, Default)]
+struct KbGestureRecognizer {
+    touches: HashMap<u64, KbGestureTouch>,
+    last_tap: Option<(f64, (f64, f64))>,
+    // Distance and angle between the fingers of a two finger gesture
+    two_finger_state: Option<(f64, f64)>,
+}
+
+impl KbGestureRecognizer {
+    fn on_touch(
+        &mut self,
+        id: u64,
+        phase: TouchPhase,
+        position: (f64, f64),
+        screen_height: u32,
+        gestures: &mut Vec<KbGesture>,
+    ) {
+        let screen_height = screen_height.max(1) as f64;
+        match phase {
+            TouchPhase::Started => {
+                let multi_touch = !self.touches.is_empty();
+                for touch in self.touches.values_mut() {
+                    touch.multi_touch = true;
+                }
+                self.touches.insert(
+                    id,
+                    KbGestureTouch {
+                        start_pos: position,
+                        current_pos: position,
+                        start_time: kb_time_now(),
+                        moved: false,
+                        long_pressed: false,
+                        multi_touch,
+                    },
+                );
+                self.two_finger_state = self.get_two_finger_state();
+            }
+            TouchPhase::Moved => {
+                if let Some(touch) = self.touches.get_mut(&id) {
+                    touch.current_pos = position;
+                    if distance(touch.start_pos, position) > TAP_MAX_DISTANCE * screen_height {
+                        touch.moved = true;
+                    }
+                }
+                self.update_two_finger_gestures(gestures);
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                if let Some(touch) = self.touches.remove(&id) {
+                    if phase == TouchPhase::Ended && !touch.multi_touch && !touch.long_pressed {
+                        self.on_touch_ended(&touch, position, screen_height, gestures);
+                    }
+                }
+                self.two_finger_state = self.get_two_finger_state();
+            }
+        }
+    }
+
+    fn on_touch_ended(
+        &mut self,
+        touch: &KbGestureTouch,
+        end_pos: (f64, f64),
+        screen_height: f64,
+        gestures: &mut Vec<KbGesture>,
+    ) {
+        let cur_time = kb_time_now();
+        let touch_time = (cur_time - touch.start_time) as f32;
+        // The touch can end away from where it was last moved to
+        let moved =
+            touch.moved || distance(touch.start_pos, end_pos) > TAP_MAX_DISTANCE * screen_height;
+
+        if !moved && touch_time <= TAP_MAX_TIME {
+            gestures.push(KbGesture::Tap { position: end_pos });
+
+            let is_double_tap = match self.last_tap {
+                Some((tap_time, tap_pos)) => {
+                    ((cur_time - tap_time) as f32) <= DOUBLE_TAP_MAX_TIME
+                        && distance(tap_pos, end_pos) <= TAP_MAX_DISTANCE * screen_height
+                }
+                None => false,
+            };
+            if is_double_tap {
+                gestures.push(KbGesture::DoubleTap { position: end_pos });
+                self.last_tap = None;
+            } else {
+                self.last_tap = Some((cur_time, end_pos));
+            }
+            return;
+        }
+
+        let offset = (end_pos.0 - touch.start_pos.0, end_pos.1 - touch.start_pos.1);
+        if touch_time <= SWIPE_MAX_TIME
+            && distance(touch.start_pos, end_pos) >= SWIPE_MIN_DISTANCE * screen_height
+        {
+            let direction = if offset.0.abs() > offset.1.abs() {
+                if offset.0 < 0.0 {
+                    KbSwipeDirection::Left
+                } else {
+                    KbSwipeDirection::Right
+                }
+            } else if offset.1 < 0.0 {
+                KbSwipeDirection::Up
+            } else {
+                KbSwipeDirection::Down
+            };
+            gestures.push(KbGesture::Swipe {
+                direction,
+                start: touch.start_pos,
+                end: end_pos,
+            });
+        }
+    }
+
+    // Sends long presses for touches that have been held in place long enough
+    fn update(&mut self, gestures: &mut Vec<KbGesture>) {
+        for touch in self.touches.values_mut() {
+            if touch.moved || touch.long_pressed || touch.multi_touch {
+                continue;
+            }
+            if kb_time_since(touch.start_time) >= LONG_PRESS_TIME {
+                touch.long_pressed = true;
+                gestures.push(KbGesture::LongPress {
+                    position: touch.current_pos,
+                });
+            }
+        }
+    }
+
+    fn get_two_finger_state(&self) -> Option<(f64, f64)> {
+        if self.touches.len() != 2 {
+            return None;
+        }
+
+        let mut positions = self.touches.values().map(|touch| touch.current_pos);
+        let (first, second) = (positions.next()?, positions.next()?);
+        Some((
+            distance(first, second),
+            (second.1 - first.1).atan2(second.0 - first.0),
+        ))
+    }
+
+    fn update_two_finger_gestures(&mut self, gestures: &mut Vec<KbGesture>) {
+        let (prev_distance, prev_angle) = match self.two_finger_state {
+            Some(two_finger_state) => two_finger_state,
+            None => return,
+        };
+        let (cur_distance, cur_angle) = match self.get_two_finger_state() {
+            Some(two_finger_state) => two_finger_state,
+            None => return,
+        };
+
+        let mut positions = self.touches.values().map(|touch| touch.current_pos);
+        let (first, second) = (positions.next().unwrap(), positions.next().unwrap());
+        let center = ((first.0 + second.0) * 0.5, (first.1 + second.1) * 0.5);
+
+        if prev_distance > 0.0 && cur_distance != prev_distance {
+            gestures.push(KbGesture::Pinch {
+                scale: cur_distance / prev_distance,
+                center,
+            });
+        }
+
+        // Keep the change in -PI..PI so crossing the atan2 seam isn't a full turn
+        let mut radians = cur_angle - prev_angle;
+        if radians > std::f64::consts::PI {
+            radians -= std::f64::consts::TAU;
+        } else if radians < -std::f64::consts::PI {
+            radians += std::f64::consts::TAU;
+        }
+        if radians != 0.0 {
+            gestures.push(KbGesture::Rotate { radians, center });
+        }
+
+        self.two_finger_state = Some((cur_distance, cur_angle));
+    }
+}
+
+fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    ((b.0 - a.0) * (b.0 - a.0) + (b.1 - a.1) * (b.1 - a.1)).sqrt()
+}
+
+fn apply_text_edit(text_input: &mut KbTextInput, text_edit: KbTextEdit) {
+    let text = &mut text_input.text;
+    let prev_char_start = text[..text_input.cursor]
+        .char_indices()
+        .next_back()
+        .map(|(i, _)| i);
+    let next_char_end = text[text_input.cursor..]
+        .chars()
+        .next()
+        .map(|c| text_input.cursor + c.len_utf8());
+
+    match text_edit {
+        KbTextEdit::Backspace => {
+            if let Some(prev_char_start) = prev_char_start {
+                text.replace_range(prev_char_start..text_input.cursor, "");
+                text_input.cursor = prev_char_start;
+            }
+        }
+        KbTextEdit::Delete => {
+            if let Some(next_char_end) = next_char_end {
+                text.replace_range(text_input.cursor..next_char_end, "");
+            }
+        }
+        KbTextEdit::Left => text_input.cursor = prev_char_start.unwrap_or(text_input.cursor),
+        KbTextEdit::Right => text_input.cursor = next_char_end.unwrap_or(text_input.cursor),
+        KbTextEdit::Home => text_input.cursor = 0,
+        KbTextEdit::End => text_input.cursor = text.len(),
+        KbTextEdit::Submit => text_input.submitted = true,
+    }
+}
+
+// JustPressed becomes Down and JustReleased becomes None once they have been seen for a frame
+fn advance_button_state(button_state: &mut KbButtonState, mouse_start: (i32, i32)) {
+    match *button_state {
+        KbButtonState::JustPressed { press_time } => {
+            *button_state = KbButtonState::Down {
+                mouse_start,
+                press_time,
+            }
+        }
+        KbButtonState::JustReleased { .. } => *button_state = KbButtonState::None,
+        _ => {}
+    }
+}
+
+// Held buttons ignore repeated presses, and a release that was never pressed is dropped
+fn update_button_state(button_state: &mut KbButtonState, pressed: bool) {
+    match (pressed, button_state.clone()) {
+        (true, KbButtonState::None | KbButtonState::JustReleased { .. }) => {
+            *button_state = KbButtonState::JustPressed {
+                press_time: kb_time_now(),
+            };
+        }
+        (
+            false,
+            KbButtonState::JustPressed { press_time } | KbButtonState::Down { press_time, .. },
+        ) => {
+            *button_state = KbButtonState::JustReleased {
+                time_held: kb_time_since(press_time),
+            };
+        }
+        _ => {}
+    }
+}
+
+fn get_gamepad_button_name(button: KbGamepadButton) -> &'static str {
+    GAMEPAD_BUTTON_NAMES
+        .iter()
+        .find(|(b, _)| *b == button)
+        .map(|(_, name)| *name)
+        .unwrap()
+}
+
+// "w" and "space" are keys, "mouse_left" is a mouse button, "pad_south" and "pad_left_stick_up" are
+// gamepad buttons and { "touch": [min_x, min_y, max_x, max_y] } is a touch region
+fn parse_binding(json_binding: &json::JsonValue) -> Option<KbInputBinding> {
+    if let Some(name) = json_binding.as_str() {
+        if let Some(key_name) = find_key_name(name) {
+            return Some(KbInputBinding::Key(key_name));
+        }
+        if let Some((_, button_name)) = MOUSE_BUTTON_NAMES.iter().find(|(_, n)| *n == name) {
+            return Some(KbInputBinding::MouseButton(button_name));
+        }
+        if let Some((_, button_name)) = GAMEPAD_BUTTON_NAMES.iter().find(|(_, n)| *n == name) {
+            return Some(KbInputBinding::GamepadButton(button_name));
+        }
+        if let Some((_, _, button_name)) = GAMEPAD_AXIS_BUTTON_NAMES
+            .iter()
+            .find(|(_, _, n)| *n == name)
+        {
+            return Some(KbInputBinding::GamepadButton(button_name));
+        }
+        return None;
+    }
+
+    let json_region = &json_binding["touch"];
+    if !json_region.is_array() || json_region.len() != 4 {
+        return None;
+    }
+    let min = (json_region[0].as_f32()?, json_region[1].as_f32()?);
+    let max = (json_region[2].as_f32()?, json_region[3].as_f32()?);
+    Some(KbInputBinding::TouchRegion { min, max })
+}
+
+fn parse_input_event(words: &[&str]) -> Option<KbInputEvent> {
+    let event = match *words {
+        ["button", name, pressed] => {
+            let name = find_key_name(name).or_else(|| {
+                MOUSE_BUTTON_NAMES
+                    .iter()
+                    .find(|(_, button_name)| *button_name == name)
+                    .map(|(_, button_name)| *button_name)
+            })?;
+            KbInputEvent::Button {
+                name,
+                pressed: pressed == "1",
+            }
+        }
+        ["mouse_move", x, y] => KbInputEvent::MouseMove {
+            x: x.parse().ok()?,
+            y: y.parse().ok()?,
+        },
+        ["scroll", delta] => KbInputEvent::MouseScroll {
+            delta: delta.parse().ok()?,
+        },
+        ["mouse_motion", delta_x, delta_y] => KbInputEvent::MouseMotion {
+            delta_x: delta_x.parse().ok()?,
+            delta_y: delta_y.parse().ok()?,
+        },
+        ["touch", id, phase, x, y] => KbInputEvent::Touch {
+            id: id.parse().ok()?,
+            phase: match phase {
+                "started" => TouchPhase::Started,
+                "moved" => TouchPhase::Moved,
+                "ended" => TouchPhase::Ended,
+                "cancelled" => TouchPhase::Cancelled,
+                _ => return None,
+            },
+            x: x.parse().ok()?,
+            y: y.parse().ok()?,
+        },
+        ["modifiers", shift, control, alt, logo] => KbInputEvent::Modifiers(KbModifiers {
+            shift: shift == "1",
+            control: control == "1",
+            alt: alt == "1",
+            logo: logo == "1",
+        }),
+        ["pad_connected", id] => KbInputEvent::Gamepad(KbGamepadEvent::Connected {
+            id: id.parse().ok()?,
+        }),
+        ["pad_disconnected", id] => KbInputEvent::Gamepad(KbGamepadEvent::Disconnected {
+            id: id.parse().ok()?,
+        }),
+        ["pad_button", id, button, pressed] => KbInputEvent::Gamepad(KbGamepadEvent::Button {
+            id: id.parse().ok()?,
+            button: GAMEPAD_BUTTON_NAMES
+                .iter()
+                .find(|(_, name)| *name == button)
+                .map(|(b, _)| *b)?,
+            pressed: pressed == "1",
+        }),
+        ["pad_axis", id, axis, value] => KbInputEvent::Gamepad(KbGamepadEvent::Axis {
+            id: id.parse().ok()?,
+            axis: GAMEPAD_AXIS_NAMES
+                .iter()
+                .find(|(_, name)| *name == axis)
+                .map(|(a, _)| *a)?,
+            value: value.parse().ok()?,
+        }),
+        ["text", ref codes @ ..] => KbInputEvent::Text(parse_text_codes(codes)?),
+        ["text_edit", edit] => KbInputEvent::TextEdit(
+            TEXT_EDIT_NAMES
+                .iter()
+                .find(|(_, name)| *name == edit)
+                .map(|(e, _)| *e)?,
+        ),
+        ["ime_preedit", ref codes @ ..] => KbInputEvent::ImePreedit(parse_text_codes(codes)?),
+        _ => return None,
+    };
+    Some(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-5,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    // An input manager reading gamepads from the returned backend.  Its events arrive on the next
+    // begin_frame()
+    fn new_input_manager_with_gamepads() -> (KbInputManager, KbSyntheticGamepadBackend) {
+        let gamepad_backend = KbSyntheticGamepadBackend::new();
+        let mut input_manager = KbInputManager::new();
+        input_manager.set_gamepad_backend(Box::new(gamepad_backend.clone()));
+        (input_manager, gamepad_backend)
+    }
+
+    fn next_frame(input_manager: &mut KbInputManager) {
+        input_manager.update_key_states();
+        input_manager.begin_frame();
+    }
+
+    #[test]
+    fn gamepad_dead_zones() {
+        let (mut input_manager, gamepad_backend) = new_input_manager_with_gamepads();
+        gamepad_backend.connect(0);
+        let left_stick = |input_manager: &KbInputManager| {
+            input_manager.get_gamepad_stick(0, KbGamepadStick::Left)
+        };
+
+        // Inside the round dead zone, even when both axes together are outside a square one
+        gamepad_backend.set_axis(0, KbGamepadAxis::LeftStickX, 0.1);
+        gamepad_backend.set_axis(0, KbGamepadAxis::LeftStickY, 0.1);
+        input_manager.begin_frame();
+        assert_eq!(left_stick(&input_manager), (0.0, 0.0));
+        assert_eq!(
+            input_manager.get_gamepad_axis(0, KbGamepadAxis::LeftStickX),
+            0.0
+        );
+
+        // The rest of the range is rescaled to start at 0
+        gamepad_backend.set_axis(0, KbGamepadAxis::LeftStickX, 0.575);
+        gamepad_backend.set_axis(0, KbGamepadAxis::LeftStickY, 0.0);
+        next_frame(&mut input_manager);
+        assert_near(left_stick(&input_manager).0, 0.5);
+        assert_near(
+            input_manager.get_gamepad_axis(0, KbGamepadAxis::LeftStickX),
+            0.5,
+        );
+
+        // Diagonals keep their direction and sticks past the edge are clamped
+        gamepad_backend.set_axis(0, KbGamepadAxis::LeftStickX, -0.6);
+        gamepad_backend.set_axis(0, KbGamepadAxis::LeftStickY, 0.8);
+        next_frame(&mut input_manager);
+        assert_near(left_stick(&input_manager).0, -0.6);
+        assert_near(left_stick(&input_manager).1, 0.8);
+        gamepad_backend.set_axis(0, KbGamepadAxis::LeftStickX, 1.5);
+        gamepad_backend.set_axis(0, KbGamepadAxis::LeftStickY, 0.0);
+        next_frame(&mut input_manager);
+        assert_near(left_stick(&input_manager).0, 1.0);
+
+        // Triggers only go one way
+        for (value, expected) in [(0.1, 0.0), (0.575, 0.5), (1.0, 1.0), (-1.0, 0.0)] {
+            gamepad_backend.set_axis(0, KbGamepadAxis::RightTrigger, value);
+            next_frame(&mut input_manager);
+            assert_near(
+                input_manager.get_gamepad_axis(0, KbGamepadAxis::RightTrigger),
+                expected,
+            );
+        }
+
+        input_manager.set_gamepad_dead_zone(0.0);
+        gamepad_backend.set_axis(0, KbGamepadAxis::RightTrigger, 0.1);
+        next_frame(&mut input_manager);
+        assert_near(
+            input_manager.get_gamepad_axis(0, KbGamepadAxis::RightTrigger),
+            0.1,
+        );
+    }
+
+    #[test]
+    fn gamepads_connect_disconnect_and_keep_their_own_state() {
+        let (mut input_manager, gamepad_backend) = new_input_manager_with_gamepads();
+        input_manager.begin_frame();
+        assert!(input_manager.get_gamepad_ids().is_empty());
+
+        gamepad_backend.connect(3);
+        gamepad_backend.connect(0);
+        gamepad_backend.set_button(3, KbGamepadButton::South, true);
+        gamepad_backend.set_axis(0, KbGamepadAxis::LeftStickY, -1.0);
+        next_frame(&mut input_manager);
+        assert_eq!(input_manager.get_gamepad_ids(), [0, 3]);
+        assert!(input_manager
+            .get_gamepad_button(3, KbGamepadButton::South)
+            .just_pressed());
+        assert_eq!(
+            input_manager.get_gamepad_button(0, KbGamepadButton::South),
+            KbButtonState::None
+        );
+        assert_eq!(
+            input_manager.get_gamepad_stick(0, KbGamepadStick::Left),
+            (0.0, -1.0)
+        );
+        assert_eq!(
+            input_manager.get_gamepad_stick(3, KbGamepadStick::Left),
+            (0.0, 0.0)
+        );
+
+        next_frame(&mut input_manager);
+        assert!(input_manager
+            .get_gamepad_button(3, KbGamepadButton::South)
+            .is_down());
+
+        gamepad_backend.set_button(3, KbGamepadButton::South, false);
+        next_frame(&mut input_manager);
+        assert!(input_manager
+            .get_gamepad_button(3, KbGamepadButton::South)
+            .just_released());
+
+        // Disconnecting forgets the pad's state
+        gamepad_backend.set_button(0, KbGamepadButton::East, true);
+        gamepad_backend.disconnect(0);
+        next_frame(&mut input_manager);
+        assert_eq!(input_manager.get_gamepad_ids(), [3]);
+        assert!(!input_manager.is_gamepad_connected(0));
+        assert_eq!(
+            input_manager.get_gamepad_button(0, KbGamepadButton::East),
+            KbButtonState::None
+        );
+        assert_eq!(
+            input_manager.get_gamepad_stick(0, KbGamepadStick::Left),
+            (0.0, 0.0)
+        );
+
+        gamepad_backend.connect(0);
+        next_frame(&mut input_manager);
+        assert_eq!(input_manager.get_gamepad_ids(), [0, 3]);
+        assert_eq!(
+            input_manager.get_gamepad_stick(0, KbGamepadStick::Left),
+            (0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn gamepad_actions_are_analog() {
+        let (mut input_manager, gamepad_backend) = new_input_manager_with_gamepads();
+        input_manager
+            .load_bindings(
+                r#"{
+                    "left": ["a", "pad_left_stick_left"],
+                    "right": ["d", "pad_left_stick_right"],
+                    "jump": ["pad_south"]
+                }"#,
+            )
+            .unwrap();
+        gamepad_backend.connect(0);
+        gamepad_backend.connect(1);
+        gamepad_backend.set_axis(0, KbGamepadAxis::LeftStickX, -0.575);
+        input_manager.begin_frame();
+        assert_near(input_manager.action_axis("left", "right"), -0.5);
+        assert_near(input_manager.action_value("left"), 0.5);
+        assert!(!input_manager.action_down("left"));
+
+        // Past the axis button threshold the action is also down
+        gamepad_backend.set_axis(0, KbGamepadAxis::LeftStickX, -1.0);
+        next_frame(&mut input_manager);
+        assert_near(input_manager.action_axis("left", "right"), -1.0);
+        assert!(input_manager.action_just_pressed("left"));
+
+        // Every pad and key counts, and opposite directions cancel out
+        gamepad_backend.set_axis(1, KbGamepadAxis::LeftStickX, 0.575);
+        next_frame(&mut input_manager);
+        assert_near(input_manager.action_axis("left", "right"), -0.5);
+        input_manager.set_key_state(PhysicalKey::Code(KeyCode::KeyD), ElementState::Pressed);
+        assert_near(input_manager.action_axis("left", "right"), 0.0);
+
+        gamepad_backend.set_button(1, KbGamepadButton::South, true);
+        next_frame(&mut input_manager);
+        assert_eq!(input_manager.action_value("jump"), 1.0);
+
+        // Contexts that consume gamepads hide all of it
+        let mut menu = KbInputContext::new("menu");
+        menu.consume_gamepads = true;
+        input_manager.push_input_context(menu);
+        assert_eq!(input_manager.action_value("jump"), 0.0);
+        assert_near(input_manager.action_axis("left", "right"), 1.0);
+        assert_eq!(
+            input_manager.get_gamepad_stick(0, KbGamepadStick::Left),
+            (0.0, 0.0)
+        );
+    }
+
+    // Unique to this test run so parallel runs don't share files
+    fn temp_path(name: &str) -> String {
+        let file_name = format!("kb_input_{}_{}", std::process::id(), name);
+        std::env::temp_dir()
+            .join(file_name)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn failed_recording_and_playback_leave_time_and_random_alone() {
+        let clock = Rc::new(KbManualClock::new());
+        clock.set_time(123.0);
+        kb_set_clock(clock.clone());
+        kb_seed_random(77);
+        let mut input_manager = KbInputManager::new();
+
+        let missing_path = temp_path("missing_dir/recording.txt");
+        let error = input_manager.start_recording(&missing_path);
+        assert!(matches!(error, Err(KbInputError::Io { ref path, .. }) if *path == missing_path));
+        assert!(!input_manager.is_recording());
+        let error = input_manager.start_playback(&missing_path);
+        assert!(matches!(error, Err(KbInputError::Io { .. })));
+
+        let recording_path = temp_path("bad_recording.txt");
+        std::fs::write(
+            &recording_path,
+            "seed 5\nstart_time 10\n0 time 10\n0 not_an_event 1\n",
+        )
+        .unwrap();
+        let error = input_manager.start_playback(&recording_path);
+        let _ = std::fs::remove_file(&recording_path);
+        assert_eq!(
+            error,
+            Err(KbInputError::BadRecordingLine {
+                line: 4,
+                text: "0 not_an_event 1".to_string(),
+            })
+        );
+        assert!(!input_manager.is_playing_back());
+
+        // Still the test's clock and random sequence
+        clock.advance(1.0);
+        assert_eq!(kb_time_now(), 124.0);
+        let random_value = crate::kb_utils::kb_random_u32(0, u32::MAX - 1);
+        kb_seed_random(77);
+        assert_eq!(
+            crate::kb_utils::kb_random_u32(0, u32::MAX - 1),
+            random_value
+        );
+    }
+
+    #[test]
+    fn playback_rejects_times_that_go_backwards() {
+        let mut input_manager = KbInputManager::new();
+        let recording_path = temp_path("backwards_recording.txt");
+        let recordings = [
+            (
+                "start_time 10\n0 time 10\n1 time 12\n2 time 11\n",
+                4,
+                "2 time 11",
+            ),
+            ("start_time 10\n0 time 9\n", 2, "0 time 9"),
+            ("start_time -1\n", 1, "start_time -1"),
+        ];
+        for (recording_text, line, text) in recordings {
+            std::fs::write(&recording_path, recording_text).unwrap();
+            let error = input_manager.start_playback(&recording_path);
+            assert_eq!(
+                error,
+                Err(KbInputError::BadRecordingLine {
+                    line,
+                    text: text.to_string(),
+                })
+            );
+            assert!(!input_manager.is_playing_back());
+        }
+
+        // Equal times are fine, e.g. frames recorded while paused
+        std::fs::write(&recording_path, "start_time 10\n0 time 10\n1 time 10\n").unwrap();
+        let result = input_manager.start_playback(&recording_path);
+        let _ = std::fs::remove_file(&recording_path);
+        result.unwrap();
+        assert_eq!(kb_time_now(), 10.0);
+    }
+
+    #[test]
+    fn key_releases_hold_times_and_modifiers() {
+        let clock = Rc::new(KbManualClock::new());
+        kb_set_clock(clock.clone());
+        let mut input_manager = KbInputManager::new();
+        input_manager
+            .load_bindings(r#"{ "jump": ["space"] }"#)
+            .unwrap();
+        let space = PhysicalKey::Code(KeyCode::Space);
+
+        input_manager.set_key_state(space, ElementState::Pressed);
+        assert!(input_manager.get_key_state("space").just_pressed());
+        assert_eq!(input_manager.get_key_state("space").time_held(), 0.0);
+        clock.advance(0.5);
+        next_frame(&mut input_manager);
+        input_manager.set_key_state(space, ElementState::Pressed);
+        assert!(input_manager.get_key_state("space").is_down());
+        assert_eq!(input_manager.get_key_state("space").time_held(), 0.5);
+        assert!(!input_manager.action_just_released("jump"));
+
+        clock.advance(0.25);
+        input_manager.set_key_state(space, ElementState::Released);
+        clock.advance(1.0);
+        assert_eq!(
+            input_manager.get_key_state("space"),
+            KbButtonState::JustReleased { time_held: 0.75 }
+        );
+        assert!(input_manager.action_just_released("jump"));
+        assert!(!input_manager.action_down("jump"));
+        next_frame(&mut input_manager);
+        assert_eq!(input_manager.get_key_state("space"), KbButtonState::None);
+        assert!(!input_manager.action_just_released("jump"));
+
+        // A release that was never pressed is dropped
+        input_manager.set_key_state(PhysicalKey::Code(KeyCode::KeyQ), ElementState::Released);
+        assert_eq!(input_manager.get_key_state("q"), KbButtonState::None);
+
+        let modifiers =
+            winit::keyboard::ModifiersState::SHIFT | winit::keyboard::ModifiersState::ALT;
+        input_manager.set_modifiers(&modifiers);
+        assert_eq!(
+            input_manager.get_modifiers(),
+            KbModifiers {
+                shift: true,
+                control: false,
+                alt: true,
+                logo: false,
+            }
+        );
+        next_frame(&mut input_manager);
+        assert!(input_manager.get_modifiers().shift);
+        input_manager.set_modifiers(&winit::keyboard::ModifiersState::empty());
+        assert_eq!(input_manager.get_modifiers(), KbModifiers::default());
+    }
+
+    #[test]
+    fn old_key_names_still_work() {
+        let mut input_manager = KbInputManager::new();
+        input_manager
+            .load_bindings(r#"{ "zoom_in": ["+"], "zoom_out": ["minus"] }"#)
+            .unwrap();
+        assert_eq!(
+            input_manager.get_bindings("zoom_in"),
+            [KbInputBinding::Key("equal")]
+        );
+
+        input_manager.set_key_state(PhysicalKey::Code(KeyCode::Equal), ElementState::Pressed);
+        input_manager.set_key_state(PhysicalKey::Code(KeyCode::Minus), ElementState::Pressed);
+        assert!(input_manager.action_down("zoom_in"));
+        assert!(input_manager.action_down("zoom_out"));
+        assert!(input_manager.get_key_state("+").just_pressed());
+        assert!(input_manager.get_key_state("-").just_pressed());
+
+        let mut menu = KbInputContext::new("menu");
+        menu.consumed_buttons.push("+".to_string());
+        input_manager.push_input_context(menu);
+        assert!(!input_manager.action_down("zoom_in"));
+        assert!(input_manager.action_down("zoom_out"));
+
+        assert_eq!(
+            parse_input_event(&["button", "+", "1"]),
+            Some(KbInputEvent::Button {
+                name: "equal",
+                pressed: true,
+            })
+        );
+    }
+
+    #[test]
+    fn mouse_motion_adds_up_until_the_next_frame() {
+        let mut input_manager = KbInputManager::new();
+        input_manager.set_mouse_position(&winit::dpi::PhysicalPosition::new(100.0, 50.0));
+        input_manager.add_mouse_motion((3.0, 4.0));
+        input_manager.add_mouse_motion((2.0, -1.5));
+        assert_eq!(input_manager.get_mouse_delta(), (5.0, 2.5));
+        assert_eq!(input_manager.get_mouse_position(), (100, 50));
+
+        let mut game_config = KbConfig::default();
+        assert_eq!(input_manager.get_mouse_look(&game_config), (5.0, 2.5));
+        game_config.mouse_sensitivity = 2.0;
+        game_config.mouse_invert_y = true;
+        assert_eq!(input_manager.get_mouse_look(&game_config), (10.0, -5.0));
+
+        // Contexts that consume the mouse hide its motion from the contexts below
+        input_manager.push_input_context(KbInputContext::new("game"));
+        let mut menu = KbInputContext::new("menu");
+        menu.consume_mouse = true;
+        input_manager.push_input_context(menu);
+        assert_eq!(input_manager.get_mouse_delta(), (0.0, 0.0));
+        let game = input_manager.get_input_context("game");
+        assert_eq!(game.get_mouse_delta(), (0.0, 0.0));
+        assert_eq!(game.get_mouse_look(&game_config), (0.0, 0.0));
+        let menu = input_manager.get_input_context("menu");
+        assert_eq!(menu.get_mouse_look(&game_config), (10.0, -5.0));
+        input_manager.pop_input_context();
+
+        input_manager.update_key_states();
+        assert_eq!(input_manager.get_mouse_delta(), (0.0, 0.0));
+        input_manager.add_mouse_motion((-1.0, 0.0));
+        assert_eq!(input_manager.get_mouse_look(&game_config), (-2.0, 0.0));
+    }
+
+    #[test]
+    fn input_contexts_hide_input_from_the_contexts_below() {
+        let mut input_manager = KbInputManager::new();
+        input_manager
+            .load_bindings(r#"{ "pause": ["escape"], "fire": ["space", "mouse_left"] }"#)
+            .unwrap();
+        input_manager.set_key_state(PhysicalKey::Code(KeyCode::Escape), ElementState::Pressed);
+        input_manager.set_key_state(PhysicalKey::Code(KeyCode::Space), ElementState::Pressed);
+        input_manager.set_mouse_button_state(&MouseButton::Left, &ElementState::Pressed);
+
+        input_manager.push_input_context(KbInputContext::new("game"));
+        let mut console = KbInputContext::new("console");
+        console.consume_keys = true;
+        input_manager.push_input_context(console);
+        let mut menu = KbInputContext::new("menu");
+        menu.consumed_buttons.push("escape".to_string());
+        input_manager.push_input_context(menu);
+
+        // The manager sees what nothing consumed
+        assert!(!input_manager.action_down("pause"));
+        assert!(input_manager.action_down("fire"));
+        assert_eq!(input_manager.get_key_state("space"), KbButtonState::None);
+        assert!(input_manager.get_key_state("mouse_left").just_pressed());
+
+        let menu = input_manager.get_input_context("menu");
+        assert!(menu.action_down("pause"));
+        assert!(menu.get_key_state("space").just_pressed());
+        let console = input_manager.get_input_context("console");
+        assert!(!console.action_down("pause"));
+        assert!(console.get_key_state("space").just_pressed());
+        let game = input_manager.get_input_context("game");
+        assert!(!game.get_key_state("space").just_pressed());
+        assert!(game.get_key_state("mouse_left").just_pressed());
+        let unknown = input_manager.get_input_context("unknown");
+        assert!(!unknown.action_down("pause"));
+
+        input_manager.remove_input_context("console");
+        assert!(input_manager.get_key_state("space").just_pressed());
+        assert!(input_manager.get_input_context("game").action_down("fire"));
+        assert!(!input_manager.get_input_context("game").action_down("pause"));
+        input_manager.pop_input_context();
+        assert!(input_manager.action_down("pause"));
+    }
+
+    #[test]
+    fn recorded_events_parse_back_unchanged() {
+        let mut events = vec![
+            KbInputEvent::Button {
+                name: "space",
+                pressed: true,
+            },
+            KbInputEvent::Button {
+                name: "mouse_right",
+                pressed: false,
+            },
+            KbInputEvent::MouseMove { x: 12.5, y: -3.0 },
+            KbInputEvent::MouseScroll { delta: -0.1 },
+            KbInputEvent::MouseMotion {
+                delta_x: 0.1 + 0.2,
+                delta_y: 1e-7,
+            },
+            KbInputEvent::Modifiers(KbModifiers {
+                shift: true,
+                control: false,
+                alt: true,
+                logo: false,
+            }),
+            KbInputEvent::Gamepad(KbGamepadEvent::Connected { id: 2 }),
+            KbInputEvent::Gamepad(KbGamepadEvent::Disconnected { id: 2 }),
+            KbInputEvent::Text("two words\nand \u{e9}\u{1f600}".to_string()),
+            KbInputEvent::Text(String::new()),
+            KbInputEvent::ImePreedit("\u{304b}".to_string()),
+            KbInputEvent::ImePreedit(String::new()),
+        ];
+        for phase in [
+            TouchPhase::Started,
+            TouchPhase::Moved,
+            TouchPhase::Ended,
+            TouchPhase::Cancelled,
+        ] {
+            events.push(KbInputEvent::Touch {
+                id: u64::MAX,
+                phase,
+                x: 1.0 / 3.0,
+                y: 640.0,
+            });
+        }
+        for (button, _) in GAMEPAD_BUTTON_NAMES {
+            events.push(KbInputEvent::Gamepad(KbGamepadEvent::Button {
+                id: 1,
+                button: *button,
+                pressed: true,
+            }));
+        }
+        for (axis, _) in GAMEPAD_AXIS_NAMES {
+            events.push(KbInputEvent::Gamepad(KbGamepadEvent::Axis {
+                id: 0,
+                axis: *axis,
+                value: -0.123_456_79,
+            }));
+        }
+        for (text_edit, _) in TEXT_EDIT_NAMES {
+            events.push(KbInputEvent::TextEdit(*text_edit));
+        }
+
+        for event in events {
+            let line = event.to_string();
+            let words = line.split_whitespace().collect::<Vec<&str>>();
+            assert_eq!(parse_input_event(&words), Some(event), "{}", line);
+        }
+        assert_eq!(parse_input_event(&["button", "pad_south", "1"]), None);
+        assert_eq!(
+            parse_input_event(&["touch", "1", "hovering", "0", "0"]),
+            None
+        );
+    }
+
+    #[test]
+    fn playback_replays_input_time_and_random_numbers() {
+        let recording_path = temp_path("replay.txt");
+
+        // Input arrives between frames, like winit events before a redraw
+        let play_frames = |input_manager: &mut KbInputManager| {
+            let mut frames = Vec::new();
+            for frame in 0..6 {
+                match frame {
+                    1 => {
+                        input_manager.set_key_state(
+                            PhysicalKey::Code(KeyCode::Space),
+                            ElementState::Pressed,
+                        );
+                        input_manager
+                            .set_mouse_position(&winit::dpi::PhysicalPosition::new(10.0, 20.0));
+                    }
+                    3 => {
+                        input_manager.set_key_state(
+                            PhysicalKey::Code(KeyCode::Space),
+                            ElementState::Released,
+                        );
+                    }
+                    _ => {}
+                }
+                std::thread::sleep(std::time::Duration::from_millis(2));
+
+                input_manager.begin_frame();
+                frames.push((
+                    kb_time_now(),
+                    crate::kb_utils::kb_random_f32(0.0, 1.0),
+                    input_manager.get_key_state("space"),
+                    input_manager.get_mouse_position(),
+                ));
+                input_manager.update_key_states();
+            }
+            frames
+        };
+
+        let mut recorder = KbInputManager::new();
+        recorder.start_recording(&recording_path).unwrap();
+        let recorded_frames = play_frames(&mut recorder);
+        recorder.stop_recording();
+
+        // Different live input and random state beforehand don't matter
+        kb_seed_random(1);
+        let mut player = KbInputManager::new();
+        let playback = player.start_playback(&recording_path);
+        let _ = std::fs::remove_file(&recording_path);
+        playback.unwrap();
+        assert!(!player.set_key_state(PhysicalKey::Code(KeyCode::KeyA), ElementState::Pressed));
+
+        let mut played_frames = Vec::new();
+        for _ in 0..recorded_frames.len() {
+            player.begin_frame();
+            played_frames.push((
+                kb_time_now(),
+                crate::kb_utils::kb_random_f32(0.0, 1.0),
+                player.get_key_state("space"),
+                player.get_mouse_position(),
+            ));
+            player.update_key_states();
+        }
+        assert_eq!(played_frames, recorded_frames);
+        assert!(recorded_frames[1].2.just_pressed());
+        assert!(recorded_frames[3].2.just_released());
+        assert!(recorded_frames[2].0 > recorded_frames[1].0);
+        assert!(player.is_playback_finished());
+        assert_eq!(player.get_key_state("a"), KbButtonState::None);
+    }
+
+    fn touch(input_manager: &mut KbInputManager, phase: TouchPhase, id: u64, position: (f64, f64)) {
+        let location = winit::dpi::PhysicalPosition::new(position.0, position.1);
+        input_manager.update_touch(phase, id, location);
+    }
+
+    #[test]
+    fn touch_regions_report_presses_and_releases() {
+        let clock = Rc::new(KbManualClock::new());
+        kb_set_clock(clock.clone());
+        let mut input_manager = KbInputManager::new();
+        input_manager.set_screen_size(2000, 1000);
+        input_manager
+            .load_bindings(r#"{ "jump": [{ "touch": [0.5, 0.0, 1.0, 1.0] }] }"#)
+            .unwrap();
+
+        touch(&mut input_manager, TouchPhase::Started, 0, (500.0, 500.0));
+        assert!(!input_manager.action_down("jump"));
+        touch(&mut input_manager, TouchPhase::Started, 1, (1500.0, 500.0));
+        assert!(input_manager.action_just_pressed("jump"));
+        next_frame(&mut input_manager);
+        assert!(input_manager.action_down("jump"));
+        assert!(!input_manager.action_just_pressed("jump"));
+
+        clock.advance(0.5);
+        touch(&mut input_manager, TouchPhase::Ended, 1, (1500.0, 500.0));
+        assert!(!input_manager.action_down("jump"));
+        assert!(input_manager.action_just_released("jump"));
+        assert_eq!(
+            input_manager.get_touch_map()[&1].touch_state.time_held(),
+            0.5
+        );
+        next_frame(&mut input_manager);
+        assert!(!input_manager.action_just_released("jump"));
+        assert!(!input_manager.get_touch_map().contains_key(&1));
+        assert!(input_manager.get_touch_map().contains_key(&0));
+    }
+
+    #[test]
+    fn virtual_sticks_and_buttons() {
+        let clock = Rc::new(KbManualClock::new());
+        kb_set_clock(clock.clone());
+        let mut input_manager = KbInputManager::new();
+        input_manager.set_screen_size(2000, 1000);
+
+        // Centered at (500, 750) in pixels with a radius of 100 pixels
+        let mut stick = KbVirtualStick::new((0.0, 0.5), (0.5, 1.0), (0.25, 0.75), 0.1);
+        stick.update(&input_manager);
+        assert!(!stick.is_active());
+
+        touch(&mut input_manager, TouchPhase::Started, 0, (510.0, 750.0));
+        stick.update(&input_manager);
+        assert!(stick.is_active());
+        assert_eq!(stick.get_axis(), (0.0, 0.0));
+
+        touch(&mut input_manager, TouchPhase::Moved, 0, (560.0, 750.0));
+        stick.update(&input_manager);
+        assert_near(stick.get_axis().0, 0.5);
+        assert_near(stick.get_axis().1, 0.0);
+
+        touch(&mut input_manager, TouchPhase::Moved, 0, (500.0, 1050.0));
+        stick.update(&input_manager);
+        assert_near(stick.get_axis().0, 0.0);
+        assert_near(stick.get_axis().1, 1.0);
+
+        // Sliding out of the region keeps control of the stick
+        touch(&mut input_manager, TouchPhase::Moved, 0, (1500.0, 750.0));
+        stick.update(&input_manager);
+        assert!(stick.is_active());
+        assert_near(stick.get_axis().0, 1.0);
+
+        touch(&mut input_manager, TouchPhase::Ended, 0, (1500.0, 750.0));
+        stick.update(&input_manager);
+        assert!(!stick.is_active());
+        assert_eq!(stick.get_axis(), (0.0, 0.0));
+        next_frame(&mut input_manager);
+
+        // Touches that start outside the region are ignored
+        touch(&mut input_manager, TouchPhase::Started, 1, (1500.0, 750.0));
+        touch(&mut input_manager, TouchPhase::Moved, 1, (500.0, 750.0));
+        stick.update(&input_manager);
+        assert!(!stick.is_active());
+        touch(&mut input_manager, TouchPhase::Ended, 1, (500.0, 750.0));
+        next_frame(&mut input_manager);
+
+        // Positions are normalized, so the same stick works at another resolution
+        input_manager.set_screen_size(1000, 500);
+        touch(&mut input_manager, TouchPhase::Started, 2, (280.0, 375.0));
+        stick.update(&input_manager);
+        assert_near(stick.get_axis().0, 0.5);
+        touch(&mut input_manager, TouchPhase::Ended, 2, (280.0, 375.0));
+        next_frame(&mut input_manager);
+        input_manager.set_screen_size(2000, 1000);
+
+        // The button is round on screen.  Its radius is 100 pixels
+        let mut button = KbVirtualButton::new((0.75, 0.5), 0.1);
+        let button_touches = [
+            ((1620.0, 500.0), false),
+            ((1500.0, 620.0), false),
+            ((1590.0, 500.0), true),
+            ((1500.0, 410.0), true),
+        ];
+        for (position, is_inside) in button_touches {
+            touch(&mut input_manager, TouchPhase::Started, 3, position);
+            button.update(&input_manager);
+            assert_eq!(
+                button.get_state().just_pressed(),
+                is_inside,
+                "{:?}",
+                position
+            );
+            touch(&mut input_manager, TouchPhase::Cancelled, 3, position);
+            next_frame(&mut input_manager);
+            button.update(&input_manager);
+        }
+
+        touch(&mut input_manager, TouchPhase::Started, 4, (1500.0, 500.0));
+        button.update(&input_manager);
+        assert!(button.get_state().just_pressed());
+        next_frame(&mut input_manager);
+        clock.advance(0.25);
+        button.update(&input_manager);
+        assert!(button.get_state().is_down());
+
+        // Sliding off the button doesn't release it
+        touch(&mut input_manager, TouchPhase::Moved, 4, (100.0, 100.0));
+        button.update(&input_manager);
+        assert!(button.get_state().is_down());
+
+        touch(&mut input_manager, TouchPhase::Ended, 4, (100.0, 100.0));
+        button.update(&input_manager);
+        assert_eq!(
+            button.get_state(),
+            KbButtonState::JustReleased { time_held: 0.25 }
+        );
+        next_frame(&mut input_manager);
+        button.update(&input_manager);
+        assert_eq!(button.get_state(), KbButtonState::None);
+    }
+
+    #[test]
+    fn taps_double_taps_and_long_presses() {
+        let clock = Rc::new(KbManualClock::new());
+        kb_set_clock(clock.clone());
+        let mut input_manager = KbInputManager::new();
+        input_manager.set_screen_size(1000, 1000);
+
+        touch(&mut input_manager, TouchPhase::Started, 0, (100.0, 100.0));
+        clock.advance(0.1);
+        touch(&mut input_manager, TouchPhase::Ended, 0, (105.0, 100.0));
+        assert_eq!(
+            input_manager.get_gestures(),
+            [KbGesture::Tap {
+                position: (105.0, 100.0)
+            }]
+        );
+        next_frame(&mut input_manager);
+        assert!(input_manager.get_gestures().is_empty());
+
+        clock.advance(0.1);
+        touch(&mut input_manager, TouchPhase::Started, 1, (110.0, 100.0));
+        clock.advance(0.1);
+        touch(&mut input_manager, TouchPhase::Ended, 1, (110.0, 100.0));
+        assert_eq!(
+            input_manager.get_gestures(),
+            [
+                KbGesture::Tap {
+                    position: (110.0, 100.0)
+                },
+                KbGesture::DoubleTap {
+                    position: (110.0, 100.0)
+                }
+            ]
+        );
+
+        // A third quick tap starts a new pair, and taps too far apart in time aren't doubles
+        let taps = [(0.1, 1), (0.1, 2), (1.0, 1)];
+        for (delay, num_gestures) in taps {
+            next_frame(&mut input_manager);
+            clock.advance(delay);
+            touch(&mut input_manager, TouchPhase::Started, 2, (110.0, 100.0));
+            touch(&mut input_manager, TouchPhase::Ended, 2, (110.0, 100.0));
+            assert_eq!(input_manager.get_gestures().len(), num_gestures);
+        }
+
+        // Too slow for a tap, too short for a long press
+        next_frame(&mut input_manager);
+        clock.advance(1.0);
+        touch(&mut input_manager, TouchPhase::Started, 3, (500.0, 500.0));
+        clock.advance(0.4);
+        next_frame(&mut input_manager);
+        touch(&mut input_manager, TouchPhase::Ended, 3, (500.0, 500.0));
+        assert!(input_manager.get_gestures().is_empty());
+
+        // Held long enough, the touch is a long press once and never a tap
+        touch(&mut input_manager, TouchPhase::Started, 4, (500.0, 500.0));
+        clock.advance(0.3);
+        next_frame(&mut input_manager);
+        assert!(input_manager.get_gestures().is_empty());
+        clock.advance(0.3);
+        next_frame(&mut input_manager);
+        assert_eq!(
+            input_manager.get_gestures(),
+            [KbGesture::LongPress {
+                position: (500.0, 500.0)
+            }]
+        );
+        clock.advance(0.3);
+        next_frame(&mut input_manager);
+        touch(&mut input_manager, TouchPhase::Ended, 4, (500.0, 500.0));
+        assert!(input_manager.get_gestures().is_empty());
+
+        // Moving further than the tap distance rules out taps and long presses
+        next_frame(&mut input_manager);
+        touch(&mut input_manager, TouchPhase::Started, 5, (500.0, 500.0));
+        touch(&mut input_manager, TouchPhase::Moved, 5, (530.0, 500.0));
+        clock.advance(1.0);
+        next_frame(&mut input_manager);
+        touch(&mut input_manager, TouchPhase::Ended, 5, (500.0, 500.0));
+        assert!(input_manager.get_gestures().is_empty());
+        touch(&mut input_manager, TouchPhase::Started, 6, (500.0, 500.0));
+        touch(&mut input_manager, TouchPhase::Moved, 6, (530.0, 500.0));
+        touch(&mut input_manager, TouchPhase::Ended, 6, (500.0, 500.0));
+        assert!(input_manager.get_gestures().is_empty());
+    }
+
+    #[test]
+    fn swipes_pinches_and_rotations() {
+        let clock = Rc::new(KbManualClock::new());
+        kb_set_clock(clock.clone());
+        let mut input_manager = KbInputManager::new();
+        input_manager.set_screen_size(1000, 1000);
+
+        let swipes = [
+            ((500.0, 500.0), (650.0, 520.0), KbSwipeDirection::Right),
+            ((500.0, 500.0), (380.0, 450.0), KbSwipeDirection::Left),
+            ((500.0, 500.0), (500.0, 300.0), KbSwipeDirection::Up),
+            ((500.0, 500.0), (480.0, 900.0), KbSwipeDirection::Down),
+        ];
+        for (start, end, direction) in swipes {
+            next_frame(&mut input_manager);
+            clock.advance(1.0);
+            touch(&mut input_manager, TouchPhase::Started, 0, start);
+            clock.advance(0.2);
+            touch(&mut input_manager, TouchPhase::Moved, 0, end);
+            touch(&mut input_manager, TouchPhase::Ended, 0, end);
+            assert_eq!(
+                input_manager.get_gestures(),
+                [KbGesture::Swipe {
+                    direction,
+                    start,
+                    end
+                }]
+            );
+        }
+
+        // Too slow or too short
+        for (time, end) in [(0.8, (800.0, 500.0)), (0.1, (550.0, 500.0))] {
+            next_frame(&mut input_manager);
+            touch(&mut input_manager, TouchPhase::Started, 0, (500.0, 500.0));
+            clock.advance(time);
+            touch(&mut input_manager, TouchPhase::Ended, 0, end);
+            assert!(input_manager.get_gestures().is_empty());
+        }
+
+        next_frame(&mut input_manager);
+        touch(&mut input_manager, TouchPhase::Started, 0, (400.0, 500.0));
+        touch(&mut input_manager, TouchPhase::Started, 1, (600.0, 500.0));
+        touch(&mut input_manager, TouchPhase::Moved, 1, (700.0, 500.0));
+        assert_eq!(
+            input_manager.get_gestures(),
+            [KbGesture::Pinch {
+                scale: 1.5,
+                center: (550.0, 500.0)
+            }]
+        );
+
+        // Quarter turn clockwise around the first finger at the same distance
+        next_frame(&mut input_manager);
+        touch(&mut input_manager, TouchPhase::Moved, 1, (400.0, 800.0));
+        match input_manager.get_gestures() {
+            [KbGesture::Rotate { radians, center }] => {
+                assert_near(*radians as f32, std::f32::consts::FRAC_PI_2);
+                assert_eq!(*center, (400.0, 650.0));
+            }
+            gestures => panic!("Expected a rotation, got {:?}", gestures),
+        }
+
+        // Fingers that were part of a two finger gesture aren't taps or swipes when lifted
+        next_frame(&mut input_manager);
+        touch(&mut input_manager, TouchPhase::Ended, 1, (400.0, 800.0));
+        touch(&mut input_manager, TouchPhase::Moved, 0, (400.0, 900.0));
+        touch(&mut input_manager, TouchPhase::Ended, 0, (400.0, 900.0));
+        clock.advance(1.0);
+        next_frame(&mut input_manager);
+        assert!(input_manager.get_gestures().is_empty());
+    }
+
+    fn type_key(input_manager: &mut KbInputManager, character: &str) {
+        input_manager.update_text_input(
+            &Key::Character(character.into()),
+            Some(character),
+            ElementState::Pressed,
+        );
+    }
+
+    #[test]
+    fn text_input_ignores_shortcuts_but_not_alt_gr() {
+        use winit::keyboard::ModifiersState;
+        let mut input_manager = KbInputManager::new();
+        input_manager.start_text_input("");
+        type_key(&mut input_manager, "a");
+
+        input_manager.set_modifiers(&ModifiersState::CONTROL);
+        type_key(&mut input_manager, "v");
+        assert!(input_manager.is_paste_requested());
+        input_manager.set_modifiers(&ModifiersState::SUPER);
+        type_key(&mut input_manager, "c");
+
+        // AltGr+q on a German layout
+        input_manager.set_modifiers(&(ModifiersState::CONTROL | ModifiersState::ALT));
+        type_key(&mut input_manager, "@");
+        input_manager.set_modifiers(&ModifiersState::ALT);
+        type_key(&mut input_manager, "\u{e5}");
+        input_manager.set_modifiers(&ModifiersState::SHIFT);
+        type_key(&mut input_manager, "B");
+        assert_eq!(input_manager.get_text_input(), "a@\u{e5}B");
+
+        input_manager.paste_text("pasted");
+        assert!(!input_manager.is_paste_requested());
+        assert_eq!(input_manager.stop_text_input(), "a@\u{e5}Bpasted");
+    }
+
+    // Only built with the gamepad feature, so cargo test --features gamepad checks that the gilrs
+    // backend still compiles.  Machines that can't open gilrs skip the rest
+    #[cfg(all(feature = "gamepad", not(target_arch = "wasm32")))]
+    #[test]
+    fn gilrs_backend_connects_pads_that_are_already_plugged_in() {
+        let gamepad_backend = match KbGilrsGamepadBackend::new() {
+            Ok(gamepad_backend) => gamepad_backend,
+            Err(_) => return,
+        };
+        let num_gamepads = gamepad_backend.gilrs.gamepads().count();
+
+        let mut input_manager = KbInputManager::new();
+        input_manager.set_gamepad_backend(Box::new(gamepad_backend));
+        input_manager.begin_frame();
+        assert_eq!(input_manager.get_gamepad_ids().len(), num_gamepads);
+    }
+
+    #[test]
+    fn bad_bindings_keep_the_old_ones() {
+        let mut input_manager = KbInputManager::new();
+        input_manager
+            .load_bindings(r#"{ "jump": ["space"] }"#)
+            .unwrap();
+
+        let error = input_manager.load_bindings(r#"{ "jump": ["space"] "#);
+        assert!(matches!(error, Err(KbInputError::Parse { .. })));
+
+        let error = input_manager.load_bindings(
+            r#"{
+                "jump": ["space"],
+                "fire": ["mouse_left", "not_a_key"]
+            }"#,
+        );
+        assert_eq!(
+            error,
+            Err(KbInputError::BadBinding {
+                action: "fire".to_string(),
+                line: Some(3),
+            })
+        );
+        assert!(error
+            .unwrap_err()
+            .to_string()
+            .starts_with("\"fire\" on line 3"));
+        assert_eq!(
+            input_manager.get_bindings("jump"),
+            [KbInputBinding::Key("space")]
+        );
+        assert!(input_manager.get_bindings("fire").is_empty());
     }
 }
@@ -4,8 +4,10 @@ use wgpu_text::glyph_brush::{Section as TextSection, Text};
 
 use crate::{
     kb_assets::*,
+    kb_clock::*,
     kb_config::*,
     kb_game_object::*,
+    kb_input::{KbVirtualButton, KbVirtualStick},
     kb_resource::*,
     kb_utils::*,
     log,
@@ -42,13 +44,20 @@ pub struct KbRenderer<'a> {
     active_particles: usize,
 
     debug_lines: Vec<KbLine>,
+    // Normalized screen space.  Cleared every frame
+    screen_lines: Vec<KbLine>,
 
     game_camera: KbCamera,
     postprocess_mode: KbPostProcessMode,
+    view_overrides: KbViewOverrides,
     frame_times: Vec<f32>,
+    // Wall-clock so the frame rate display is accurate under a manual engine clock
     frame_timer: Instant,
     frame_count: u32,
     window_id: winit::window::WindowId,
+    window: Arc<winit::window::Window>,
+    cursor_locked: bool,
+    ime_allowed: bool,
 
     allow_debug_text: bool,
     display_debug_msg: bool,
@@ -57,6 +66,30 @@ pub struct KbRenderer<'a> {
     debug_msg_color: CgVec4,
 }
 
+// Look settings changed at runtime, e.g. per level.  None uses the config's value
+#[derive(Clone, Default)]
+struct KbViewOverrides {
+    fov: Option<f32>,
+    foreground_fov: Option<f32>,
+    near_plane: Option<f32>,
+    far_plane: Option<f32>,
+    clear_color: Option<CgVec4>,
+    sun_color: Option<CgVec4>,
+}
+
+impl KbViewOverrides {
+    fn apply(&self, game_config: &KbConfig) -> KbViewSettings {
+        let mut view_settings = game_config.view_settings();
+        view_settings.fov = self.fov.unwrap_or(view_settings.fov);
+        view_settings.foreground_fov = self.foreground_fov.unwrap_or(view_settings.foreground_fov);
+        view_settings.near_plane = self.near_plane.unwrap_or(view_settings.near_plane);
+        view_settings.far_plane = self.far_plane.unwrap_or(view_settings.far_plane);
+        view_settings.clear_color = self.clear_color.unwrap_or(view_settings.clear_color);
+        view_settings.sun_color = self.sun_color.unwrap_or(view_settings.sun_color);
+        view_settings
+    }
+}
+
 impl<'a> KbRenderer<'a> {
     pub async fn new(window: Arc<winit::window::Window>, game_config: &KbConfig) -> Self {
         log!("GameRenderer::new() called...");
@@ -131,13 +164,18 @@ impl<'a> KbRenderer<'a> {
             active_particles: 0,
 
             debug_lines,
+            screen_lines: Vec::<KbLine>::new(),
 
             game_camera: KbCamera::new(),
-            postprocess_mode: KbPostProcessMode::Passthrough,
+            postprocess_mode: game_config.postprocess_mode.clone(),
+            view_overrides: KbViewOverrides::default(),
             frame_times: Vec::<f32>::new(),
             frame_timer: Instant::now(),
             frame_count: 0,
             window_id: window.id(),
+            window,
+            cursor_locked: false,
+            ime_allowed: false,
 
             game_debug_msg: "".to_string(),
             game_hud_msg: "".to_string(),
@@ -309,6 +347,9 @@ impl<'a> KbRenderer<'a> {
         game_objects: &Vec<GameObject>,
         game_config: &KbConfig,
     ) -> Result<(), wgpu::SurfaceError> {
+        // Values set through the renderer take priority over the config until cleared
+        let view_settings = self.view_overrides.apply(game_config);
+
         self.update_particles(game_config);
         PERF_SCOPE!("render_frame()");
 
@@ -340,6 +381,7 @@ impl<'a> KbRenderer<'a> {
                 &self.game_camera,
                 &self.actor_map,
                 game_config,
+                &view_settings,
             );
         }
         {
@@ -352,6 +394,7 @@ impl<'a> KbRenderer<'a> {
                 &self.game_camera,
                 &self.actor_map,
                 game_config,
+                &view_settings,
             );
         }
         if !self.actor_map.is_empty() {
@@ -366,6 +409,7 @@ impl<'a> KbRenderer<'a> {
                     &self.game_camera,
                     &self.actor_map,
                     game_config,
+                    &view_settings,
                 );
             }
         }
@@ -378,6 +422,7 @@ impl<'a> KbRenderer<'a> {
                 &self.game_camera,
                 &self.debug_lines,
                 game_config,
+                &view_settings,
             );
         }
 
@@ -389,6 +434,7 @@ impl<'a> KbRenderer<'a> {
                 &self.game_camera,
                 &mut self.particle_map,
                 game_config,
+                &view_settings,
             );
             self.model_render_group.render_particles(
                 KbParticleBlendMode::Additive,
@@ -396,6 +442,7 @@ impl<'a> KbRenderer<'a> {
                 &self.game_camera,
                 &mut self.particle_map,
                 game_config,
+                &view_settings,
             );
         }
 
@@ -404,6 +451,7 @@ impl<'a> KbRenderer<'a> {
                 &mut self.device_resources,
                 &self.game_camera,
                 game_config,
+                &view_settings,
             );
         }
 
@@ -417,6 +465,7 @@ impl<'a> KbRenderer<'a> {
                 &self.game_camera,
                 &self.actor_map,
                 game_config,
+                &view_settings,
             );
             {
                 PERF_SCOPE!("Foreground Custom");
@@ -430,6 +479,7 @@ impl<'a> KbRenderer<'a> {
                         &self.game_camera,
                         &self.actor_map,
                         game_config,
+                        &view_settings,
                     );
                 }
             }
@@ -493,6 +543,17 @@ impl<'a> KbRenderer<'a> {
             );
         }
 
+        if !self.screen_lines.is_empty() {
+            PERF_SCOPE!("Screen space lines");
+            self.line_render_group.render_screen_space(
+                &mut self.device_resources,
+                &final_view,
+                &self.screen_lines,
+                game_config,
+            );
+            self.screen_lines.clear();
+        }
+
         {
             PERF_SCOPE!("Debug text pass");
             let mut command_encoder = self.get_encoder("Debug Text Pass");
@@ -507,7 +568,7 @@ impl<'a> KbRenderer<'a> {
 
         self.end_frame(final_tex);
 
-        let cur_time = game_config.start_time.elapsed().as_secs_f32();
+        let cur_time = kb_time_since(game_config.start_time);
         self.debug_lines.retain_mut(|l| cur_time < l.end_time);
 
         Ok(())
@@ -530,6 +591,43 @@ impl<'a> KbRenderer<'a> {
         self.window_id
     }
 
+    // Hides the cursor and keeps it in the window for mouse-look.  Platforms that can't lock the
+    // cursor in place confine it to the window instead
+    pub fn set_cursor_locked(&mut self, locked: bool) {
+        use winit::window::CursorGrabMode;
+
+        let grab_result = if locked {
+            self.window
+                .set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| self.window.set_cursor_grab(CursorGrabMode::Confined))
+        } else {
+            self.window.set_cursor_grab(CursorGrabMode::None)
+        };
+        if let Err(error) = grab_result {
+            log!("KbRenderer::set_cursor_locked() - {}", error);
+        }
+
+        self.window.set_cursor_visible(!locked);
+        self.cursor_locked = locked;
+    }
+
+    pub fn is_cursor_locked(&self) -> bool {
+        self.cursor_locked
+    }
+
+    pub fn set_cursor_visible(&mut self, visible: bool) {
+        self.window.set_cursor_visible(visible);
+    }
+
+    // IME events are only sent while allowed.  KbGameEngine::tick_frame() allows them during text
+    // input
+    pub fn set_ime_allowed(&mut self, allowed: bool) {
+        if self.ime_allowed != allowed {
+            self.window.set_ime_allowed(allowed);
+            self.ime_allowed = allowed;
+        }
+    }
+
     pub fn add_or_update_actor(&mut self, actor: &KbActor) {
         self.actor_map.insert(actor.id, actor.clone());
     }
@@ -590,6 +688,17 @@ impl<'a> KbRenderer<'a> {
             .await
     }
 
+    // Model space triangles of a loaded model, or of just its mesh named mesh_name
+    pub fn get_model_triangles(
+        &mut self,
+        model_handle: &KbModelHandle,
+        mesh_name: Option<&str>,
+    ) -> Option<Vec<[CgVec3; 3]>> {
+        self.asset_manager
+            .get_model(model_handle)?
+            .get_triangles(mesh_name)
+    }
+
     pub fn set_camera(&mut self, camera: &KbCamera) {
         self.game_camera = camera.clone();
     }
@@ -658,10 +767,64 @@ impl<'a> KbRenderer<'a> {
             end: *end,
             color: *color,
             thickness,
-            end_time: game_config.start_time.elapsed().as_secs_f32() + duration,
+            end_time: kb_time_since(game_config.start_time) + duration,
         });
     }
 
+    // Draws the stick's base and, while touched, its thumb.  Call every frame the stick is shown
+    pub fn draw_virtual_stick(&mut self, stick: &KbVirtualStick, color: &CgVec4) {
+        self.add_screen_circle(stick.center, stick.radius, color);
+
+        if stick.is_active() {
+            let aspect_ratio = self.get_aspect_ratio();
+            let axis = stick.get_axis();
+            let thumb_center = (
+                stick.center.0 + axis.0 * stick.radius / aspect_ratio,
+                stick.center.1 + axis.1 * stick.radius,
+            );
+            self.add_screen_circle(thumb_center, stick.radius * 0.4, color);
+        }
+    }
+
+    // Draws the button, filled in while it's held.  Call every frame the button is shown
+    pub fn draw_virtual_button(&mut self, button: &KbVirtualButton, color: &CgVec4) {
+        self.add_screen_circle(button.center, button.radius, color);
+
+        let button_state = button.get_state();
+        if button_state.is_down() || button_state.just_pressed() {
+            self.add_screen_circle(button.center, button.radius * 0.8, color);
+        }
+    }
+
+    // radius is a fraction of the screen height
+    fn add_screen_circle(&mut self, center: (f32, f32), radius: f32, color: &CgVec4) {
+        const NUM_SEGMENTS: usize = 32;
+
+        let aspect_ratio = self.get_aspect_ratio();
+        let point_on_circle = |i: usize| {
+            let angle = i as f32 / NUM_SEGMENTS as f32 * std::f32::consts::TAU;
+            CgVec3::new(
+                center.0 + angle.cos() * radius / aspect_ratio,
+                center.1 + angle.sin() * radius,
+                0.0,
+            )
+        };
+        for i in 0..NUM_SEGMENTS {
+            self.screen_lines.push(KbLine {
+                start: point_on_circle(i),
+                end: point_on_circle(i + 1),
+                color: *color,
+                thickness: 4.0,
+                end_time: 0.0,
+            });
+        }
+    }
+
+    fn get_aspect_ratio(&self) -> f32 {
+        let surface_config = &self.device_resources.surface_config;
+        surface_config.width as f32 / surface_config.height.max(1) as f32
+    }
+
     pub fn set_allow_debug_text(&mut self, allow: bool) {
         self.allow_debug_text = allow;
     }
@@ -686,6 +849,41 @@ impl<'a> KbRenderer<'a> {
         self.postprocess_mode = new_mode.clone();
     }
 
+    pub fn set_fov(&mut self, fov: f32) {
+        self.view_overrides.fov = Some(fov);
+    }
+
+    pub fn set_foreground_fov(&mut self, foreground_fov: f32) {
+        self.view_overrides.foreground_fov = Some(foreground_fov);
+    }
+
+    // Planes where near_plane <= 0 or far_plane <= near_plane are logged and ignored
+    pub fn set_clip_planes(&mut self, near_plane: f32, far_plane: f32) {
+        if near_plane <= 0.0 || far_plane <= near_plane {
+            log!(
+                "KbRenderer::set_clip_planes() - Ignoring invalid planes {} and {}",
+                near_plane,
+                far_plane
+            );
+            return;
+        }
+        self.view_overrides.near_plane = Some(near_plane);
+        self.view_overrides.far_plane = Some(far_plane);
+    }
+
+    pub fn set_clear_color(&mut self, color: &CgVec4) {
+        self.view_overrides.clear_color = Some(*color);
+    }
+
+    pub fn set_sun_color(&mut self, color: &CgVec4) {
+        self.view_overrides.sun_color = Some(*color);
+    }
+
+    // Goes back to the fov, clip planes and colors from the config
+    pub fn clear_view_overrides(&mut self) {
+        self.view_overrides = KbViewOverrides::default();
+    }
+
     pub fn num_active_particles(&self) -> usize {
         self.active_particles
     }
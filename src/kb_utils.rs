@@ -15,24 +15,50 @@ macro_rules! log {
     };
 }
 
-pub fn kb_random_f32(min_val: f32, max_val: f32) -> f32 {
+thread_local! {
+    static KB_RANDOM_STATE: std::cell::Cell<Option<u64>> = const { std::cell::Cell::new(None) };
+}
+
+// Makes kb_random_*() repeatable.  Until this is called they read from the OS
+pub fn kb_seed_random(seed: u64) {
+    // xorshift gets stuck at 0
+    let seed = if seed == 0 {
+        0x9E37_79B9_7F4A_7C15
+    } else {
+        seed
+    };
+    KB_RANDOM_STATE.with(|state| state.set(Some(seed)));
+}
+
+fn kb_random_bits() -> u32 {
+    let seeded_state = KB_RANDOM_STATE.with(|state| {
+        let mut x = state.get()?;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(Some(x));
+        Some(x)
+    });
+    if let Some(x) = seeded_state {
+        return (x >> 32) as u32;
+    }
+
     let mut buf: [u8; 4] = [0, 0, 0, 0];
     let _ = getrandom::getrandom(&mut buf);
     let mut t = buf[0] as u32;
     t += (buf[1] as u32) << 8;
     t += (buf[2] as u32) << 16;
     t += (buf[3] as u32) << 24;
-    let t = t as f32 / u32::MAX as f32;
+    t
+}
+
+pub fn kb_random_f32(min_val: f32, max_val: f32) -> f32 {
+    let t = kb_random_bits() as f32 / u32::MAX as f32;
     min_val + (max_val - min_val) * t
 }
 
 pub fn kb_random_u32(min_val: u32, max_val: u32) -> u32 {
-    let mut buf: [u8; 4] = [0, 0, 0, 0];
-    let _ = getrandom::getrandom(&mut buf);
-    let mut t = buf[0] as u32;
-    t += (buf[1] as u32) << 8;
-    t += (buf[2] as u32) << 16;
-    t += (buf[3] as u32) << 24;
+    let t = kb_random_bits();
     let dif = (max_val - min_val) + 1;
     min_val + (t % dif)
 }
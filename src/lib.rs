@@ -6,6 +6,7 @@ use winit::{
     window::WindowBuilder,
 };
 pub mod kb_assets;
+pub mod kb_clock;
 pub mod kb_collision;
 pub mod kb_config;
 pub mod kb_engine;
@@ -72,8 +73,34 @@ where
         game_config.window_height,
     ));
 
-    let mut game_engine = T::new(&game_config);
     let mut input_manager = KbInputManager::new();
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        // Without the recording the game still runs on live input
+        if !game_config.input_playback_file.is_empty() {
+            if let Err(error) = input_manager.start_playback(&game_config.input_playback_file) {
+                log!("Failed to load input recording - {}", error);
+            }
+        } else if !game_config.input_record_file.is_empty() {
+            if let Err(error) = input_manager.start_recording(&game_config.input_record_file) {
+                log!("Failed to start input recording - {}", error);
+            }
+        }
+        game_config.start_time = kb_clock::kb_time_now();
+        game_config.last_frame_time = game_config.start_time;
+    }
+
+    #[cfg(all(feature = "gamepad", not(target_arch = "wasm32")))]
+    match KbGilrsGamepadBackend::new() {
+        Ok(gamepad_backend) => input_manager.set_gamepad_backend(Box::new(gamepad_backend)),
+        Err(error) => {
+            log!("Gamepads disabled - {}", error);
+        }
+    }
+
+    let mut game_engine = T::new(&game_config);
+    input_manager.set_screen_size(game_config.window_width, game_config.window_height);
+    game_engine.initialize_input(&mut input_manager);
     let mut game_renderer = KbRenderer::new(window.clone(), &game_config).await;
 
     game_engine
@@ -171,12 +198,20 @@ where
                         input_manager.set_mouse_position(position);
                     }
 
+                    WindowEvent::ModifiersChanged(modifiers) => {
+                        input_manager.set_modifiers(&modifiers.state());
+                    }
+                    WindowEvent::Ime(ime) => {
+                        input_manager.update_ime(ime);
+                    }
+
                     WindowEvent::CloseRequested => control_flow.exit(),
 
                     WindowEvent::Resized(physical_size) => {
                         if physical_size.width > 0 && physical_size.height > 0 {
                             game_config.window_width = physical_size.width;
                             game_config.window_height = physical_size.height;
+                            input_manager.set_screen_size(physical_size.width, physical_size.height);
                             let _ = async {
                                 game_renderer.resize(&game_config);
                             };
@@ -197,12 +232,19 @@ where
                         is_synthetic: _,
                     } => {
                         input_manager.set_key_state(event.physical_key, event.state);
+                        input_manager.update_text_input(
+                            &event.logical_key,
+                            event.text.as_deref(),
+                            event.state,
+                        );
 
-                        if input_manager.get_key_state("h").just_pressed() {
+                        // Engine hot keys are ignored while typing
+                        let is_typing = input_manager.is_text_input_active();
+                        if !is_typing && input_manager.get_key_state("h").just_pressed() {
                             game_renderer.enable_help_text();
                         }
 
-                        if input_manager.get_key_state("v").just_pressed() {
+                        if !is_typing && input_manager.get_key_state("v").just_pressed() {
                             game_config.vsync = !game_config.vsync;
 
                             if game_config.vsync {
@@ -218,6 +260,15 @@ where
                     _ => {}
                 },
 
+                // Raw mouse movement keeps arriving while the cursor is locked, unlike CursorMoved
+                Event::DeviceEvent {
+                    event: DeviceEvent::MouseMotion { delta },
+                    ..
+                } => {
+                    input_manager.add_mouse_motion(delta);
+                    window.request_redraw();
+                }
+
                 _ => {
                     window.request_redraw();
                 }
@@ -239,6 +290,10 @@ where
                             &mut input_manager,
                             &mut game_config,
                         );
+                        if input_manager.is_playback_finished() {
+                            log!("Input playback finished");
+                            control_flow.exit();
+                        }
                     }
                     if hack_wait > 8 {
                         let render_result = game_renderer
@@ -279,6 +334,10 @@ where
                                 &mut input_manager,
                                 &mut game_config,
                             );
+                            if input_manager.is_playback_finished() {
+                                log!("Input playback finished");
+                                control_flow.exit();
+                            }
 
                             let render_result = game_renderer
                                 .render_frame(game_engine.get_game_objects(), &game_config);
@@ -313,13 +372,29 @@ where
                         input_manager.set_mouse_position(position);
                     }
 
-                    WindowEvent::CloseRequested => control_flow.exit(),
+                    WindowEvent::ModifiersChanged(modifiers) => {
+                        input_manager.set_modifiers(&modifiers.state());
+                    }
+                    WindowEvent::Ime(ime) => {
+                        input_manager.update_ime(ime);
+                    }
+
+                    WindowEvent::CloseRequested => {
+                        input_manager.stop_recording();
+                        if game_config.get_user_settings_path().is_some() {
+                            if let Err(error) = game_config.save() {
+                                log!("Failed to save user settings - {}", error);
+                            }
+                        }
+                        control_flow.exit()
+                    }
 
                     WindowEvent::Resized(physical_size) => {
                         // log!("Resized {} {}", physical_size.width, physical_size.z
                         if physical_size.width > 0 && physical_size.height > 0 {
                             game_config.window_width = physical_size.width;
                             game_config.window_height = physical_size.height;
+                            input_manager.set_screen_size(physical_size.width, physical_size.height);
                             game_renderer.resize(&game_config);
                         }
                     }
@@ -330,12 +405,29 @@ where
                         is_synthetic: _,
                     } => {
                         input_manager.set_key_state(event.physical_key, event.state);
+                        input_manager.update_text_input(
+                            &event.logical_key,
+                            event.text.as_deref(),
+                            event.state,
+                        );
+
+                        #[cfg(feature = "clipboard")]
+                        if input_manager.is_paste_requested() {
+                            match arboard::Clipboard::new().and_then(|mut c| c.get_text()) {
+                                Ok(text) => input_manager.paste_text(&text),
+                                Err(error) => {
+                                    log!("Paste failed - {}", error);
+                                }
+                            }
+                        }
 
-                        if input_manager.get_key_state("h").just_pressed() {
+                        // Engine hot keys are ignored while typing
+                        let is_typing = input_manager.is_text_input_active();
+                        if !is_typing && input_manager.get_key_state("h").just_pressed() {
                             game_renderer.enable_help_text();
                         }
 
-                        if input_manager.get_key_state("v").just_pressed() {
+                        if !is_typing && input_manager.get_key_state("v").just_pressed() {
                             game_config.vsync = !game_config.vsync;
 
                             if game_config.vsync {
@@ -351,6 +443,15 @@ where
                     _ => {}
                 },
 
+                // Raw mouse movement keeps arriving while the cursor is locked, unlike CursorMoved
+                Event::DeviceEvent {
+                    event: DeviceEvent::MouseMotion { delta },
+                    ..
+                } => {
+                    input_manager.add_mouse_motion(delta);
+                    window.request_redraw();
+                }
+
                 _ => {
                     window.request_redraw();
                 }
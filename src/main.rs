@@ -89,7 +89,7 @@ impl KbGameEngine for EmptyGame {
     fn tick_frame_internal(
         &mut self,
         _game_renderer: &mut KbRenderer,
-        _input_manager: &KbInputManager,
+        _input_manager: &mut KbInputManager,
         _game_config: &KbConfig,
     ) {
         // Add game update logic here
@@ -98,7 +98,13 @@ impl KbGameEngine for EmptyGame {
 
 fn main() {
     let config_file_text = include_str!("../engine_assets/game_config.txt");
-    let game_config = KbConfig::new(config_file_text);
+    let mut game_config = KbConfig::load_layered(config_file_text)
+        .unwrap_or_else(|error| panic!("Failed to load game_config.txt - {}", error));
+    let config_file_path = concat!(env!("CARGO_MANIFEST_DIR"), "/engine_assets/game_config.txt");
+    game_config.watch_file(config_file_path);
+    if let Err(error) = game_config.load_user_settings(config_file_path) {
+        kb_engine3::log!("Ignoring user settings - {}", error);
+    }
 
     // Pass your Game's type to run_game() which will create an instance and call functions on it
     let run_game = kb_engine3::run_game::<EmptyGame>(game_config);
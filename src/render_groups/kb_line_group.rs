@@ -26,6 +26,7 @@ pub struct KbLineUniform {
 pub struct KbLineRenderGroup {
     pub vertex_buffer: wgpu::Buffer,
     pub pipeline: wgpu::RenderPipeline,
+    pub screen_space_pipeline: wgpu::RenderPipeline,
     pub uniform: KbLineUniform,
     pub uniform_buffer: wgpu::Buffer,
     pub uniform_bind_group: wgpu::BindGroup,
@@ -98,52 +99,25 @@ impl KbLineRenderGroup {
             .load_shader(shader_path, device_resources)
             .await;
         let model_shader = asset_manager.get_shader(&shader_handle);
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("KbLineRenderGroup_opaque_pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: model_shader,
-                entry_point: "vs_main",
-                buffers: &[KbVertex::desc()],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: model_shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::LessEqual,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        });
+        let pipeline = create_line_pipeline(
+            device,
+            &render_pipeline_layout,
+            model_shader,
+            surface_config.format,
+            true,
+        );
+        let screen_space_pipeline = create_line_pipeline(
+            device,
+            &render_pipeline_layout,
+            model_shader,
+            surface_config.format,
+            false,
+        );
 
         KbLineRenderGroup {
             vertex_buffer,
             pipeline,
+            screen_space_pipeline,
             uniform,
             uniform_buffer,
             uniform_bind_group,
@@ -157,6 +131,7 @@ impl KbLineRenderGroup {
         game_camera: &KbCamera,
         lines: &Vec<KbLine>,
         game_config: &KbConfig,
+        view_settings: &KbViewSettings,
     ) {
         let mut command_encoder =
             device_resources
@@ -195,12 +170,12 @@ impl KbLineRenderGroup {
         let (view_matrix, view_dir, _) = game_camera.calculate_view_matrix();
         let view_pos = game_camera.get_position();
         let view_pos = [view_pos.x, view_pos.y, view_pos.z, 1.0];
-        let fov = game_config.fov;
+        let fov = view_settings.fov;
         let proj_matrix = cgmath::perspective(
             cgmath::Deg(fov),
             game_config.window_width as f32 / game_config.window_height as f32,
-            0.1,
-            10000.0,
+            view_settings.near_plane,
+            view_settings.far_plane,
         );
 
         let uniform_buffer = &self.uniform_buffer;
@@ -283,4 +258,156 @@ impl KbLineRenderGroup {
             .queue
             .submit(std::iter::once(command_encoder.finish()));
     }
+
+    // Draws lines over the finished frame, e.g. for on-screen controls.  start and end are in
+    // normalized screen space, z is ignored and thickness is in pixels
+    pub fn render_screen_space(
+        &mut self,
+        device_resources: &mut KbDeviceResources,
+        view: &wgpu::TextureView,
+        lines: &[KbLine],
+        game_config: &KbConfig,
+    ) {
+        let mut command_encoder =
+            device_resources
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("KbLineGroup::render_screen_space()"),
+                });
+
+        let mut render_pass = command_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Screen Space Line"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        render_pass.set_pipeline(&self.screen_space_pipeline);
+
+        // Vertices are already in clip space
+        let uniform_data = KbLineUniform {
+            mvp_matrix: CG_MAT4_IDENT.into(),
+            model_color: [1.0, 1.0, 1.0, 1.0],
+            ..Default::default()
+        };
+        device_resources.queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[uniform_data]),
+        );
+
+        let width = game_config.window_width as f32;
+        let height = game_config.window_height as f32;
+        let to_clip_space = |x: f32, y: f32| KbVertex {
+            position: [x / width * 2.0 - 1.0, 1.0 - y / height * 2.0, 0.0],
+            tex_coords: [0.0, 0.0],
+            normal: [0.0, 0.0, 1.0],
+            color: [0.0, 0.0, 0.0, 0.0],
+        };
+
+        let mut vertices = Vec::<KbVertex>::new();
+        for line in lines.iter().take(MAX_LINES) {
+            let start = CgVec2::new(line.start.x * width, line.start.y * height);
+            let end = CgVec2::new(line.end.x * width, line.end.y * height);
+            if (end - start).magnitude2() < 0.0001 {
+                continue;
+            }
+            let forward_vec = (end - start).normalize();
+            let up_vec = CgVec2::new(-forward_vec.y, forward_vec.x) * line.thickness * 0.5;
+
+            let corners = [start + up_vec, start - up_vec, end - up_vec, end + up_vec];
+            for i in [0, 1, 2, 0, 2, 3] {
+                let mut vertex = to_clip_space(corners[i].x, corners[i].y);
+                vertex.color = line.color.into();
+                vertices.push(vertex);
+            }
+        }
+        device_resources.queue.write_buffer(
+            &self.vertex_buffer,
+            0,
+            bytemuck::cast_slice(vertices.as_slice()),
+        );
+
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+        render_pass.draw(0..vertices.len() as u32, 0..1);
+
+        drop(render_pass);
+        device_resources
+            .queue
+            .submit(std::iter::once(command_encoder.finish()));
+    }
+}
+
+// World lines are depth tested.  Screen space lines are alpha blended over everything
+fn create_line_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    depth_tested: bool,
+) -> wgpu::RenderPipeline {
+    let (blend, depth_stencil) = if depth_tested {
+        (
+            wgpu::BlendState::REPLACE,
+            Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+        )
+    } else {
+        (wgpu::BlendState::ALPHA_BLENDING, None)
+    };
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(if depth_tested {
+            "KbLineRenderGroup_opaque_pipeline"
+        } else {
+            "KbLineRenderGroup_screen_space_pipeline"
+        }),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[KbVertex::desc()],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(blend),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil,
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
 }
@@ -5,7 +5,9 @@ use wgpu::{
     TextureSampleType, TextureViewDimension,
 };
 
-use crate::{kb_assets::*, kb_config::*, kb_game_object::*, kb_resource::*, log};
+use crate::{
+    kb_assets::*, kb_clock::*, kb_config::*, kb_game_object::*, kb_resource::*, kb_utils::*, log,
+};
 
 #[repr(C)]
 #[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
@@ -75,6 +77,9 @@ pub struct KbModel {
     uniform_buffers: Vec<wgpu::Buffer>,
     uniform_bind_groups: Vec<wgpu::BindGroup>,
     next_uniform_buffer: usize,
+
+    // Model space triangles of each glTF mesh by name, kept on the CPU for building collision
+    mesh_triangles: Vec<(String, Vec<[CgVec3; 3]>)>,
 }
 
 impl KbModel {
@@ -218,6 +223,7 @@ impl KbModel {
             uniform_buffers,
             uniform_bind_groups,
             next_uniform_buffer: 0,
+            mesh_triangles: Vec::new(),
         }
     }
 
@@ -233,6 +239,7 @@ impl KbModel {
         let mut indices = Vec::<u16>::new();
         let mut vertices = Vec::<KbVertex>::new();
         let mut textures = Vec::<KbTextureHandle>::new();
+        let mut mesh_triangles = Vec::<(String, Vec<[CgVec3; 3]>)>::new();
         // https://stackoverflow.com/questions/75846989/how-to-load-gltf-files-with-gltf-rs-crate
 
         let (gltf_doc, buffers, gltf_images) = gltf::import_slice(bytes).unwrap();
@@ -256,6 +263,7 @@ impl KbModel {
         }
 
         for m in gltf_doc.meshes() {
+            let mut triangles = Vec::<[CgVec3; 3]>::new();
             for p in m.primitives() {
                 let r = p.reader(|buffer| Some(&buffers[buffer.index()]));
                 if let Some(gltf::mesh::util::ReadIndices::U16(gltf::accessor::Iter::Standard(
@@ -291,6 +299,20 @@ impl KbModel {
                     }
                 }
 
+                if p.mode() == gltf::mesh::Mode::Triangles {
+                    let triangle_indices: Vec<u32> = match r.read_indices() {
+                        Some(read_indices) => read_indices.into_u32().collect(),
+                        None => (0..positions.len() as u32).collect(),
+                    };
+                    for triangle in triangle_indices.chunks_exact(3) {
+                        triangles.push([
+                            positions[triangle[0] as usize].into(),
+                            positions[triangle[1] as usize].into(),
+                            positions[triangle[2] as usize].into(),
+                        ]);
+                    }
+                }
+
                 let mut i = 0;
                 while i < positions.len() {
                     let vertex = KbVertex {
@@ -303,6 +325,7 @@ impl KbModel {
                     i += 1;
                 }
             }
+            mesh_triangles.push((m.name().unwrap_or_default().to_string(), triangles));
         }
 
         let num_indices = indices.len() as u32;
@@ -463,6 +486,25 @@ impl KbModel {
             empty_texture,
             tex_bind_group,
             next_uniform_buffer: 0,
+            mesh_triangles,
+        }
+    }
+
+    // Model space triangles of every mesh, or of just the mesh named mesh_name.  None if there's no
+    // mesh with that name
+    pub fn get_triangles(&self, mesh_name: Option<&str>) -> Option<Vec<[CgVec3; 3]>> {
+        match mesh_name {
+            Some(mesh_name) => self
+                .mesh_triangles
+                .iter()
+                .find(|(name, _)| name == mesh_name)
+                .map(|(_, triangles)| triangles.clone()),
+            None => Some(
+                self.mesh_triangles
+                    .iter()
+                    .flat_map(|(_, triangles)| triangles.iter().copied())
+                    .collect(),
+            ),
         }
     }
 
@@ -787,6 +829,7 @@ impl KbModelRenderGroup {
         game_camera: &KbCamera,
         actors: &HashMap<u32, KbActor>,
         game_config: &KbConfig,
+        view_settings: &KbViewSettings,
     ) {
         let mut command_encoder =
             device_resources
@@ -798,7 +841,7 @@ impl KbModelRenderGroup {
         let render_group = (*render_group).clone();
         let (color_attachment, depth_attachment) = {
             let (color_ops, depth_ops) = {
-                let clear_color = game_config.clear_color;
+                let clear_color = view_settings.clear_color;
                 if render_group == KbRenderGroupType::World {
                     (
                         wgpu::Operations {
@@ -870,15 +913,15 @@ impl KbModelRenderGroup {
         let fov = if render_group == KbRenderGroupType::Foreground
             || render_group == KbRenderGroupType::ForegroundCustom
         {
-            game_config.foreground_fov
+            view_settings.foreground_fov
         } else {
-            game_config.fov
+            view_settings.fov
         };
         let proj_matrix = cgmath::perspective(
             cgmath::Deg(fov),
             game_config.window_width as f32 / game_config.window_height as f32,
-            0.1,
-            10000.0,
+            view_settings.near_plane,
+            view_settings.far_plane,
         );
 
         // Iterate over actors and add their uniform info to their corresponding KbModels
@@ -934,7 +977,7 @@ impl KbModelRenderGroup {
                 (game_config.window_height as f32) / (game_config.window_width as f32),
                 0.0,
             ];
-            uniform_data.time[0] = game_config.start_time.elapsed().as_secs_f32();
+            uniform_data.time[0] = kb_time_since(game_config.start_time);
             uniform_data.time[1] = 1.0;
             uniform_data.model_color = [
                 actor.get_color().x,
@@ -949,9 +992,9 @@ impl KbModelRenderGroup {
                 actor.get_custom_data_1().w,
             ];
             uniform_data.sun_color = [
-                game_config.sun_color.x,
-                game_config.sun_color.y,
-                game_config.sun_color.z,
+                view_settings.sun_color.x,
+                view_settings.sun_color.y,
+                view_settings.sun_color.z,
                 0.0,
             ];
             device_resources.queue.write_buffer(
@@ -994,6 +1037,7 @@ impl KbModelRenderGroup {
         game_camera: &KbCamera,
         particles: &mut HashMap<KbParticleHandle, KbParticleActor>,
         game_config: &KbConfig,
+        view_settings: &KbViewSettings,
     ) {
         let mut command_encoder =
             device_resources
@@ -1030,9 +1074,9 @@ impl KbModelRenderGroup {
         let view_pos = game_camera.get_position();
         let view_pos = [view_pos.x, view_pos.y, view_pos.z, 1.0];
         let proj_matrix = cgmath::perspective(
-            cgmath::Deg(game_config.fov),
+            cgmath::Deg(view_settings.fov),
             game_config.window_width as f32 / game_config.window_height as f32,
-            0.1,
+            view_settings.near_plane,
             1000000.0,
         );
         let view_proj_matrix = proj_matrix * view_matrix;
@@ -1079,7 +1123,7 @@ impl KbModelRenderGroup {
                 (game_config.window_height as f32) / (game_config.window_width as f32),
                 0.0,
             ];
-            uniform.time[0] = game_config.start_time.elapsed().as_secs_f32();
+            uniform.time[0] = kb_time_since(game_config.start_time);
             uniform.time[1] = 1.0;
             uniform.custom_data_1 = [0.0, 0.0, 0.0, 0.0];
             uniform.model_color = [1.0, 1.0, 1.0, 1.0];
@@ -1,6 +1,6 @@
 use wgpu::util::DeviceExt;
 
-use crate::{kb_assets::*, kb_config::*, kb_resource::*};
+use crate::{kb_assets::*, kb_clock::*, kb_config::*, kb_resource::*};
 
 pub struct KbPostprocessRenderGroup {
     pub vertex_buffer: wgpu::Buffer,
@@ -227,8 +227,7 @@ impl KbPostprocessRenderGroup {
         render_pass.set_vertex_buffer(1, device_resources.instance_buffer.slice(..));
         render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
 
-        self.postprocess_uniform.time_mode_unused_unused[0] =
-            game_config.start_time.elapsed().as_secs_f32();
+        self.postprocess_uniform.time_mode_unused_unused[0] = kb_time_since(game_config.start_time);
         self.postprocess_uniform.time_mode_unused_unused[1] = {
             let postprocess_mode = match postprocess_override {
                 Some(p) => p.clone(),
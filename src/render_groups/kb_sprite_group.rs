@@ -6,7 +6,8 @@ use wgpu::{
 };
 
 use crate::{
-    kb_assets::*, kb_config::*, kb_game_object::*, kb_resource::*, kb_utils::*, log, PERF_SCOPE,
+    kb_assets::*, kb_clock::*, kb_config::*, kb_game_object::*, kb_resource::*, kb_utils::*, log,
+    PERF_SCOPE,
 };
 
 pub struct KbSpriteRenderGroup {
@@ -390,7 +391,7 @@ impl KbSpriteRenderGroup {
             (game_config.window_height as f32) / (game_config.window_width as f32),
             0.0,
         ]; //[self.game_config.window_width as f32, self.game_config.window_height as f32, (self.game_config.window_height as f32) / (self.game_config.window_width as f32), 0.0]));
-        self.uniform.time[0] = game_config.start_time.elapsed().as_secs_f32();
+        self.uniform.time[0] = kb_time_since(game_config.start_time);
 
         render_pass.set_bind_group(0, &self.tex_bind_group, &[]);
         render_pass.set_bind_group(1, &self.uniform_bind_group, &[]);
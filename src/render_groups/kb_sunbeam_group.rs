@@ -309,9 +309,10 @@ impl KbSunbeamRenderGroup {
         device_resources: &mut KbDeviceResources,
         camera: &KbCamera,
         game_config: &KbConfig,
+        view_settings: &KbViewSettings,
     ) {
-        self.render_mask(device_resources, camera, game_config);
-        self.render_beams(device_resources, camera, game_config);
+        self.render_mask(device_resources, camera, game_config, view_settings);
+        self.render_beams(device_resources, camera, game_config, view_settings);
     }
 
     pub fn render_mask(
@@ -319,6 +320,7 @@ impl KbSunbeamRenderGroup {
         device_resources: &mut KbDeviceResources,
         camera: &KbCamera,
         game_config: &KbConfig,
+        view_settings: &KbViewSettings,
     ) {
         let mut command_encoder =
             device_resources
@@ -358,10 +360,10 @@ impl KbSunbeamRenderGroup {
         });
 
         let proj_matrix = cgmath::perspective(
-            cgmath::Deg(game_config.fov),
+            cgmath::Deg(view_settings.fov),
             game_config.window_width as f32 / game_config.window_height as f32,
-            0.1,
-            10000.0,
+            view_settings.near_plane,
+            view_settings.far_plane,
         );
         let (view_matrix, view_dir, _) = camera.calculate_view_matrix();
         let sunbeam_uniform = KbSunbeamUniform {
@@ -398,6 +400,7 @@ impl KbSunbeamRenderGroup {
         device_resources: &mut KbDeviceResources,
         camera: &KbCamera,
         game_config: &KbConfig,
+        view_settings: &KbViewSettings,
     ) {
         let mut command_encoder =
             device_resources
@@ -433,16 +436,16 @@ impl KbSunbeamRenderGroup {
 
         let (view_matrix, view_dir, _) = camera.calculate_view_matrix();
         let view_proj = cgmath::perspective(
-            cgmath::Deg(game_config.fov),
+            cgmath::Deg(view_settings.fov),
             game_config.window_width as f32 / game_config.window_height as f32,
-            0.1,
-            10000.0,
+            view_settings.near_plane,
+            view_settings.far_plane,
         ) * view_matrix;
 
         let iteration_color = [
-            SUN_COLOR[0] * game_config.sun_color.x,
-            SUN_COLOR[1] * game_config.sun_color.y,
-            SUN_COLOR[2] * game_config.sun_color.z,
+            SUN_COLOR[0] * view_settings.sun_color.x,
+            SUN_COLOR[1] * view_settings.sun_color.y,
+            SUN_COLOR[2] * view_settings.sun_color.z,
             0.8,
         ];
 